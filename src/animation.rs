@@ -0,0 +1,351 @@
+use glam::{Mat4, Quat, Vec3};
+
+use crate::transform::Transform;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Interpolation {
+    Linear,
+    Step,
+    CubicSpline,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+    Weights, //morph target weights
+}
+
+//Keyframes of one property of one node.
+//Every value has `width` floats: 3 for translation/scale, 4 for rotations (xyzw)
+//and one per morph target for weights.
+//Cubic splines store in-tangent, value and out-tangent for every key
+pub struct Channel {
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub values: Vec<f32>,
+    pub width: usize,
+}
+
+impl Channel {
+    fn value(&self, key: usize) -> &[f32] {
+        let idx = match self.interpolation {
+            Interpolation::CubicSpline => key * 3 + 1,
+            _ => key,
+        };
+
+        &self.values[idx * self.width..(idx + 1) * self.width]
+    }
+
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        let last = self.times.len() - 1;
+
+        if time <= self.times[0] {
+            return self.value(0).to_vec();
+        }
+        if time >= self.times[last] {
+            return self.value(last).to_vec();
+        }
+
+        //Key before the time, the one after always exists because of the checks above
+        let key = self.times.partition_point(|t| *t <= time) - 1;
+        let dt = self.times[key + 1] - self.times[key];
+        let t = (time - self.times[key]) / dt;
+
+        match self.interpolation {
+            Interpolation::Step => self.value(key).to_vec(),
+            Interpolation::Linear => {
+                let (a, b) = (self.value(key), self.value(key + 1));
+
+                if self.property == Property::Rotation {
+                    let rot = Quat::from_slice(a).slerp(Quat::from_slice(b), t);
+                    rot.to_array().to_vec()
+                } else {
+                    a.iter().zip(b).map(|(a, b)| a + (b - a) * t).collect()
+                }
+            }
+            Interpolation::CubicSpline => {
+                //Hermite spline, tangents are scaled by the time between the keys
+                let t2 = t * t;
+                let t3 = t2 * t;
+
+                let value = |idx: usize| &self.values[idx * self.width..(idx + 1) * self.width];
+                let v0 = value(key * 3 + 1);
+                let out0 = value(key * 3 + 2);
+                let in1 = value((key + 1) * 3);
+                let v1 = value((key + 1) * 3 + 1);
+
+                let mut result: Vec<f32> = (0..self.width)
+                    .map(|i| {
+                        v0[i] * (2.0 * t3 - 3.0 * t2 + 1.0)
+                            + out0[i] * dt * (t3 - 2.0 * t2 + t)
+                            + v1[i] * (-2.0 * t3 + 3.0 * t2)
+                            + in1[i] * dt * (t3 - t2)
+                    })
+                    .collect();
+
+                if self.property == Property::Rotation {
+                    let rot = Quat::from_slice(&result).normalize();
+                    result = rot.to_array().to_vec();
+                }
+
+                result
+            }
+        }
+    }
+}
+
+pub struct AnimationClip {
+    pub name: String,
+    pub channels: Vec<Channel>,
+    pub duration: f32, //last keyframe of all channels
+}
+
+impl AnimationClip {
+    pub fn new(name: String, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+
+        Self {
+            name,
+            channels,
+            duration,
+        }
+    }
+
+    //Poses the nodes the clip animates, everything else keeps its transform
+    pub fn apply(&self, time: f32, nodes: &mut [Node]) {
+        for channel in &self.channels {
+            let value = channel.sample(time);
+            let node = &mut nodes[channel.node];
+
+            match channel.property {
+                Property::Translation => node.transform.translation = Vec3::from_slice(&value),
+                Property::Rotation => node.transform.rotation = Quat::from_slice(&value),
+                Property::Scale => node.transform.scale = Vec3::from_slice(&value),
+                Property::Weights => node.weights = value,
+            }
+        }
+    }
+}
+
+//A glTF node, the transform is relative to the parent and gets animated
+#[derive(Clone)]
+pub struct Node {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub transform: Transform,
+    pub weights: Vec<f32>, //morph target weights of the node's mesh
+}
+
+impl Node {
+    //Model space matrices, parents have to come before their children
+    pub fn world_matrices(nodes: &[Node], order: &[usize]) -> Vec<Mat4> {
+        let mut world = vec![Mat4::IDENTITY; nodes.len()];
+
+        for idx in order {
+            let node = &nodes[*idx];
+            world[*idx] = match node.parent {
+                Some(parent) => world[parent] * node.transform.local(),
+                None => node.transform.local(),
+            };
+        }
+
+        world
+    }
+}
+
+pub struct Skin {
+    pub joints: Vec<usize>, //node indices
+    pub inverse_bind: Vec<Mat4>,
+}
+
+impl Skin {
+    //Moves a vertex from bind pose to the joint's current pose in model space
+    pub fn joint_matrices(&self, world: &[Mat4]) -> Vec<Mat4> {
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind)
+            .map(|(joint, inverse_bind)| world[*joint] * *inverse_bind)
+            .collect()
+    }
+}
+
+pub struct Playback {
+    pub clip: usize,
+    pub time: f32, //seconds into the clip
+    pub speed: f32,
+    pub playing: bool,
+    pub looping: bool,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            clip: 0,
+            time: 0.0,
+            speed: 1.0,
+            playing: true,
+            looping: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn channel(
+        property: Property,
+        interpolation: Interpolation,
+        times: &[f32],
+        values: &[f32],
+    ) -> Channel {
+        let width = match property {
+            Property::Rotation => 4,
+            Property::Weights => 1,
+            _ => 3,
+        };
+        let stride = if interpolation == Interpolation::CubicSpline {
+            3
+        } else {
+            1
+        };
+        assert_eq!(values.len(), times.len() * width * stride);
+
+        Channel {
+            node: 0,
+            property,
+            interpolation,
+            times: times.to_vec(),
+            values: values.to_vec(),
+            width,
+        }
+    }
+
+    #[test]
+    fn step_holds_the_previous_key() {
+        let channel = channel(
+            Property::Translation,
+            Interpolation::Step,
+            &[0.0, 1.0],
+            &[0.0, 0.0, 0.0, 2.0, 4.0, 6.0],
+        );
+
+        assert_eq!(channel.sample(0.0), [0.0, 0.0, 0.0]);
+        assert_eq!(channel.sample(0.99), [0.0, 0.0, 0.0]);
+        assert_eq!(channel.sample(1.0), [2.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn linear_blends_and_clamps_outside_the_keys() {
+        let channel = channel(
+            Property::Translation,
+            Interpolation::Linear,
+            &[1.0, 2.0, 4.0],
+            &[0.0, 0.0, 0.0, 2.0, 4.0, 6.0, 4.0, 4.0, 4.0],
+        );
+
+        assert_eq!(channel.sample(1.25), [0.5, 1.0, 1.5]);
+        assert_eq!(channel.sample(3.0), [3.0, 4.0, 5.0]);
+
+        //Before the first key and after the last
+        assert_eq!(channel.sample(0.0), [0.0, 0.0, 0.0]);
+        assert_eq!(channel.sample(10.0), [4.0, 4.0, 4.0]);
+    }
+
+    #[test]
+    fn rotations_slerp_the_short_way() {
+        //The same rotation with the opposite sign, lerping the components would pass through zero
+        let end = -Quat::from_rotation_y(FRAC_PI_2);
+        let values: Vec<f32> = [Quat::IDENTITY, end]
+            .iter()
+            .flat_map(|q| q.to_array())
+            .collect();
+        let channel = channel(
+            Property::Rotation,
+            Interpolation::Linear,
+            &[0.0, 1.0],
+            &values,
+        );
+
+        let half = Quat::from_slice(&channel.sample(0.5));
+        assert!(half.is_normalized());
+        assert!(
+            half.angle_between(Quat::from_rotation_y(FRAC_PI_2 * 0.5)) < 1e-4,
+            "{half}"
+        );
+    }
+
+    #[test]
+    fn cubic_spline_reads_in_tangent_value_out_tangent() {
+        //The first in-tangent and last out-tangent never matter
+        let channel = channel(
+            Property::Weights,
+            Interpolation::CubicSpline,
+            &[0.0, 2.0],
+            &[100.0, 0.0, 2.0, 0.0, 1.0, 100.0],
+        );
+
+        //Keys land on the values, not the tangents, also when clamped
+        assert_eq!(channel.sample(-1.0), [0.0]);
+        assert_eq!(channel.sample(0.0), [0.0]);
+        assert_eq!(channel.sample(2.0), [1.0]);
+        assert_eq!(channel.sample(3.0), [1.0]);
+
+        //Halfway: (v0 + v1) / 2 + dt / 8 * (out0 - in1)
+        assert!((channel.sample(1.0)[0] - 1.0).abs() < 1e-6);
+        //A quarter: v1 * 5/32 + dt * (out0 * 9/64 - in1 * 3/64)
+        assert!((channel.sample(0.5)[0] - (5.0 / 32.0 + 2.0 * 2.0 * 9.0 / 64.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn clips_pose_only_their_nodes() {
+        let node = |name: &str, parent| Node {
+            name: name.to_string(),
+            parent,
+            transform: Transform::IDENTITY,
+            weights: Vec::new(),
+        };
+        let mut nodes = vec![node("Root", None), node("Child", Some(0))];
+        nodes[1].transform.translation = Vec3::X;
+
+        let mut translation = channel(
+            Property::Translation,
+            Interpolation::Linear,
+            &[0.0, 2.0],
+            &[0.0, 0.0, 0.0, 0.0, 4.0, 0.0],
+        );
+        translation.node = 0;
+        let mut weights = channel(
+            Property::Weights,
+            Interpolation::Linear,
+            &[0.0, 3.0],
+            &[0.0, 3.0],
+        );
+        weights.node = 1;
+
+        let clip = AnimationClip::new("Clip".to_string(), vec![translation, weights]);
+        assert_eq!(clip.duration, 3.0);
+
+        clip.apply(1.0, &mut nodes);
+        assert_eq!(nodes[0].transform.translation, Vec3::Y * 2.0);
+        assert_eq!(nodes[0].weights, Vec::<f32>::new());
+        assert_eq!(nodes[1].transform.translation, Vec3::X);
+        assert_eq!(nodes[1].weights, [1.0]);
+
+        //Children follow their parent
+        let world = Node::world_matrices(&nodes, &[0, 1]);
+        assert_eq!(world[0].transform_point3(Vec3::ZERO), Vec3::Y * 2.0);
+        assert_eq!(
+            world[1].transform_point3(Vec3::ZERO),
+            Vec3::new(1.0, 2.0, 0.0)
+        );
+    }
+}
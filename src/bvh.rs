@@ -0,0 +1,451 @@
+use glam::{Mat4, Vec3, Vec4};
+
+//EMPTY is inside out, so growing it by anything gives that thing's bounds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub const EMPTY: Self = Self {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points.into_iter().fold(Self::EMPTY, |mut aabb, point| {
+            aabb.grow(point);
+            aabb
+        })
+    }
+
+    pub fn grow(&mut self, point: Vec3) {
+        self.min = self.min.min(point);
+        self.max = self.max.max(point);
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.cmpgt(self.max).any()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn corners(&self) -> [Vec3; 8] {
+        std::array::from_fn(|i| {
+            Vec3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            )
+        })
+    }
+
+    //Bounds of the transformed box, a bit bigger than the box itself once rotated
+    pub fn transformed(&self, matrix: &Mat4) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        Aabb::from_points(self.corners().map(|corner| matrix.transform_point3(corner)))
+    }
+
+    //Conservative, a box is only culled when all its corners are outside the same clip
+    //plane. Works for reversed-Z and infinite projections since z stays within 0..w
+    pub fn in_frustum(&self, view_proj: &Mat4) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+
+        let clip = self
+            .corners()
+            .map(|corner| *view_proj * Vec4::from((corner, 1.0)));
+
+        let outside = |test: fn(&Vec4) -> bool| clip.iter().all(test);
+        !(outside(|c| c.x < -c.w)
+            || outside(|c| c.x > c.w)
+            || outside(|c| c.y < -c.w)
+            || outside(|c| c.y > c.w)
+            || outside(|c| c.z < 0.0)
+            || outside(|c| c.z > c.w))
+    }
+
+    //Distance along the ray to where it enters the box, 0 if it starts inside
+    pub fn ray_hit(&self, ray: &Ray, max_t: f32) -> Option<f32> {
+        let inv_dir = ray.dir.recip();
+        let t0 = (self.min - ray.origin) * inv_dir;
+        let t1 = (self.max - ray.origin) * inv_dir;
+
+        let near = t0.min(t1).max_element().max(0.0);
+        let far = t0.max(t1).min_element().min(max_t);
+        (near <= far).then_some(near)
+    }
+}
+
+//Distances along a ray are in multiples of dir, transforming the ray keeps them valid
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub dir: Vec3,
+}
+
+impl Ray {
+    pub fn new(origin: Vec3, dir: Vec3) -> Self {
+        Self {
+            origin,
+            dir: dir.normalize(),
+        }
+    }
+
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.dir * t
+    }
+
+    //dir isn't renormalized, so t means the same point before and after
+    pub fn transformed(&self, matrix: &Mat4) -> Ray {
+        Ray {
+            origin: matrix.transform_point3(self.origin),
+            dir: matrix.transform_vector3(self.dir),
+        }
+    }
+
+    //Möller-Trumbore, hits both faces
+    pub fn triangle_hit(&self, a: Vec3, b: Vec3, c: Vec3) -> Option<f32> {
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.dir.cross(edge2);
+        let det = edge1.dot(p);
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let inv_det = det.recip();
+        let s = self.origin - a;
+        let u = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge1);
+        let v = self.dir.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(q) * inv_det;
+        (t >= 0.0).then_some(t)
+    }
+}
+
+//Leaves have a count, inner nodes have their two children at first and first + 1
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    bounds: Aabb,
+    first: u32,
+    count: u32,
+}
+
+const MAX_LEAF_ITEMS: usize = 4;
+
+//Over the bounds of anything, items are indices into the slice it was built from
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    items: Vec<u32>,
+}
+
+impl Bvh {
+    //Splits at the median centroid along the longest axis, which keeps it balanced
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity((bounds.len() * 2).max(1)),
+            items: (0..bounds.len() as u32).collect(),
+        };
+
+        if !bounds.is_empty() {
+            bvh.nodes.push(Node {
+                bounds: Aabb::EMPTY,
+                first: 0,
+                count: bounds.len() as u32,
+            });
+            bvh.split(0, bounds);
+        }
+
+        bvh
+    }
+
+    fn split(&mut self, node_idx: usize, bounds: &[Aabb]) {
+        let Node { first, count, .. } = self.nodes[node_idx];
+        let range = first as usize..(first + count) as usize;
+
+        self.nodes[node_idx].bounds = self.items[range.clone()]
+            .iter()
+            .fold(Aabb::EMPTY, |aabb, item| {
+                aabb.union(&bounds[*item as usize])
+            });
+
+        if range.len() <= MAX_LEAF_ITEMS {
+            return;
+        }
+
+        let centers = Aabb::from_points(
+            self.items[range.clone()]
+                .iter()
+                .map(|item| bounds[*item as usize].center()),
+        );
+        let extent = centers.max - centers.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        let mid = range.len() / 2;
+        self.items[range.clone()].select_nth_unstable_by(mid, |a, b| {
+            let a = bounds[*a as usize].center()[axis];
+            let b = bounds[*b as usize].center()[axis];
+            a.total_cmp(&b)
+        });
+
+        let left = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: Aabb::EMPTY,
+            first,
+            count: mid as u32,
+        });
+        self.nodes.push(Node {
+            bounds: Aabb::EMPTY,
+            first: first + mid as u32,
+            count: count - mid as u32,
+        });
+        self.nodes[node_idx] = Node {
+            bounds: self.nodes[node_idx].bounds,
+            first: left as u32,
+            count: 0,
+        };
+
+        self.split(left, bounds);
+        self.split(left + 1, bounds);
+    }
+
+    //Same items with new bounds, keeps the tree. Gets slower to query the further things
+    //move from where they were built, rebuild then
+    pub fn refit(&mut self, bounds: &[Aabb]) {
+        debug_assert_eq!(bounds.len(), self.items.len());
+
+        //Children always come after their parent
+        for node_idx in (0..self.nodes.len()).rev() {
+            let Node { first, count, .. } = self.nodes[node_idx];
+
+            self.nodes[node_idx].bounds = if count > 0 {
+                self.items[first as usize..(first + count) as usize]
+                    .iter()
+                    .fold(Aabb::EMPTY, |aabb, item| {
+                        aabb.union(&bounds[*item as usize])
+                    })
+            } else {
+                let left = &self.nodes[first as usize].bounds;
+                left.union(&self.nodes[first as usize + 1].bounds)
+            };
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |root| root.bounds)
+    }
+
+    //Visits the items of every leaf reached, enter decides whether to go into a node
+    pub fn traverse(&self, mut enter: impl FnMut(&Aabb) -> bool, mut visit: impl FnMut(u32)) {
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(node_idx) = stack.pop() {
+            let node = &self.nodes[node_idx];
+            if !enter(&node.bounds) {
+                continue;
+            }
+
+            if node.count > 0 {
+                let items = &self.items[node.first as usize..(node.first + node.count) as usize];
+                items.iter().for_each(|item| visit(*item));
+            } else {
+                stack.push(node.first as usize + 1);
+                stack.push(node.first as usize);
+            }
+        }
+    }
+
+    //Closest item the ray hits within max_t. hit does the exact test for an item and gets
+    //the closest distance so far, nodes further than that are skipped
+    pub fn ray_cast(
+        &self,
+        ray: &Ray,
+        max_t: f32,
+        mut hit: impl FnMut(u32, f32) -> Option<f32>,
+    ) -> Option<(u32, f32)> {
+        let mut closest: Option<(u32, f32)> = None;
+        let mut stack: Vec<(usize, f32)> = Vec::new();
+
+        if let Some(t) = self
+            .nodes
+            .first()
+            .and_then(|root| root.bounds.ray_hit(ray, max_t))
+        {
+            stack.push((0, t));
+        }
+
+        while let Some((node_idx, entry)) = stack.pop() {
+            let best = closest.map_or(max_t, |(_, t)| t);
+            if entry > best {
+                continue;
+            }
+
+            let node = &self.nodes[node_idx];
+            if node.count > 0 {
+                for item in &self.items[node.first as usize..(node.first + node.count) as usize] {
+                    let best = closest.map_or(max_t, |(_, t)| t);
+                    if let Some(t) = hit(*item, best).filter(|t| *t <= best) {
+                        closest = Some((*item, t));
+                    }
+                }
+                continue;
+            }
+
+            //Nearer child last so it's popped first
+            let children = [node.first as usize, node.first as usize + 1];
+            let mut hits: Vec<(usize, f32)> = children
+                .iter()
+                .filter_map(|child| {
+                    let t = self.nodes[*child].bounds.ray_hit(ray, best)?;
+                    Some((*child, t))
+                })
+                .collect();
+            hits.sort_by(|a, b| b.1.total_cmp(&a.1));
+            stack.extend(hits);
+        }
+
+        closest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A row of unit boxes along x, box i spans i..i+1
+    fn row(count: usize) -> Vec<Aabb> {
+        (0..count)
+            .map(|i| {
+                Aabb::new(
+                    Vec3::new(i as f32, 0.0, 0.0),
+                    Vec3::new(i as f32 + 1.0, 1.0, 1.0),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn traverse_finds_overlapping_items() {
+        let bounds = row(100);
+        let bvh = Bvh::build(&bounds);
+        assert_eq!(bvh.len(), 100);
+        assert_eq!(
+            bvh.bounds(),
+            Aabb::new(Vec3::ZERO, Vec3::new(100.0, 1.0, 1.0))
+        );
+
+        let query = Aabb::new(Vec3::new(10.5, 0.0, 0.0), Vec3::new(12.5, 1.0, 1.0));
+        let overlaps =
+            |aabb: &Aabb| aabb.min.cmple(query.max).all() && aabb.max.cmpge(query.min).all();
+
+        let mut found = Vec::new();
+        let mut entered = 0;
+        bvh.traverse(
+            |aabb| {
+                entered += 1;
+                overlaps(aabb)
+            },
+            |item| {
+                if overlaps(&bounds[item as usize]) {
+                    found.push(item)
+                }
+            },
+        );
+        found.sort();
+        assert_eq!(found, [10, 11, 12]);
+        assert!(entered < 40, "visited {entered} nodes");
+    }
+
+    #[test]
+    fn ray_cast_returns_the_closest_hit() {
+        let bounds = row(50);
+        let bvh = Bvh::build(&bounds);
+
+        //From the far end going back, box 49 is the first one in the way
+        let ray = Ray::new(Vec3::new(100.0, 0.5, 0.5), Vec3::NEG_X);
+        let exact = |item: u32, _| bounds[item as usize].ray_hit(&ray, f32::INFINITY);
+        assert_eq!(bvh.ray_cast(&ray, f32::INFINITY, exact), Some((49, 50.0)));
+
+        let miss = Ray::new(Vec3::new(100.0, 5.0, 0.5), Vec3::NEG_X);
+        let exact = |item: u32, _| bounds[item as usize].ray_hit(&miss, f32::INFINITY);
+        assert_eq!(bvh.ray_cast(&miss, f32::INFINITY, exact), None);
+    }
+
+    #[test]
+    fn refit_follows_moved_items() {
+        let mut bounds = row(20);
+        let mut bvh = Bvh::build(&bounds);
+
+        for aabb in &mut bounds[..10] {
+            *aabb = Aabb::new(aabb.min + Vec3::Y * 10.0, aabb.max + Vec3::Y * 10.0);
+        }
+        bvh.refit(&bounds);
+        assert_eq!(bvh.bounds().max.y, 11.0);
+
+        let ray = Ray::new(Vec3::new(3.5, 20.0, 0.5), Vec3::NEG_Y);
+        let exact = |item: u32, _| bounds[item as usize].ray_hit(&ray, f32::INFINITY);
+        assert_eq!(bvh.ray_cast(&ray, f32::INFINITY, exact), Some((3, 9.0)));
+    }
+
+    #[test]
+    fn frustum_and_triangle_tests() {
+        let view_proj = Mat4::perspective_rh(1.0, 1.0, 0.1, 100.0)
+            * Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+
+        let ahead = Aabb::new(Vec3::new(-1.0, -1.0, -6.0), Vec3::new(1.0, 1.0, -4.0));
+        let behind = Aabb::new(Vec3::new(-1.0, -1.0, 4.0), Vec3::new(1.0, 1.0, 6.0));
+        let beyond_far = Aabb::new(Vec3::new(-1.0, -1.0, -300.0), Vec3::new(1.0, 1.0, -200.0));
+        assert!(ahead.in_frustum(&view_proj));
+        assert!(!behind.in_frustum(&view_proj));
+        assert!(!beyond_far.in_frustum(&view_proj));
+
+        let ray = Ray::new(Vec3::new(0.25, 0.25, 5.0), Vec3::NEG_Z);
+        assert_eq!(ray.triangle_hit(Vec3::ZERO, Vec3::X, Vec3::Y), Some(5.0));
+        assert_eq!(ray.triangle_hit(Vec3::X, Vec3::ZERO, Vec3::Y), Some(5.0));
+        assert_eq!(
+            ray.triangle_hit(Vec3::ONE, Vec3::X * 2.0, Vec3::Y * 2.0),
+            None
+        );
+    }
+}
@@ -10,9 +10,11 @@
 // func projection
 // func view (look_at_rh)
 
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec2, Vec3};
 use glam::{Quat, Vec4};
 
+use crate::bvh::Ray;
+use crate::sliced_buffer::DepthCompare;
 use crate::transform::Transform;
 use crate::WIN_HEIGHT;
 use crate::WIN_WIDTH;
@@ -28,6 +30,9 @@ pub struct Camera {
     pub transform: Transform,
     pub yaw: f32,
     pub pitch: f32,
+    pub reversed_z: bool, //near maps to 1 and far to 0 for better precision in the distance
+    pub infinite_far: bool, //ignores far_plane
+    pub parent: Mat4,     //world matrix of the scene node the camera rides on
 }
 
 impl Default for Camera {
@@ -43,6 +48,9 @@ impl Default for Camera {
             transform: Transform::IDENTITY,
             yaw: 0.0,
             pitch: 0.0,
+            reversed_z: false,
+            infinite_far: false,
+            parent: Mat4::IDENTITY,
         }
     }
 }
@@ -74,7 +82,37 @@ impl Camera {
     }
 
     pub fn perspective(&self) -> Mat4 {
-        Mat4::perspective_rh(self.fov, self.aspect_ratio, self.near_plane, self.far_plane)
+        match (self.reversed_z, self.infinite_far) {
+            (false, false) => {
+                Mat4::perspective_rh(self.fov, self.aspect_ratio, self.near_plane, self.far_plane)
+            }
+            (false, true) => {
+                Mat4::perspective_infinite_rh(self.fov, self.aspect_ratio, self.near_plane)
+            }
+            (true, false) => {
+                Mat4::perspective_rh(self.fov, self.aspect_ratio, self.far_plane, self.near_plane)
+            }
+            (true, true) => {
+                Mat4::perspective_infinite_reverse_rh(self.fov, self.aspect_ratio, self.near_plane)
+            }
+        }
+    }
+
+    //Compare function and clear value the depth buffer needs for this projection
+    pub fn depth_compare(&self) -> DepthCompare {
+        if self.reversed_z {
+            DepthCompare::Greater
+        } else {
+            DepthCompare::Less
+        }
+    }
+
+    pub fn depth_clear_value(&self) -> f32 {
+        if self.reversed_z {
+            0.0
+        } else {
+            f32::INFINITY
+        }
     }
 
     pub fn mouse_rotation(&mut self, pitch: f32, yaw: f32) {
@@ -94,6 +132,22 @@ impl Camera {
         self.transform.rotation = orientation;
     }
 
+    //Ray through a point on screen, ndc goes from -1 to 1 with y up
+    pub fn ray_from_screen(&self, ndc: Vec2) -> Ray {
+        let half_height = (self.fov * 0.5).tan();
+        let dir = Vec3::new(
+            ndc.x * half_height * self.aspect_ratio,
+            ndc.y * half_height,
+            -1.0,
+        );
+
+        let inv_view = self.view().inverse();
+        Ray::new(
+            inv_view.transform_point3(Vec3::ZERO),
+            inv_view.transform_vector3(dir),
+        )
+    }
+
     pub fn view(&self) -> Mat4 {
         let eye = self.transform.translation.extend(1.0);
         let orientation = Mat4::from_quat(self.transform.rotation);
@@ -108,6 +162,34 @@ impl Camera {
 
         let orientation = Quat::from_mat4(&view);
 
-        view
+        view * self.parent_frame().inverse()
+    }
+
+    //The parent without its scale, scaling the view would skew depth and the frustum
+    pub fn parent_frame(&self) -> Mat4 {
+        let (_, rotation, translation) = self.parent.to_scale_rotation_translation();
+        Mat4::from_rotation_translation(rotation, translation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled_parents_only_move_and_turn_the_view() {
+        let mut camera = Camera::default();
+        camera.set_position(Vec3::new(0.0, 1.0, 0.0));
+        camera.parent = Mat4::from_scale_rotation_translation(
+            Vec3::splat(3.0),
+            Quat::from_rotation_y(std::f32::consts::FRAC_PI_2),
+            Vec3::new(10.0, 0.0, 0.0),
+        );
+
+        let inv_view = camera.view().inverse();
+        assert!((inv_view.determinant() - 1.0).abs() < 1e-5);
+        assert!(inv_view
+            .transform_point3(Vec3::ZERO)
+            .abs_diff_eq(Vec3::new(10.0, 1.0, 0.0), 1e-5));
     }
 }
@@ -6,34 +6,39 @@ use glam::{Vec2, Vec3, Vec4};
 pub struct Vertex {
     pub position: Vec4,
     pub normal: Vec3,
-    pub color: Vec3,
+    pub color: Vec4, //RGBA
     pub uv: Vec2,
+    pub uv1: Vec2, //second UV set, lightmaps and AO usually
 }
 
 pub static PLANE_DATA: [Vertex; 4] = [
     Vertex {
         position: glam::vec4(-1.0, -1.0, 1.0, 1.0),
         normal: glam::vec3(-1.0, -1.0, 1.0),
-        color: glam::vec3(-1.0, -1.0, 1.0),
+        color: glam::vec4(-1.0, -1.0, 1.0, 1.0),
         uv: glam::vec2(0.0, 1.0),
+        uv1: glam::vec2(0.0, 1.0),
     },
     Vertex {
         position: glam::vec4(-1.0, 1.0, 1.0, 1.0),
         normal: glam::vec3(-1.0, -1.0, 1.0),
-        color: glam::vec3(-1.0, -1.0, 1.0),
+        color: glam::vec4(-1.0, -1.0, 1.0, 1.0),
         uv: glam::vec2(0.0, 0.0),
+        uv1: glam::vec2(0.0, 0.0),
     },
     Vertex {
         position: glam::vec4(1.0, 1.0, 1.0, 1.0),
         normal: glam::vec3(-1.0, -1.0, 1.0),
-        color: glam::vec3(-1.0, -1.0, 1.0),
+        color: glam::vec4(-1.0, -1.0, 1.0, 1.0),
         uv: glam::vec2(1.0, 0.0),
+        uv1: glam::vec2(1.0, 0.0),
     },
     Vertex {
         position: glam::vec4(1.0, -1.0, 1.0, 1.0),
         normal: glam::vec3(-1.0, -1.0, 1.0),
-        color: glam::vec3(-1.0, -1.0, 1.0),
+        color: glam::vec4(-1.0, -1.0, 1.0, 1.0),
         uv: glam::vec2(1.0, 1.0),
+        uv1: glam::vec2(1.0, 1.0),
     },
 ];
 
@@ -41,50 +46,58 @@ pub static CUBE_VERTICES: [Vertex; 8] = [
     Vertex {
         position: Vec4::new(-1.0, -1.0, 1.0, 1.0),
         normal: Vec3::new(0.0, 0.0, 1.0),
-        color: Vec3::new(1.0, 0.0, 0.0),
+        color: Vec4::new(1.0, 0.0, 0.0, 1.0),
         uv: Vec2::new(0.0, 0.0),
+        uv1: Vec2::new(0.0, 0.0),
     },
     Vertex {
         position: Vec4::new(1.0, -1.0, 1.0, 1.0),
         normal: Vec3::new(0.0, 0.0, 1.0),
-        color: Vec3::new(0.0, 1.0, 0.0),
+        color: Vec4::new(0.0, 1.0, 0.0, 1.0),
         uv: Vec2::new(1.0, 0.0),
+        uv1: Vec2::new(1.0, 0.0),
     },
     Vertex {
         position: Vec4::new(-1.0, 1.0, 1.0, 1.0),
         normal: Vec3::new(0.0, 0.0, 1.0),
-        color: Vec3::new(0.0, 0.0, 1.0),
+        color: Vec4::new(0.0, 0.0, 1.0, 1.0),
         uv: Vec2::new(0.0, 1.0),
+        uv1: Vec2::new(0.0, 1.0),
     },
     Vertex {
         position: Vec4::new(1.0, 1.0, 1.0, 1.0),
         normal: Vec3::new(0.0, 0.0, 1.0),
-        color: Vec3::new(1.0, 1.0, 0.0),
+        color: Vec4::new(1.0, 1.0, 0.0, 1.0),
         uv: Vec2::new(1.0, 1.0),
+        uv1: Vec2::new(1.0, 1.0),
     },
     Vertex {
         position: Vec4::new(-1.0, -1.0, -1.0, 1.0),
         normal: Vec3::new(0.0, 0.0, -1.0),
-        color: Vec3::new(0.0, 1.0, 1.0),
+        color: Vec4::new(0.0, 1.0, 1.0, 1.0),
         uv: Vec2::new(1.0, 0.0),
+        uv1: Vec2::new(1.0, 0.0),
     },
     Vertex {
         position: Vec4::new(1.0, -1.0, -1.0, 1.0),
         normal: Vec3::new(0.0, 0.0, -1.0),
-        color: Vec3::new(1.0, 0.0, 1.0),
+        color: Vec4::new(1.0, 0.0, 1.0, 1.0),
         uv: Vec2::new(0.0, 0.0),
+        uv1: Vec2::new(0.0, 0.0),
     },
     Vertex {
         position: Vec4::new(-1.0, 1.0, -1.0, 1.0),
         normal: Vec3::new(0.0, 0.0, -1.0),
-        color: Vec3::new(1.0, 0.5, 0.0),
+        color: Vec4::new(1.0, 0.5, 0.0, 1.0),
         uv: Vec2::new(1.0, 1.0),
+        uv1: Vec2::new(1.0, 1.0),
     },
     Vertex {
         position: Vec4::new(1.0, 1.0, -1.0, 1.0),
         normal: Vec3::new(0.0, 0.0, -1.0),
-        color: Vec3::new(0.5, 0.5, 0.5),
+        color: Vec4::new(0.5, 0.5, 0.5, 1.0),
         uv: Vec2::new(0.0, 1.0),
+        uv1: Vec2::new(0.0, 1.0),
     },
 ];
 
@@ -102,38 +115,44 @@ pub static RHOMBUS_VERTICES: [Vertex; 6] = [
     Vertex {
         position: Vec4::new(0.0, 0.0, 1.0, 1.0),
         normal: Vec3::new(0.0, 0.0, 1.0),
-        color: Vec3::new(0.5, 0.7, 0.2),
+        color: Vec4::new(0.5, 0.7, 0.2, 1.0),
         uv: Vec2::new(0.5, 0.5),
+        uv1: Vec2::new(0.5, 0.5),
     },
     Vertex {
         position: Vec4::new(1.0, 0.0, 0.0, 1.0),
         normal: Vec3::new(1.0, 0.0, 0.0),
-        color: Vec3::new(0.2, 0.3, 0.7),
+        color: Vec4::new(0.2, 0.3, 0.7, 1.0),
         uv: Vec2::new(1.0, 0.0),
+        uv1: Vec2::new(1.0, 0.0),
     },
     Vertex {
         position: Vec4::new(0.0, 1.0, 0.0, 1.0),
         normal: Vec3::new(0.0, 1.0, 0.0),
-        color: Vec3::new(0.3, 0.1, 0.8),
+        color: Vec4::new(0.3, 0.1, 0.8, 1.0),
         uv: Vec2::new(0.5, 1.0),
+        uv1: Vec2::new(0.5, 1.0),
     },
     Vertex {
         position: Vec4::new(-1.0, 0.0, 0.0, 1.0),
         normal: Vec3::new(-1.0, 0.0, 0.0),
-        color: Vec3::new(0.1, 0.9, 0.3),
+        color: Vec4::new(0.1, 0.9, 0.3, 1.0),
         uv: Vec2::new(0.0, 0.0),
+        uv1: Vec2::new(0.0, 0.0),
     },
     Vertex {
         position: Vec4::new(0.0, -1.0, 0.0, 1.0),
         normal: Vec3::new(0.0, -1.0, 0.0),
-        color: Vec3::new(0.9, 0.2, 0.4),
+        color: Vec4::new(0.9, 0.2, 0.4, 1.0),
         uv: Vec2::new(0.5, 0.0),
+        uv1: Vec2::new(0.5, 0.0),
     },
     Vertex {
         position: Vec4::new(0.0, 0.0, -1.0, 1.0),
         normal: Vec3::new(0.0, 0.0, -1.0),
-        color: Vec3::new(0.6, 0.5, 0.1),
+        color: Vec4::new(0.6, 0.5, 0.1, 1.0),
         uv: Vec2::new(1.0, 0.5),
+        uv1: Vec2::new(1.0, 0.5),
     },
 ];
 
@@ -145,42 +164,48 @@ pub static PYRAMID_VERTEX: [Vertex; 5] = [
     Vertex {
         position: Vec4::new(0.0, 1.0, 0.0, 1.0),
         normal: Vec3::new(0.0, 1.0, 0.0),
-        color: Vec3::new(1.0, 0.5, 0.0),
+        color: Vec4::new(1.0, 0.5, 0.0, 1.0),
         uv: Vec2::new(0.5, 0.5),
+        uv1: Vec2::new(0.5, 0.5),
     },
     Vertex {
         position: Vec4::new(-0.5, 0.0, -0.5, 1.0),
         normal: Vec3::new(-1.0, 0.0, -1.0),
-        color: Vec3::new(0.0, 0.5, 1.0),
+        color: Vec4::new(0.0, 0.5, 1.0, 1.0),
         uv: Vec2::new(0.2, 0.8),
+        uv1: Vec2::new(0.2, 0.8),
     },
     Vertex {
         position: Vec4::new(0.5, 0.0, -0.5, 1.0),
         normal: Vec3::new(1.0, 0.0, -1.0),
-        color: Vec3::new(0.0, 1.0, 0.5),
+        color: Vec4::new(0.0, 1.0, 0.5, 1.0),
         uv: Vec2::new(0.8, 0.8),
+        uv1: Vec2::new(0.8, 0.8),
     },
     Vertex {
         position: Vec4::new(0.5, 0.0, 0.5, 1.0),
         normal: Vec3::new(1.0, 0.0, 1.0),
-        color: Vec3::new(0.5, 0.0, 0.5),
+        color: Vec4::new(0.5, 0.0, 0.5, 1.0),
         uv: Vec2::new(0.8, 0.2),
+        uv1: Vec2::new(0.8, 0.2),
     },
     Vertex {
         position: Vec4::new(-0.5, 0.0, 0.5, 1.0),
         normal: Vec3::new(-1.0, 0.0, 1.0),
-        color: Vec3::new(1.0, 0.5, 0.5),
+        color: Vec4::new(1.0, 0.5, 0.5, 1.0),
         uv: Vec2::new(0.2, 0.2),
+        uv1: Vec2::new(0.2, 0.2),
     },
 ];
 
 pub static PYRAMID_INDEX: [u32; 18] = [0, 1, 2, 0, 2, 3, 0, 3, 4, 0, 4, 1, 1, 4, 3, 1, 3, 2];
 
 impl Vertex {
-    pub fn new(position: Vec4, normal: Vec3, color: Vec3, uv: Vec2) -> Self {
+    pub fn new(position: Vec4, normal: Vec3, color: Vec4, uv: Vec2) -> Self {
         Self {
             position,
             uv,
+            uv1: uv,
             normal,
             color,
         }
@@ -195,9 +220,11 @@ impl Add for Vertex {
         let normal = self.normal + rhs.normal;
         let color = self.color + rhs.color;
         let uv = self.uv + rhs.uv;
+        let uv1 = self.uv1 + rhs.uv1;
         Self {
             position,
             uv,
+            uv1,
             normal,
             color,
         }
@@ -212,9 +239,11 @@ impl Sub for Vertex {
         let normal = self.normal - rhs.normal;
         let color = self.color - rhs.color;
         let uv = self.uv - rhs.uv;
+        let uv1 = self.uv1 - rhs.uv1;
         Self {
             position,
             uv,
+            uv1,
             normal,
             color,
         }
@@ -229,9 +258,11 @@ impl Mul<f32> for Vertex {
         let normal = self.normal * rhs;
         let color = self.color * rhs;
         let uv = self.uv * rhs;
+        let uv1 = self.uv1 * rhs;
         Self {
             position,
             uv,
+            uv1,
             normal,
             color,
         }
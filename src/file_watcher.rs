@@ -0,0 +1,125 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant, SystemTime},
+};
+
+//Polls modification times, no OS notifications or extra threads needed
+pub struct FileWatcher {
+    files: HashMap<String, Option<SystemTime>>, //None while the file doesn't exist
+    interval: Duration,
+    last_poll: Instant,
+}
+
+impl Default for FileWatcher {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(500))
+    }
+}
+
+impl FileWatcher {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            files: HashMap::new(),
+            interval,
+            last_poll: Instant::now(),
+        }
+    }
+
+    fn modified(path: &str) -> Option<SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    //Starts from the current state, so only later changes get reported
+    pub fn watch(&mut self, path: &str) {
+        self.files.insert(path.to_string(), Self::modified(path));
+    }
+
+    pub fn unwatch(&mut self, path: &str) {
+        self.files.remove(path);
+    }
+
+    pub fn is_watched(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+
+    //Watches exactly these paths, the ones that were already watched keep their state
+    pub fn set_paths<'a>(&mut self, paths: impl IntoIterator<Item = &'a str>) {
+        let mut files = HashMap::new();
+        for path in paths {
+            let modified = match self.files.get(path) {
+                Some(modified) => *modified,
+                None => Self::modified(path),
+            };
+            files.insert(path.to_string(), modified);
+        }
+        self.files = files;
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    //Changed paths, at most once per interval
+    pub fn poll(&mut self) -> Vec<String> {
+        if self.last_poll.elapsed() < self.interval {
+            return Vec::new();
+        }
+        self.last_poll = Instant::now();
+        self.changed()
+    }
+
+    //Paths that got written or created since the last check. Deleting a file isn't a
+    //change, editors that save by replacing the file show up once the new one is there
+    pub fn changed(&mut self) -> Vec<String> {
+        let mut changed = Vec::new();
+
+        for (path, modified) in &mut self.files {
+            let current = Self::modified(path);
+            if current.is_some() && current != *modified {
+                changed.push(path.clone());
+            }
+            *modified = current;
+        }
+
+        changed.sort();
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{temp_dir, touch};
+
+    #[test]
+    fn reports_writes_and_creation() {
+        let dir = temp_dir("file_watcher");
+        let existing = dir.join("existing.txt");
+        let created = dir.join("created.txt");
+        let _ = std::fs::remove_file(&created);
+        touch(&existing, "a", 1000);
+
+        let mut watcher = FileWatcher::new(Duration::ZERO);
+        watcher.watch(existing.to_str().unwrap());
+        watcher.watch(created.to_str().unwrap());
+        assert!(watcher.changed().is_empty());
+
+        touch(&existing, "b", 2000);
+        touch(&created, "c", 2000);
+        let changed = watcher.changed();
+        assert_eq!(changed.len(), 2);
+        assert!(watcher.changed().is_empty());
+
+        std::fs::remove_file(&created).unwrap();
+        assert!(watcher.changed().is_empty());
+
+        //Dropped paths aren't polled anymore, kept ones keep their state
+        watcher.set_paths([existing.to_str().unwrap()]);
+        assert!(!watcher.is_watched(created.to_str().unwrap()));
+        assert!(watcher.changed().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,625 @@
+use std::{borrow::Cow, collections::HashMap, io::Cursor, path::Path};
+
+use glam::{Mat4, Vec3, Vec4};
+use gltf::json::{
+    self,
+    accessor::{ComponentType, GenericComponentType, Type},
+    buffer::Target,
+    extensions,
+    material::{AlphaMode, PbrBaseColorFactor, PbrMetallicRoughness, StrengthFactor},
+    mesh::{Mode, Semantic},
+    texture::WrappingMode,
+    validation::Checked::Valid,
+    Index,
+};
+
+use crate::{
+    mesh::{Topology, VertexMesh},
+    model::Model,
+    sampler::Wrap,
+    tex_manager::TEXTURE_MANAGER,
+    texture::Texture,
+    transform::Transform,
+};
+
+#[derive(Debug)]
+pub enum ExportError {
+    Io(std::io::Error),
+    Json(json::Error),
+    Gltf(gltf::Error),
+    Image(image::ImageError),
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::Io(err) => write!(f, "IO error: {err}"),
+            ExportError::Json(err) => write!(f, "JSON error: {err}"),
+            ExportError::Gltf(err) => write!(f, "glTF error: {err}"),
+            ExportError::Image(err) => write!(f, "Image error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<std::io::Error> for ExportError {
+    fn from(err: std::io::Error) -> Self {
+        ExportError::Io(err)
+    }
+}
+
+impl From<json::Error> for ExportError {
+    fn from(err: json::Error) -> Self {
+        ExportError::Json(err)
+    }
+}
+
+impl From<gltf::Error> for ExportError {
+    fn from(err: gltf::Error) -> Self {
+        ExportError::Gltf(err)
+    }
+}
+
+impl From<image::ImageError> for ExportError {
+    fn from(err: image::ImageError) -> Self {
+        ExportError::Image(err)
+    }
+}
+
+//Collects the json and one binary buffer with every accessor and image in it
+struct Builder {
+    root: json::Root,
+    bin: Vec<u8>,
+    textures: HashMap<i32, Index<json::Texture>>, //TEXTURE_MANAGER idx to glTF texture
+}
+
+impl Builder {
+    fn push_view(&mut self, bytes: &[u8], target: Option<Target>) -> Index<json::buffer::View> {
+        //Accessors need their data aligned to the component size
+        self.bin.resize(self.bin.len().next_multiple_of(4), 0);
+
+        self.root.buffer_views.push(json::buffer::View {
+            buffer: Index::new(0),
+            byte_length: bytes.len() as u32,
+            byte_offset: Some(self.bin.len() as u32),
+            byte_stride: None,
+            name: None,
+            target: target.map(Valid),
+            extensions: None,
+            extras: Default::default(),
+        });
+        self.bin.extend_from_slice(bytes);
+
+        Index::new(self.root.buffer_views.len() as u32 - 1)
+    }
+
+    fn push_accessor(
+        &mut self,
+        bytes: &[u8],
+        count: usize,
+        component_type: ComponentType,
+        type_: Type,
+        min_max: Option<(Vec<f32>, Vec<f32>)>,
+    ) -> Index<json::Accessor> {
+        let target = match type_ {
+            Type::Scalar => Target::ElementArrayBuffer,
+            _ => Target::ArrayBuffer,
+        };
+        let view = self.push_view(bytes, Some(target));
+        let (min, max) = match min_max {
+            Some((min, max)) => (Some(min.into()), Some(max.into())),
+            None => (None, None),
+        };
+
+        self.root.accessors.push(json::Accessor {
+            buffer_view: Some(view),
+            byte_offset: 0,
+            count: count as u32,
+            component_type: Valid(GenericComponentType(component_type)),
+            extensions: None,
+            extras: Default::default(),
+            type_: Valid(type_),
+            min,
+            max,
+            name: None,
+            normalized: false,
+            sparse: None,
+        });
+
+        Index::new(self.root.accessors.len() as u32 - 1)
+    }
+
+    fn push_floats(&mut self, values: &[f32], count: usize, type_: Type) -> Index<json::Accessor> {
+        let bytes: Vec<u8> = values.iter().flat_map(|f| f.to_le_bytes()).collect();
+        self.push_accessor(&bytes, count, ComponentType::F32, type_, None)
+    }
+
+    //One glTF texture per TEXTURE_MANAGER texture, however many meshes use it
+    fn push_texture(&mut self, tex_idx: i32) -> Result<Option<Index<json::Texture>>, ExportError> {
+        if let Some(texture) = self.textures.get(&tex_idx) {
+            return Ok(Some(*texture));
+        }
+
+        let texture = match TEXTURE_MANAGER.read().unwrap().get_texture(&tex_idx) {
+            Some(texture) => texture.clone(),
+            None => return Ok(None), //got destroyed, the mesh just loses it
+        };
+
+        let image = self.push_view(&encode_png(&texture)?, None);
+        self.root.images.push(json::Image {
+            buffer_view: Some(image),
+            mime_type: Some(json::image::MimeType("image/png".to_string())),
+            name: None,
+            uri: None,
+            extensions: None,
+            extras: Default::default(),
+        });
+
+        let wrap = |wrap: &Wrap| {
+            Valid(match wrap {
+                Wrap::ClampToEdge => WrappingMode::ClampToEdge,
+                Wrap::Repeat => WrappingMode::Repeat,
+                Wrap::Mirror => WrappingMode::MirroredRepeat,
+            })
+        };
+        self.root.samplers.push(json::texture::Sampler {
+            wrap_s: wrap(&texture.sampler.wrap_s),
+            wrap_t: wrap(&texture.sampler.wrap_t),
+            ..Default::default()
+        });
+
+        self.root.textures.push(json::Texture {
+            name: None,
+            sampler: Some(Index::new(self.root.samplers.len() as u32 - 1)),
+            source: Index::new(self.root.images.len() as u32 - 1),
+            extensions: None,
+            extras: Default::default(),
+        });
+
+        let index = Index::new(self.root.textures.len() as u32 - 1);
+        self.textures.insert(tex_idx, index);
+        Ok(Some(index))
+    }
+
+    fn push_material(&mut self, mesh: &VertexMesh) -> Result<Index<json::Material>, ExportError> {
+        let material = &mesh.material;
+
+        let base_color_texture = match mesh.texture {
+            Some(tex_idx) => self.push_texture(tex_idx)?.map(|index| {
+                let transform = material.base_tex.transform.map(|transform| {
+                    extensions::texture::TextureTransform {
+                        offset: extensions::texture::TextureTransformOffset(
+                            transform.offset.to_array(),
+                        ),
+                        rotation: extensions::texture::TextureTransformRotation(transform.rotation),
+                        scale: extensions::texture::TextureTransformScale(
+                            transform.scale.to_array(),
+                        ),
+                        tex_coord: None,
+                        extras: Default::default(),
+                    }
+                });
+
+                json::texture::Info {
+                    index,
+                    tex_coord: material.base_tex.tex_coord,
+                    extensions: transform.map(|transform| extensions::texture::Info {
+                        texture_transform: Some(transform),
+                    }),
+                    extras: Default::default(),
+                }
+            }),
+            None => None,
+        };
+
+        if base_color_texture
+            .as_ref()
+            .is_some_and(|info| info.extensions.is_some())
+        {
+            let name = "KHR_texture_transform".to_string();
+            if !self.root.extensions_used.contains(&name) {
+                self.root.extensions_used.push(name);
+            }
+        }
+
+        //Nothing here is metallic, the default of 1 would look black in other viewers
+        self.root.materials.push(json::Material {
+            alpha_mode: Valid(if material.base_color.w < 1.0 {
+                AlphaMode::Blend
+            } else {
+                AlphaMode::Opaque
+            }),
+            pbr_metallic_roughness: PbrMetallicRoughness {
+                base_color_factor: PbrBaseColorFactor(material.base_color.to_array()),
+                base_color_texture,
+                metallic_factor: StrengthFactor(0.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        Ok(Index::new(self.root.materials.len() as u32 - 1))
+    }
+
+    //Skinned and morphed meshes go out in their bind pose
+    fn push_mesh(
+        &mut self,
+        mesh: &VertexMesh,
+        name: String,
+    ) -> Result<Index<json::Mesh>, ExportError> {
+        let vertices = &mesh.vertices;
+        let count = vertices.len();
+
+        let positions: Vec<Vec3> = vertices.iter().map(|v| v.position.truncate()).collect();
+        let (min, max) = positions.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), p| (min.min(*p), max.max(*p)),
+        );
+        let bytes: Vec<u8> = positions
+            .iter()
+            .flat_map(|p| p.to_array())
+            .flat_map(|f| f.to_le_bytes())
+            .collect();
+        let position = self.push_accessor(
+            &bytes,
+            count,
+            ComponentType::F32,
+            Type::Vec3,
+            Some((min.to_array().to_vec(), max.to_array().to_vec())),
+        );
+
+        let normals: Vec<f32> = vertices.iter().flat_map(|v| v.normal.to_array()).collect();
+        let uvs: Vec<f32> = vertices.iter().flat_map(|v| v.uv.to_array()).collect();
+
+        let mut attributes = HashMap::new();
+        attributes.insert(Valid(Semantic::Positions), position);
+        attributes.insert(
+            Valid(Semantic::Normals),
+            self.push_floats(&normals, count, Type::Vec3),
+        );
+        attributes.insert(
+            Valid(Semantic::TexCoords(0)),
+            self.push_floats(&uvs, count, Type::Vec2),
+        );
+
+        //Only write what the loaders wouldn't fill in by themselves
+        if vertices.iter().any(|v| v.uv1 != v.uv) {
+            let uvs1: Vec<f32> = vertices.iter().flat_map(|v| v.uv1.to_array()).collect();
+            attributes.insert(
+                Valid(Semantic::TexCoords(1)),
+                self.push_floats(&uvs1, count, Type::Vec2),
+            );
+        }
+        if vertices.iter().any(|v| v.color != Vec4::ONE) {
+            let colors: Vec<f32> = vertices.iter().flat_map(|v| v.color.to_array()).collect();
+            attributes.insert(
+                Valid(Semantic::Colors(0)),
+                self.push_floats(&colors, count, Type::Vec4),
+            );
+        }
+
+        let bytes: Vec<u8> = mesh.indices.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let indices = self.push_accessor(
+            &bytes,
+            mesh.indices.len(),
+            ComponentType::U32,
+            Type::Scalar,
+            None,
+        );
+
+        let mode = match mesh.topology {
+            Topology::TriangleList => Mode::Triangles,
+            Topology::LineList => Mode::Lines,
+            Topology::LineStrip => Mode::LineStrip,
+            Topology::PointList => Mode::Points,
+        };
+
+        let material = self.push_material(mesh)?;
+
+        self.root.meshes.push(json::Mesh {
+            extensions: None,
+            extras: Default::default(),
+            name: Some(name),
+            primitives: vec![json::mesh::Primitive {
+                attributes,
+                extensions: None,
+                extras: Default::default(),
+                indices: Some(indices),
+                material: Some(material),
+                mode: Valid(mode),
+                targets: None,
+            }],
+            weights: None,
+        });
+
+        Ok(Index::new(self.root.meshes.len() as u32 - 1))
+    }
+
+    fn push_node(&mut self, node: json::Node) -> Index<json::Node> {
+        self.root.nodes.push(node);
+        Index::new(self.root.nodes.len() as u32 - 1)
+    }
+
+    //A node per model placed by its world matrix, the meshes are children placed by
+    //their own transform and whatever glTF node they came from
+    fn push_model(
+        &mut self,
+        name: &str,
+        model: &Model,
+        world: &Mat4,
+    ) -> Result<Index<json::Node>, ExportError> {
+        let mut children = Vec::new();
+
+        for (idx, mesh) in model.meshes.iter().enumerate() {
+            let mesh_name = format!("{name} {idx}");
+            let json_mesh = self.push_mesh(mesh, mesh_name.clone())?;
+            let local = model.mesh_parent(mesh) * mesh.transform.local();
+
+            let mut node = trs_node(&decompose(local));
+            node.name = Some(mesh_name);
+            node.mesh = Some(json_mesh);
+            children.push(self.push_node(node));
+        }
+
+        let mut node = trs_node(&decompose(*world));
+        node.name = Some(name.to_string());
+        node.children = (!children.is_empty()).then_some(children);
+        Ok(self.push_node(node))
+    }
+}
+
+fn decompose(matrix: Mat4) -> Transform {
+    let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+    Transform {
+        translation,
+        rotation,
+        scale,
+    }
+}
+
+fn trs_node(transform: &Transform) -> json::Node {
+    json::Node {
+        camera: None,
+        children: None,
+        extensions: None,
+        extras: Default::default(),
+        matrix: None,
+        mesh: None,
+        name: None,
+        rotation: Some(json::scene::UnitQuaternion(transform.rotation.to_array())),
+        scale: Some(transform.scale.to_array()),
+        translation: Some(transform.translation.to_array()),
+        skin: None,
+        weights: None,
+    }
+}
+
+fn encode_png(texture: &Texture) -> Result<Vec<u8>, ExportError> {
+    let pixels: Vec<u8> = texture
+        .data
+        .iter()
+        .flat_map(|pixel| crate::render_utils::u32_to_argb8(*pixel))
+        .collect();
+
+    let mut png = Vec::new();
+    image::RgbaImage::from_raw(texture.width, texture.height, pixels)
+        .expect("texture data doesn't match its size")
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+//The json with a single buffer that has no uri yet, and that buffer's bytes
+fn build(
+    scene_name: &str,
+    models: &[(&str, &Model, Mat4)],
+) -> Result<(json::Root, Vec<u8>), ExportError> {
+    let mut builder = Builder {
+        root: json::Root {
+            asset: json::Asset {
+                generator: Some("rust_erizer".to_string()),
+                version: "2.0".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        bin: Vec::new(),
+        textures: HashMap::new(),
+    };
+
+    let mut nodes = Vec::new();
+    for (name, model, world) in models {
+        nodes.push(builder.push_model(name, model, world)?);
+    }
+
+    builder.root.scenes.push(json::Scene {
+        extensions: None,
+        extras: Default::default(),
+        name: Some(scene_name.to_string()),
+        nodes,
+    });
+    builder.root.scene = Some(Index::new(0));
+
+    builder.bin.resize(builder.bin.len().next_multiple_of(4), 0);
+    if !builder.bin.is_empty() {
+        builder.root.buffers.push(json::Buffer {
+            byte_length: builder.bin.len() as u32,
+            name: None,
+            uri: None,
+            extensions: None,
+            extras: Default::default(),
+        });
+    }
+
+    Ok((builder.root, builder.bin))
+}
+
+//Models with their world matrices, the scene graph flattened
+pub fn to_glb(scene_name: &str, models: &[(&str, &Model, Mat4)]) -> Result<Vec<u8>, ExportError> {
+    let (root, bin) = build(scene_name, models)?;
+    let json = json::serialize::to_vec(&root)?;
+
+    let glb = gltf::binary::Glb {
+        header: gltf::binary::Header {
+            magic: *b"glTF",
+            version: 2,
+            length: 0, //to_vec works it out
+        },
+        json: Cow::Owned(json),
+        bin: (!bin.is_empty()).then_some(Cow::Owned(bin)),
+    };
+
+    Ok(glb.to_vec()?)
+}
+
+//.glb is a single file, .gltf gets its buffer written next to it as <name>.bin
+pub fn write(
+    filepath: &str,
+    scene_name: &str,
+    models: &[(&str, &Model, Mat4)],
+) -> Result<(), ExportError> {
+    let path = Path::new(filepath);
+
+    if path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("glb"))
+    {
+        std::fs::write(path, to_glb(scene_name, models)?)?;
+        return Ok(());
+    }
+
+    let (mut root, bin) = build(scene_name, models)?;
+    if let Some(buffer) = root.buffers.first_mut() {
+        let bin_path = path.with_extension("bin");
+        let bin_name = bin_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("buffer.bin");
+        buffer.uri = Some(bin_name.to_string());
+        std::fs::write(&bin_path, &bin)?;
+    }
+
+    std::fs::write(path, json::serialize::to_string_pretty(&root)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Quat, Vec2};
+
+    use super::*;
+    use crate::{data, material::UvTransform, scene::Scene, test_utils::temp_dir};
+
+    fn textured_scene() -> (Scene, i32) {
+        let tex = TEXTURE_MANAGER
+            .write()
+            .unwrap()
+            .load_from_filepath("resources/textures/bojan.jpg")
+            .unwrap();
+
+        let mut cube = VertexMesh::from_texture(&data::CUBE_VERTICES, &data::CUBE_INDICES, tex);
+        cube.material.base_color = Vec4::new(1.0, 0.5, 0.25, 1.0);
+        cube.material.base_tex.transform = Some(UvTransform {
+            offset: Vec2::new(0.5, 0.0),
+            rotation: 0.0,
+            scale: Vec2::splat(2.0),
+        });
+
+        let mut pyramid =
+            VertexMesh::from_texture(&data::PYRAMID_VERTEX, &data::PYRAMID_INDEX, tex);
+        pyramid.transform = Transform::from_translation(Vec3::new(0.0, 1.0, 0.0));
+
+        let mut scene = Scene::new("Test".to_string());
+        scene.add_mesh("Cube", cube);
+        let pyramid = scene.add_mesh("Pyramid", pyramid);
+        scene.get_model(pyramid).unwrap().transform = Transform {
+            translation: Vec3::new(2.0, 0.0, 0.0),
+            rotation: Quat::from_rotation_y(1.0),
+            scale: Vec3::splat(0.5),
+        };
+
+        (scene, tex)
+    }
+
+    fn check_round_trip(loaded: &Model) {
+        assert_eq!(loaded.meshes.len(), 2);
+
+        let cube = &loaded.meshes[0];
+        assert_eq!(cube.indices.as_slice(), data::CUBE_INDICES.as_slice());
+        assert_eq!(cube.vertices.len(), data::CUBE_VERTICES.len());
+        assert_eq!(cube.material.base_color, Vec4::new(1.0, 0.5, 0.25, 1.0));
+        assert_eq!(
+            cube.material.base_tex.transform.unwrap().scale,
+            Vec2::splat(2.0)
+        );
+        for (loaded, original) in cube.vertices.iter().zip(&data::CUBE_VERTICES) {
+            assert_eq!(loaded.position, original.position);
+            assert_eq!(loaded.uv, original.uv);
+        }
+
+        //Both meshes share the texture
+        assert_eq!(loaded.meshes[0].texture, loaded.meshes[1].texture);
+        let manager = TEXTURE_MANAGER.read().unwrap();
+        let texture = manager.get_texture(&cube.texture.unwrap()).unwrap();
+        let original = Texture::from_filepath("resources/textures/bojan.jpg").unwrap();
+        assert_eq!(
+            (texture.width, texture.height),
+            (original.width, original.height)
+        );
+        assert_eq!(texture.data, original.data);
+
+        //Model transform times the mesh's own
+        let expected = Transform {
+            translation: Vec3::new(2.0, 0.0, 0.0),
+            rotation: Quat::from_rotation_y(1.0),
+            scale: Vec3::splat(0.5),
+        }
+        .local()
+            * Transform::from_translation(Vec3::new(0.0, 1.0, 0.0)).local();
+        let pyramid = &loaded.meshes[1];
+        let world = loaded.mesh_parent(pyramid) * pyramid.transform.local();
+        assert!(world.abs_diff_eq(expected, 1e-5));
+    }
+
+    #[test]
+    fn round_trips_glb() {
+        let (scene, tex) = textured_scene();
+        let loaded = Model::from_slice(&scene.to_glb().unwrap()).unwrap();
+
+        check_round_trip(&loaded);
+        assert!(loaded.warnings.is_empty());
+        TEXTURE_MANAGER.write().unwrap().destroy_texture(&tex);
+    }
+
+    #[test]
+    fn round_trips_gltf_with_bin() {
+        let (scene, tex) = textured_scene();
+        let dir = temp_dir("export");
+        let path = dir.join("scene.gltf");
+
+        scene.export_gltf(path.to_str().unwrap()).unwrap();
+        assert!(dir.join("scene.bin").exists());
+        let loaded = Model::from_filepath(path.to_str().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        check_round_trip(&loaded.unwrap());
+        TEXTURE_MANAGER.write().unwrap().destroy_texture(&tex);
+    }
+
+    #[test]
+    fn exports_topology_and_colors() {
+        let mut lines = VertexMesh::new(&data::PYRAMID_VERTEX, &[0, 1, 1, 2], None, None);
+        lines.topology = Topology::LineList;
+        lines.vertices[0].color = Vec4::new(1.0, 0.0, 0.0, 0.5);
+
+        let mut scene = Scene::new("Lines".to_string());
+        scene.add_mesh("Lines", lines);
+
+        let loaded = Model::from_slice(&scene.to_glb().unwrap()).unwrap();
+        let mesh = &loaded.meshes[0];
+        assert_eq!(mesh.topology, Topology::LineList);
+        assert_eq!(mesh.indices, vec![0, 1, 1, 2]);
+        assert_eq!(mesh.vertices[0].color, Vec4::new(1.0, 0.0, 0.0, 0.5));
+        assert!(mesh.texture.is_none());
+    }
+}
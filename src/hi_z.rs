@@ -0,0 +1,106 @@
+use glam::{IVec2, Vec2};
+
+use crate::sliced_buffer::DepthCompare;
+
+//Hierarchical depth pyramid over the tiles.
+//Level 0 has one texel per tile, every level above halves the resolution.
+//Every texel holds the farthest depth of everything below it, so it's conservative.
+pub struct HiZ {
+    pub levels: Vec<Vec<f32>>,
+    pub sizes: Vec<IVec2>,
+    pub tile_size: i32,
+}
+
+impl HiZ {
+    pub fn new(amount_of_tiles: IVec2, tile_size: i32) -> Self {
+        let mut levels = Vec::new();
+        let mut sizes = Vec::new();
+
+        let mut size = amount_of_tiles;
+        loop {
+            levels.push(vec![f32::INFINITY; (size.x * size.y) as usize]);
+            sizes.push(size);
+
+            if size.x == 1 && size.y == 1 {
+                break;
+            }
+
+            size = IVec2::new((size.x + 1) / 2, (size.y + 1) / 2);
+        }
+
+        Self {
+            levels,
+            sizes,
+            tile_size,
+        }
+    }
+
+    pub fn fill(&mut self, val: f32) {
+        for level in self.levels.iter_mut() {
+            level.fill(val);
+        }
+    }
+
+    //Takes the farthest depth of every tile in row order and rebuilds the levels above it
+    pub fn build(&mut self, tile_depths: impl Iterator<Item = f32>, compare: DepthCompare) {
+        for (texel, depth) in self.levels[0].iter_mut().zip(tile_depths) {
+            *texel = depth;
+        }
+
+        for l in 1..self.levels.len() {
+            let src_size = self.sizes[l - 1];
+            let dst_size = self.sizes[l];
+
+            for y in 0..dst_size.y {
+                for x in 0..dst_size.x {
+                    //The top left child always exists
+                    let mut farthest = self.levels[l - 1][(x * 2 + y * 2 * src_size.x) as usize];
+
+                    for (sx, sy) in [(1, 0), (0, 1), (1, 1)] {
+                        let src_x = x * 2 + sx;
+                        let src_y = y * 2 + sy;
+
+                        if src_x < src_size.x && src_y < src_size.y {
+                            let src = self.levels[l - 1][(src_x + src_y * src_size.x) as usize];
+                            farthest = compare.farthest(farthest, src);
+                        }
+                    }
+
+                    self.levels[l][(x + y * dst_size.x) as usize] = farthest;
+                }
+            }
+        }
+    }
+
+    //Is a screen space rect with the given closest depth behind everything drawn there?
+    pub fn is_occluded(&self, min: Vec2, max: Vec2, nearest: f32, compare: DepthCompare) -> bool {
+        let last_tile = self.sizes[0] - 1;
+        let mut min_t = (min / self.tile_size as f32)
+            .floor()
+            .as_ivec2()
+            .clamp(IVec2::ZERO, last_tile);
+        let mut max_t = (max / self.tile_size as f32)
+            .floor()
+            .as_ivec2()
+            .clamp(IVec2::ZERO, last_tile);
+
+        //Go up the pyramid until the rect covers at most 2x2 texels
+        let mut level = 0;
+        while level + 1 < self.levels.len() && (max_t.x - min_t.x > 1 || max_t.y - min_t.y > 1) {
+            min_t /= 2;
+            max_t /= 2;
+            level += 1;
+        }
+
+        let size = self.sizes[level];
+        let mut farthest = self.levels[level][(min_t.x + min_t.y * size.x) as usize];
+        for y in min_t.y..=max_t.y {
+            for x in min_t.x..=max_t.x {
+                farthest =
+                    compare.farthest(farthest, self.levels[level][(x + y * size.x) as usize]);
+            }
+        }
+
+        compare.occludes(nearest, farthest)
+    }
+}
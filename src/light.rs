@@ -0,0 +1,37 @@
+use glam::Vec3;
+
+//Shines the same way everywhere, like the sun
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirectionalLight {
+    pub direction: Vec3, //normalized, from the light towards the scene
+    pub color: Vec3,     //0 to 1
+    pub intensity: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lighting {
+    pub ambient: Vec3,
+    pub lights: Vec<DirectionalLight>,
+}
+
+impl Default for Lighting {
+    fn default() -> Self {
+        Self {
+            ambient: Vec3::splat(0.2),
+            lights: vec![DirectionalLight {
+                direction: Vec3::new(-1.0, -1.0, -1.0).normalize(),
+                color: Vec3::ONE,
+                intensity: 1.0,
+            }],
+        }
+    }
+}
+
+impl Lighting {
+    //Ambient plus the Lambert term of every light
+    pub fn shade(&self, normal: Vec3) -> Vec3 {
+        self.lights.iter().fold(self.ambient, |sum, light| {
+            sum + light.color * light.intensity * f32::max(normal.dot(-light.direction), 0.0)
+        })
+    }
+}
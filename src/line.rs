@@ -0,0 +1,170 @@
+use glam::{IVec2, Vec2, Vec3, Vec4Swizzles};
+
+use crate::{
+    data::Vertex,
+    mesh::{lod_color, RenderMode},
+    render_utils,
+    sliced_buffer::{DepthCompare, DepthState, DrawState, FrameStats, PixelId, Primitive},
+    triangle::Triangle,
+};
+
+#[derive(Copy, Clone)]
+pub struct Line {
+    pub v: [Vertex; 2],
+    pub rec: [f32; 2],  //Perspective Correction Coords
+    pub ssc: [Vec2; 2], //screen coordinates
+    pub width: f32,     //in pixels
+    pub aabb: Option<[Vec2; 2]>,
+    pub primitive: u32, //idx of the line in its mesh, for the ID buffer
+}
+
+impl Line {
+    pub fn new(vertices: [Vertex; 2], width: f32) -> Self {
+        Self {
+            v: vertices,
+            rec: [0.0, 0.0],
+            ssc: [Vec2::splat(0.0); 2],
+            width,
+            aabb: None,
+            primitive: 0,
+        }
+    }
+
+    //Clips against the near plane in clip space and moves the line to screen space
+    pub fn render_line(&self, reversed_z: bool) -> Option<Line> {
+        let mut line = *self;
+
+        let p0 = line.v[0].position;
+        let p1 = line.v[1].position;
+
+        //Both outside the same plane
+        if (p0.x.abs() > p0.w && p1.x.abs() > p1.w && p0.x * p1.x > 0.0)
+            || (p0.y.abs() > p0.w && p1.y.abs() > p1.w && p0.y * p1.y > 0.0)
+            || (p0.z > p0.w && p1.z > p1.w)
+            || (p0.z < 0.0 && p1.z < 0.0)
+        {
+            return None;
+        }
+
+        let d0 = Triangle::near_dist(&p0, reversed_z);
+        let d1 = Triangle::near_dist(&p1, reversed_z);
+
+        if d0 < 0.0 {
+            line.v[0] = render_utils::lerp(line.v[0], line.v[1], (-d0) / (d1 - d0));
+        } else if d1 < 0.0 {
+            line.v[1] = render_utils::lerp(line.v[1], line.v[0], (-d1) / (d0 - d1));
+        }
+
+        Some(Self::line_to_screen(&line))
+    }
+
+    fn line_to_screen(line: &Line) -> Line {
+        let mut line = *line;
+
+        for i in 0..2 {
+            line.rec[i] = 1.0 / line.v[i].position.w;
+            line.v[i].position *= line.rec[i];
+            line.ssc[i] = render_utils::ndc_to_screen(line.v[i].position);
+        }
+
+        //Half the width plus a pixel for the anti-aliased edge
+        let extent = Vec2::splat(line.width * 0.5 + 1.0);
+        line.aabb = Some(render_utils::clamp_aabb(
+            line.ssc[0].min(line.ssc[1]) - extent,
+            line.ssc[0].max(line.ssc[1]) + extent,
+        ));
+
+        line
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_fragments(
+        &self,
+        pos: IVec2,
+        size: IVec2,
+        color_buff: &mut [u32],
+        depth_buff: &mut [f32],
+        mut id_buff: Option<&mut [Option<PixelId>]>,
+        draw: &DrawState,
+        depth_state: DepthState,
+        stats: &mut FrameStats,
+    ) {
+        let dir = self.ssc[1] - self.ssc[0];
+        let len_sq = dir.length_squared().max(f32::EPSILON);
+
+        for idx_x in 0..size.x {
+            for idx_y in 0..size.y {
+                let x = pos.x + idx_x;
+                let y = pos.y + idx_y;
+
+                let p = Vec2::new(x as f32, y as f32) + 0.5;
+                let idx: usize = (idx_x + idx_y * size.x) as usize;
+
+                //Closest point on the segment, distance is in pixels so the width stays constant
+                let t = ((p - self.ssc[0]).dot(dir) / len_sq).clamp(0.0, 1.0);
+                let dist = p.distance(self.ssc[0] + dir * t);
+
+                let coverage = (self.width * 0.5 + 0.5 - dist).clamp(0.0, 1.0);
+                if coverage <= 0.0 {
+                    continue;
+                }
+
+                //NDC depth is linear in screen space
+                let depth = render_utils::lerp(self.v[0].position.z, self.v[1].position.z, t);
+                let depth = depth_state.format.quantize(depth);
+
+                stats.fragments_tested += 1;
+                if !depth_state.compare.test(depth, depth_buff[idx]) {
+                    continue;
+                }
+
+                if depth_state.write {
+                    depth_buff[idx] = depth;
+                }
+
+                if let Some(id_buff) = id_buff.as_deref_mut() {
+                    id_buff[idx] = draw.id.map(|id| id.pixel(self.primitive));
+                }
+
+                stats.fragments_shaded += 1;
+
+                //Attributes need perspective correction
+                let rec = render_utils::lerp(self.rec[0], self.rec[1], t);
+                let correction = 1.0 / rec;
+                let color = render_utils::lerp(
+                    self.v[0].color * self.rec[0],
+                    self.v[1].color * self.rec[1],
+                    t,
+                ) * correction;
+                let normal = render_utils::lerp(
+                    self.v[0].normal * self.rec[0],
+                    self.v[1].normal * self.rec[1],
+                    t,
+                ) * correction;
+
+                let fc = shade_unlit(draw, color.xyz(), normal, depth);
+                render_utils::blend(color_buff, idx, fc, coverage);
+            }
+        }
+    }
+}
+
+impl Primitive for Line {
+    fn aabb(&self) -> [Vec2; 2] {
+        self.aabb.unwrap()
+    }
+
+    fn nearest_depth(&self, compare: DepthCompare) -> f32 {
+        compare.nearest(self.v[0].position.z, self.v[1].position.z)
+    }
+}
+
+//Lines and points have no surface to light, they show their color or a debug view
+pub fn shade_unlit(draw: &DrawState, color: Vec3, normal: Vec3, depth: f32) -> Vec3 {
+    match draw.render_mode {
+        RenderMode::Normal => (normal * 0.5 + 0.5) * 255.0,
+        RenderMode::Depth => Vec3::splat(depth * 255.0),
+        RenderMode::Lod => lod_color(draw.lod) * 255.0,
+        _ => color * draw.material.base_color.xyz() * 255.0,
+    }
+}
@@ -3,39 +3,57 @@
 
 extern crate minifb;
 
+mod animation;
+mod bvh;
 mod camera;
 mod data;
+mod file_watcher;
+mod gltf_exporter;
+mod hi_z;
 mod input;
+mod light;
+mod line;
 mod material;
 mod mesh;
 mod model;
+mod model_store;
 mod mouse_diff;
+mod obj_loader;
+mod ply_loader;
+mod point;
 mod render_utils;
 mod sampler;
 mod scene;
+mod scene_file;
+mod scene_graph;
+mod simplify;
 mod sliced_buffer;
+mod stl_loader;
+#[cfg(test)]
+mod test_utils;
 mod tex_manager;
 mod texture;
 mod transform;
 mod triangle;
 
-use mesh::VertexMesh;
+use mesh::Wireframe;
+use minifb::MouseButton;
 use minifb::MouseMode;
 use minifb::ScaleMode;
-use model::Model;
 use scene::Scene;
-use sliced_buffer::SlicedBuffers;
-use transform::Transform;
+use scene_file::ModelDesc;
+use sliced_buffer::{DepthFormat, SlicedBuffers};
 
+use bvh::Ray;
 use camera::Camera;
+use tex_manager::TEXTURE_MANAGER;
 
-use glam::Vec3;
+use glam::{Vec2, Vec3};
 use minifb::{Key, Window, WindowOptions};
 use std::time::Instant;
 
 use crate::input::enable_mouse;
 use crate::mouse_diff::set_mouse_pos;
-use crate::tex_manager::*;
 
 const _RED: Vec3 = Vec3::new(255.0, 0.0, 0.0);
 const _GREEN: Vec3 = Vec3::new(0.0, 255.0, 0.0);
@@ -51,6 +69,9 @@ const BUFF_HEIGHT: usize = WIN_HEIGHT / BUFF_SCALE;
 const BUFF_SCALE: usize = 2;
 const TILE_SIZE: i32 = 8;
 
+const SCENE_DIR: &str = "scenes";
+const EYE_HEIGHT: f32 = 1.7; //how far above the floor V puts the camera
+
 // BUFF_SCALE Down Testing
 // /1 - 1920 x 1080 - 8x8 tiles - ~300ms
 // /2 - 960 x 540 - 8x8 tiles - ~150ms
@@ -72,58 +93,18 @@ fn main() {
             panic!("{}", e);
         });
 
-    let bojan_tex = {
-        let mut manager = TEXTURE_MANAGER.write().unwrap();
-        manager
-            .load_from_filepath("resources/textures/bojan.jpg")
-            .expect("Not found")
-    };
-
-    let mut scenes: Vec<Scene> = Vec::new();
+    let mut scenes = load_scenes(SCENE_DIR);
     let mut scene_idx: usize = 0;
-    scenes.push(Scene::new("Sponza".to_string()));
-    scenes.push(Scene::new("Helment & Cube".to_string()));
-    scenes.push(Scene::new("Caspia Car".to_string()));
-
-    let cube = VertexMesh::from_texture(&data::CUBE_VERTICES, &data::CUBE_INDICES, bojan_tex);
-
-    //Triangle
-    let mut triangle = VertexMesh::from_texture(&data::PLANE_DATA, &[0, 3, 2], bojan_tex);
-    triangle.transform = Transform::from_translation(Vec3::new(0.0, 0.0, 2.0));
-
-    //Plane
-    let mut plane = VertexMesh::from_texture(&data::PLANE_DATA, &[0, 2, 1, 0, 3, 2], bojan_tex);
-    plane.transform = Transform::from_translation(Vec3::new(4.0, 0.0, 0.0));
-
-    //Rhombus
-    let mut rhombus =
-        VertexMesh::from_texture(&data::RHOMBUS_VERTICES, &data::RHOMBUS_INDEX, bojan_tex);
-    rhombus.transform = Transform::from_translation(Vec3::new(0.0, 3.0, 0.0));
-
-    //Pyramid
-    let mut pyramid =
-        VertexMesh::from_texture(&data::PYRAMID_VERTEX, &data::PYRAMID_INDEX, bojan_tex);
-    pyramid.transform = Transform::from_translation(Vec3::new(2.0, 3.0, 2.0));
+    let sponza = scenes
+        .iter()
+        .find_map(|scene| scene.model_desc("Sponza"))
+        .unwrap_or_else(|| ModelDesc::from_path("Sponza", "resources/sponza/Sponza.gltf"));
 
     // Camera Init
     let mut mouse_camera_controls = true;
     let mut camera = Camera::default();
+    scenes[scene_idx].apply_camera(&mut camera);
 
-    //For Sponza
-    camera.set_position(Vec3::new(7.0, 2.5, -0.1));
-    camera.yaw = 1.6348684;
-
-    //Sponza
-    let mut gltf_obj = Model::from_filepath("resources/sponza/Sponza.gltf");
-    gltf_obj.transform = Transform::from_scale(Vec3::new(0.008, 0.008, 0.008));
-
-    //Cube
-    // let mut gltf_obj = Model::from_filepath("resources/cube/Cube.gltf");
-
-    // Helmet
-    // let mut gltf_obj = Model::from_filepath("resources/helmet/Helmet.gltf");
-
-    let mut rotation: f32 = 0.0;
     let mut dmouse = window.get_mouse_pos(MouseMode::Pass).unwrap();
 
     // Limit to max ~60 fps update rate
@@ -135,29 +116,12 @@ fn main() {
 
     let mut prev_dt = Instant::now();
 
-    //SCENE 1
-    scenes[0].add_mesh("Cube", cube);
-    scenes[0].add_mesh("Triangle", triangle);
-    scenes[0].add_mesh("Plane", plane);
-    scenes[0].add_mesh("Rhombus", rhombus);
-    scenes[0].add_mesh("Pyramid", pyramid);
-    scenes[0].add_gltf("Sponza", "resources/sponza/Sponza.gltf");
-
-    if let Some(model) = scenes[0].get_model("Sponza") {
-        model.transform = Transform::from_scale(Vec3::new(0.008, 0.008, 0.008));
-    }
-
-    // SCENE 2
-    scenes[1].add_gltf("Helmet", "resources/helmet/Helmet.gltf");
-    scenes[1].add_gltf("Cube", "resources/cube/Cube.gltf");
-
-    if let Some(model) = scenes[1].get_model("Cube") {
-        model.transform = Transform::from_translation(Vec3::new(2.0, 2.0, 0.0));
-    }
-
-    // SCENE 3
-    scenes[2].add_gltf("Car Base", "resources/caspia/CaspiaBody.gltf");
-    scenes[2].add_gltf("Car Wheels", "resources/caspia/CaspiaWheel.gltf");
+    let mut front_to_back = false;
+    let mut sort_by_texture = false;
+    let mut wireframe_overlay = false;
+    let mut looping = true;
+    let mut id_buffer = false; //every frame, otherwise only for the frame after a click
+    let mut pick_pos: Option<(f32, f32)> = None;
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
         //Delta Time
@@ -166,20 +130,14 @@ fn main() {
         prev_dt = now;
 
         //Clear buffers
-        let clear_color = render_utils::vec3_to_u32(_RED);
+        let clear_color = render_utils::vec3_to_u32(scenes[scene_idx].clear_color);
 
+        sliced_buffers.id_buffer = id_buffer || pick_pos.is_some();
         sliced_buffers.clear_color(clear_color);
-        sliced_buffers.clear_depth(f32::INFINITY);
+        sliced_buffers.clear_depth(camera.depth_clear_value());
+        sliced_buffers.clear_ids();
         sliced_buffers.clear_tiles();
-
-        //Rotate object on screen
-        rotation += 0.01;
-        let cube_trans = Transform::from_rotation_quat(glam::Quat::from_euler(
-            glam::EulerRot::XYZ,
-            rotation,
-            rotation,
-            0.0,
-        ));
+        sliced_buffers.clear_stats();
 
         // Mouse diff for camera rotaiton
         if !first_frame {
@@ -203,36 +161,230 @@ fn main() {
             .unwrap();
         }
 
+        // Hot-reload whatever changed on disk
+        TEXTURE_MANAGER.write().unwrap().reload_changed();
+        scenes[scene_idx].hot_reload();
+
         scenes[scene_idx].change_render_mode(&window);
+        scenes[scene_idx].update(dt);
+        scenes[scene_idx].update_camera(&mut camera);
         scenes[scene_idx].render(&mut sliced_buffers, &camera);
 
-        //All triangles should have their vertices in screen space here
-        //sliced_buffers.aa_bb_comparison();
-        //
-        //sliced_buffers.render(&camera, &cube.render_mode);
+        // Pick what was clicked now that this frame's IDs are in
+        if let Some(mouse_pos) = pick_pos.take() {
+            let scene = &scenes[scene_idx];
+            match scene.pick(&sliced_buffers, &camera, mouse_pos, window.get_size()) {
+                Some(pick) => println!(
+                    "Clicked {} (mesh {}, LOD {}, primitive {}) at {:.2}",
+                    scene.model_name(pick.model).unwrap_or("?"),
+                    pick.mesh,
+                    pick.lod,
+                    pick.primitive,
+                    pick.position
+                ),
+                None => println!("Clicked nothing"),
+            }
+        }
 
         if window.is_key_down(Key::LeftBracket) {
-            scene_idx -= 1;
-            scene_idx %= scenes.len();
+            scene_idx = (scene_idx + scenes.len() - 1) % scenes.len();
+            scenes[scene_idx].apply_camera(&mut camera);
             std::thread::sleep(std::time::Duration::from_millis(100));
         }
         if window.is_key_down(Key::RightBracket) {
-            scene_idx += 1;
-            scene_idx %= scenes.len();
+            scene_idx = (scene_idx + 1) % scenes.len();
+            scenes[scene_idx].apply_camera(&mut camera);
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Save the scene and the current camera back to its file
+        if window.is_key_down(Key::F5) {
+            let scene = &mut scenes[scene_idx];
+            let filepath = scene
+                .filepath()
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("{SCENE_DIR}/{}.json", scene.name()));
+
+            scene.set_camera(&camera);
+            match scene.save(&filepath) {
+                Ok(()) => println!("Saved {filepath}"),
+                Err(err) => println!("Failed to save {filepath}: {err}"),
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Toggle depth pre-pass
+        if window.is_key_down(Key::P) {
+            sliced_buffers.depth_prepass = !sliced_buffers.depth_prepass;
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Toggle reversed-Z
+        if window.is_key_down(Key::Z) {
+            camera.reversed_z = !camera.reversed_z;
+            sliced_buffers.depth_compare = camera.depth_compare();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Toggle infinite far plane
+        if window.is_key_down(Key::I) {
+            camera.infinite_far = !camera.infinite_far;
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Cycle depth buffer precision
+        if window.is_key_down(Key::U) {
+            sliced_buffers.depth_format = match sliced_buffers.depth_format {
+                DepthFormat::F32 => DepthFormat::Unorm24,
+                DepthFormat::Unorm24 => DepthFormat::Unorm16,
+                DepthFormat::Unorm16 => DepthFormat::F32,
+            };
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Toggle Hi-Z occlusion culling
+        if window.is_key_down(Key::H) {
+            sliced_buffers.hiz_culling = !sliced_buffers.hiz_culling;
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Toggle front to back sorting
+        if window.is_key_down(Key::O) {
+            front_to_back = !front_to_back;
+            for scene in &mut scenes {
+                scene.set_front_to_back(front_to_back);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Toggle grouping draws by texture
+        if window.is_key_down(Key::T) {
+            sort_by_texture = !sort_by_texture;
+            for scene in &mut scenes {
+                scene.set_sort_by_texture(sort_by_texture);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Toggle wireframe overlay
+        if window.is_key_down(Key::L) {
+            wireframe_overlay = !wireframe_overlay;
+            let wireframe = wireframe_overlay.then(|| Wireframe {
+                color: _BLACK,
+                ..Default::default()
+            });
+
+            for scene in &mut scenes {
+                scene.set_wireframe(wireframe);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Play / pause animations
+        if window.is_key_down(Key::Space) {
+            scenes[scene_idx].toggle_playback();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Toggle looping
+        if window.is_key_down(Key::J) {
+            looping = !looping;
+            scenes[scene_idx].set_looping(looping);
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Seek animations back / forward
+        if window.is_key_down(Key::Comma) {
+            scenes[scene_idx].seek_by(-dt);
+        }
+        if window.is_key_down(Key::Period) {
+            scenes[scene_idx].seek_by(dt);
+        }
+
+        // Next animation clip
+        if window.is_key_down(Key::K) {
+            scenes[scene_idx].next_clip();
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Pick the model under the cursor, or the screen center while the mouse is locked
+        if window.is_key_down(Key::G) {
+            let ndc = match window.get_mouse_pos(MouseMode::Clamp) {
+                Some((x, y)) if !mouse_camera_controls => {
+                    let (width, height) = window.get_size();
+                    Vec2::new(x / width as f32 * 2.0 - 1.0, 1.0 - y / height as f32 * 2.0)
+                }
+                _ => Vec2::ZERO,
+            };
+
+            let ray = camera.ray_from_screen(ndc);
+            let scene = &mut scenes[scene_idx];
+            match scene.ray_cast(&ray) {
+                Some(hit) => println!(
+                    "Picked {} (mesh {}) at {:.2} distance {:.2}",
+                    scene.model_name(hit.model).unwrap_or("?"),
+                    hit.mesh,
+                    hit.position,
+                    hit.distance
+                ),
+                None => println!("Picked nothing"),
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Pick through the ID buffer, what's under the cursor or the screen center. The
+        // next frame gets rendered with IDs and picked from
+        if window.get_mouse_down(MouseButton::Middle) {
+            let window_size = window.get_size();
+            pick_pos = match window.get_mouse_pos(MouseMode::Discard) {
+                Some(pos) if !mouse_camera_controls => Some(pos),
+                _ => Some((window_size.0 as f32 * 0.5, window_size.1 as f32 * 0.5)),
+            };
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Keep the ID buffer on for every frame
+        if window.is_key_down(Key::Q) {
+            id_buffer = !id_buffer;
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Put the camera on the floor below it
+        if window.is_key_down(Key::V) {
+            let eye = camera
+                .parent_frame()
+                .transform_point3(camera.transform.translation);
+            match scenes[scene_idx].ray_cast(&Ray::new(eye, Vec3::NEG_Y)) {
+                Some(hit) => {
+                    let eye = hit.position + Vec3::Y * EYE_HEIGHT;
+                    camera.set_position(camera.parent_frame().inverse().transform_point3(eye));
+                }
+                None => println!("No floor below the camera"),
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+
+        // Export the scene as .glb
+        if window.is_key_down(Key::X) {
+            let filepath = format!("{}.glb", scenes[scene_idx].name());
+            match scenes[scene_idx].export_gltf(&filepath) {
+                Ok(()) => println!("Exported {filepath}"),
+                Err(err) => println!("Failed to export {filepath}: {err}"),
+            }
             std::thread::sleep(std::time::Duration::from_millis(100));
         }
 
         // DELETE SPONZA
         if window.is_key_down(Key::N) {
-            scenes[scene_idx].remove_model("Sponza");
+            if let Some(sponza) = scenes[scene_idx].find_model("Sponza") {
+                scenes[scene_idx].remove_model(sponza);
+            }
         }
 
-        // SPAWN SPONZA
+        // SPAWN SPONZA, the way the scene file that has it describes it
         if window.is_key_down(Key::B) {
-            scenes[scene_idx].add_gltf("Sponza", "resources/sponza/Sponza.gltf");
-
-            if let Some(model) = scenes[scene_idx].get_model("Sponza") {
-                model.transform = Transform::from_scale(Vec3::new(0.008, 0.008, 0.008));
+            if let Err(err) = scenes[scene_idx].add_desc(sponza.clone()) {
+                println!("Failed to load Sponza: {err}");
             }
         }
 
@@ -249,5 +401,38 @@ fn main() {
 
         first_frame = false;
         println!("Time elapsed: {:?}", now.elapsed());
+        println!("{}", sliced_buffers.stats);
+    }
+}
+
+//Every .json in the directory sorted by filename, an empty scene if there are none
+fn load_scenes(dir: &str) -> Vec<Scene> {
+    let mut filepaths: Vec<String> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect(),
+        Err(err) => {
+            println!("Failed to read {dir}: {err}");
+            Vec::new()
+        }
+    };
+    filepaths.sort();
+
+    let mut scenes: Vec<Scene> = filepaths
+        .iter()
+        .filter_map(|filepath| match Scene::from_file(filepath) {
+            Ok(scene) => Some(scene),
+            Err(err) => {
+                println!("Failed to load {filepath}: {err}");
+                None
+            }
+        })
+        .collect();
+
+    if scenes.is_empty() {
+        scenes.push(Scene::new("Empty".to_string()));
     }
+    scenes
 }
@@ -1,15 +1,59 @@
-use glam::Vec4;
+use glam::{Vec2, Vec4};
+
+//KHR_texture_transform, the UV gets scaled, rotated and then offset
+#[derive(Copy, Clone)]
+pub struct UvTransform {
+    pub offset: Vec2,
+    pub rotation: f32, //radians, counter clockwise
+    pub scale: Vec2,
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        Self {
+            offset: Vec2::ZERO,
+            rotation: 0.0,
+            scale: Vec2::ONE,
+        }
+    }
+}
+
+impl UvTransform {
+    pub fn apply(&self, uv: Vec2) -> Vec2 {
+        let uv = uv * self.scale;
+        let (sin, cos) = self.rotation.sin_cos();
+
+        Vec2::new(cos * uv.x + sin * uv.y, -sin * uv.x + cos * uv.y) + self.offset
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct TextureSlot {
+    pub tex_idx: i32,   //-1 when there's no texture
+    pub tex_coord: u32, //UV set to sample with, 0 or 1
+    pub transform: Option<UvTransform>,
+}
+
+impl Default for TextureSlot {
+    fn default() -> Self {
+        Self {
+            tex_idx: -1,
+            tex_coord: 0,
+            transform: None,
+        }
+    }
+}
 
 #[derive(Copy, Clone)]
 pub struct Material {
-    pub base_tex_idx: i32,
+    pub base_tex: TextureSlot,
     pub base_color: Vec4,
 }
 
 impl Default for Material {
     fn default() -> Self {
         Self {
-            base_tex_idx: -1,
+            base_tex: TextureSlot::default(),
             base_color: Vec4::splat(1.0),
         }
     }
@@ -1,12 +1,34 @@
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, OnceLock,
+};
 
 use glam::{Mat4, Vec2, Vec3, Vec4, Vec4Swizzles};
 use rand::Rng;
+use rayon::prelude::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator,
+    ParallelSlice, ParallelSliceMut,
+};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    camera::Camera, data::Vertex, material::Material, sliced_buffer::SlicedBuffers,
-    tex_manager::TEXTURE_MANAGER, texture::Texture, transform::Transform, triangle::Triangle,
+    bvh::{Aabb, Bvh, Ray},
+    camera::Camera,
+    data::Vertex,
+    line::Line,
+    material::{Material, TextureSlot, UvTransform},
+    model::{LoadOptions, NormalGeneration},
+    point::Point,
+    render_utils, simplify,
+    sliced_buffer::{DrawId, DrawState, Primitive, SlicedBuffers},
+    tex_manager::TEXTURE_MANAGER,
+    texture::Texture,
+    transform::Transform,
+    triangle::{ClipResult, Triangle},
 };
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum RenderMode {
     Default,
     VertexColor,
@@ -17,6 +39,8 @@ pub enum RenderMode {
     Bary,
     Depth,
     Aabb,
+    Wireframe,
+    Lod, //colored by the active level of detail
     Error,
 }
 
@@ -32,7 +56,9 @@ impl RenderMode {
             Uv => Bary,
             Bary => Depth,
             Depth => Aabb,
-            Aabb => Error,
+            Aabb => Wireframe,
+            Wireframe => Lod,
+            Lod => Error,
             Error => Default,
         }
     }
@@ -49,7 +75,9 @@ impl RenderMode {
             Bary => Uv,
             Depth => Bary,
             Aabb => Depth,
-            Error => Aabb,
+            Wireframe => Aabb,
+            Lod => Wireframe,
+            Error => Lod,
         }
     }
 }
@@ -60,6 +88,60 @@ impl Default for RenderMode {
     }
 }
 
+//How the index buffer gets turned into primitives
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Topology {
+    #[default]
+    TriangleList,
+    LineList,  //every two indices are a line
+    LineStrip, //every index connects to the one before it
+    PointList,
+}
+
+#[derive(Clone, Copy)]
+pub struct Wireframe {
+    pub color: Vec3, //0 - 255
+    pub width: f32,  //in pixels
+}
+
+impl Default for Wireframe {
+    fn default() -> Self {
+        Self {
+            color: Vec3::splat(255.0),
+            width: 1.0,
+        }
+    }
+}
+
+//Meshes at least this big on screen, in pixels, get full detail. Every halving of the
+//size picks the next level down
+const LOD_FULL_DETAIL_SIZE: f32 = 256.0;
+//How far past a switching size a mesh has to get before it switches, so meshes sitting
+//right at one don't flicker between two levels
+const LOD_HYSTERESIS: f32 = 0.15;
+//Levels with fewer triangles aren't worth it
+const LOD_MIN_TRIANGLES: usize = 32;
+
+//Full detail (level 0) is green, coarser levels go towards red
+pub fn lod_color(level: usize) -> Vec3 {
+    const COLORS: [Vec3; 5] = [
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(1.0, 1.0, 0.0),
+        Vec3::new(1.0, 0.5, 0.0),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(1.0, 0.0, 1.0),
+    ];
+    COLORS[level.min(COLORS.len() - 1)]
+}
+
+//Per vertex offsets, blended in by the mesh's morph weights.
+//We don't store tangents, so their deltas aren't loaded
+#[derive(Clone, Default)]
+pub struct MorphTarget {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+}
+
 pub struct VertexMesh {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
@@ -67,7 +149,21 @@ pub struct VertexMesh {
     pub texture: Option<i32>,
     pub transform: Transform,
     pub render_mode: RenderMode,
-    pub aa_bb: Option<[Vec3; 2]>, //For mesh frustum culling
+    pub aa_bb: Option<[Vec3; 2]>,     //For mesh frustum culling
+    pub sort_front_to_back: bool,     //Sort triangles by depth before submitting to reduce overdraw
+    pub wireframe: Option<Wireframe>, //Wireframe overlay, also styles RenderMode::Wireframe
+    pub topology: Topology,
+    pub line_width: f32,       //in pixels, for line topologies
+    pub point_size: f32,       //in pixels, for point lists
+    pub joints: Vec<[u16; 4]>, //JOINTS_0 per vertex, empty if the mesh isn't skinned
+    pub weights: Vec<Vec4>,    //WEIGHTS_0 per vertex
+    pub skin: Option<usize>,   //idx into the model's skins
+    pub morph_targets: Vec<MorphTarget>,
+    pub morph_weights: Vec<f32>,     //one per morph target
+    pub node: Option<usize>,         //glTF node the mesh belongs to
+    pub triangle_bvh: OnceLock<Bvh>, //built on the first ray cast, stale if the triangles change
+    pub lods: Vec<Vec<u32>>,         //simplified index buffers over the same vertices, coarser each
+    pub active_lod: AtomicUsize,     //0 is full detail, picked every render
 }
 
 #[allow(clippy::derivable_impls)]
@@ -81,6 +177,20 @@ impl Default for VertexMesh {
             transform: Default::default(),
             render_mode: Default::default(),
             aa_bb: Default::default(),
+            sort_front_to_back: false,
+            wireframe: None,
+            topology: Topology::TriangleList,
+            line_width: 1.0,
+            point_size: 1.0,
+            joints: Vec::new(),
+            weights: Vec::new(),
+            skin: None,
+            morph_targets: Vec::new(),
+            morph_weights: Vec::new(),
+            node: None,
+            triangle_bvh: OnceLock::new(),
+            lods: Vec::new(),
+            active_lod: AtomicUsize::new(0),
         }
     }
 }
@@ -120,6 +230,20 @@ impl VertexMesh {
             transform: Transform::IDENTITY,
             render_mode: RenderMode::default(),
             aa_bb: Some(aa_bb),
+            sort_front_to_back: false,
+            wireframe: None,
+            topology: Topology::TriangleList,
+            line_width: 1.0,
+            point_size: 1.0,
+            joints: Vec::new(),
+            weights: Vec::new(),
+            skin: None,
+            morph_targets: Vec::new(),
+            morph_weights: Vec::new(),
+            node: None,
+            triangle_bvh: OnceLock::new(),
+            lods: Vec::new(),
+            active_lod: AtomicUsize::new(0),
         }
     }
 
@@ -140,6 +264,20 @@ impl VertexMesh {
             transform: Transform::IDENTITY,
             render_mode: RenderMode::default(),
             aa_bb: Some(aa_bb),
+            sort_front_to_back: false,
+            wireframe: None,
+            topology: Topology::TriangleList,
+            line_width: 1.0,
+            point_size: 1.0,
+            joints: Vec::new(),
+            weights: Vec::new(),
+            skin: None,
+            morph_targets: Vec::new(),
+            morph_weights: Vec::new(),
+            node: None,
+            triangle_bvh: OnceLock::new(),
+            lods: Vec::new(),
+            active_lod: AtomicUsize::new(0),
         }
     }
 
@@ -155,9 +293,9 @@ impl VertexMesh {
         [min, max]
     }
 
-    fn cull_mesh_frustum(&self, mvp: Mat4) -> bool {
-        let min = mvp * Vec4::from((self.aa_bb.unwrap()[0], 1.0));
-        let max = mvp * Vec4::from((self.aa_bb.unwrap()[1], 1.0));
+    fn cull_mesh_frustum(aa_bb: [Vec3; 2], mvp: Mat4) -> bool {
+        let min = mvp * Vec4::from((aa_bb[0], 1.0));
+        let max = mvp * Vec4::from((aa_bb[1], 1.0));
 
         // Check if the object is too far away
         if min.z > min.w && max.z > max.w {
@@ -186,6 +324,144 @@ impl VertexMesh {
         true
     }
 
+    //Screen space rect and depth range of the AABB, None if it crosses the near or far plane
+    pub fn screen_bounds(aa_bb: [Vec3; 2], mvp: &Mat4) -> Option<(Vec2, Vec2, Vec2)> {
+        let [min, max] = aa_bb;
+
+        let mut ss_min = Vec2::splat(f32::INFINITY);
+        let mut ss_max = Vec2::splat(-f32::INFINITY);
+        let mut depth = Vec2::new(f32::INFINITY, -f32::INFINITY);
+
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            );
+
+            let clip = *mvp * Vec4::from((corner, 1.0));
+            if clip.z < 0.0 || clip.z > clip.w {
+                return None;
+            }
+
+            let ndc = clip / clip.w;
+            let sc = Vec2::new(
+                render_utils::map_to_range(ndc.x, -1.0, 1.0, 0.0, crate::BUFF_WIDTH as f32),
+                render_utils::map_to_range(-ndc.y, -1.0, 1.0, 0.0, crate::BUFF_HEIGHT as f32),
+            );
+
+            ss_min = ss_min.min(sc);
+            ss_max = ss_max.max(sc);
+            depth = Vec2::new(depth.x.min(ndc.z), depth.y.max(ndc.z));
+        }
+
+        Some((ss_min, ss_max, depth))
+    }
+
+    //Skinned and morphed meshes move their vertices every frame, their bounds come from the pose
+    pub fn is_posed(&self) -> bool {
+        self.skin.is_some() || !self.morph_targets.is_empty()
+    }
+
+    //Bounds of the unposed mesh in the space parent maps to
+    pub fn world_bounds(&self, parent: &Mat4) -> Option<Aabb> {
+        let [min, max] = self.aa_bb?;
+        let model = *parent * self.transform.local();
+        Some(Aabb::new(min, max).transformed(&model))
+    }
+
+    //Distance to the closest triangle, the ray is in the space parent maps to. Only
+    //triangle lists can be hit, lines and points have no area
+    pub fn ray_cast(&self, ray: &Ray, parent: &Mat4, max_t: f32) -> Option<f32> {
+        if self.topology != Topology::TriangleList {
+            return None;
+        }
+
+        let ray = ray.transformed(&(*parent * self.transform.local()).inverse());
+        let triangle =
+            |tri: &[u32]| [0, 1, 2].map(|i| self.vertices[tri[i] as usize].position.xyz());
+
+        let bvh = self.triangle_bvh.get_or_init(|| {
+            let bounds: Vec<Aabb> = self
+                .indices
+                .chunks_exact(3)
+                .map(|tri| Aabb::from_points(triangle(tri)))
+                .collect();
+            Bvh::build(&bounds)
+        });
+
+        let (_, t) = bvh.ray_cast(&ray, max_t, |tri_idx, _| {
+            let start = tri_idx as usize * 3;
+            let [a, b, c] = triangle(&self.indices[start..start + 3]);
+            ray.triangle_hit(a, b, c)
+        })?;
+        Some(t)
+    }
+
+    //Each level has about half the triangles of the one above it and gets simplified from
+    //it. Stops early once a level barely gets any smaller, flat shaded meshes have every
+    //edge on a seam and can't be simplified at all
+    pub fn generate_lods(&mut self, levels: usize) {
+        self.lods.clear();
+        self.active_lod.store(0, Ordering::Relaxed);
+        if self.topology != Topology::TriangleList {
+            return;
+        }
+
+        let positions: Vec<Vec3> = self.vertices.iter().map(|v| v.position.xyz()).collect();
+        let mut previous = self.indices.clone();
+
+        for _ in 0..levels {
+            let triangles = previous.len() / 3;
+            if triangles / 2 < LOD_MIN_TRIANGLES {
+                break;
+            }
+
+            let simplified = simplify::simplify(&positions, &previous, triangles / 2);
+            if simplified.len() / 3 > triangles * 9 / 10 {
+                break;
+            }
+
+            self.lods.push(simplified.clone());
+            previous = simplified;
+        }
+    }
+
+    //Level for a mesh this big on screen, None if it crosses the near plane. Only moves
+    //to another level once the size is past the switching size by LOD_HYSTERESIS
+    fn select_lod(&self, screen_size: Option<f32>) -> usize {
+        let Some(screen_size) = screen_size.filter(|_| !self.lods.is_empty()) else {
+            self.active_lod.store(0, Ordering::Relaxed);
+            return 0;
+        };
+
+        let level = |size: f32| {
+            let level = (LOD_FULL_DETAIL_SIZE / size.max(f32::EPSILON))
+                .log2()
+                .ceil();
+            (level.max(0.0) as usize).min(self.lods.len())
+        };
+
+        //Bigger is finer, so this is never an empty range
+        let finest = level(screen_size * (1.0 + LOD_HYSTERESIS));
+        let coarsest = level(screen_size * (1.0 - LOD_HYSTERESIS));
+
+        let lod = self
+            .active_lod
+            .load(Ordering::Relaxed)
+            .clamp(finest, coarsest);
+        self.active_lod.store(lod, Ordering::Relaxed);
+        lod
+    }
+
+    //What Pick::primitive indexes for that level
+    pub fn lod_indices(&self, lod: usize) -> &[u32] {
+        match lod {
+            0 => &self.indices,
+            lod => &self.lods[lod - 1],
+        }
+    }
+
     pub fn replace_transform(&mut self, trans: Transform) {
         self.transform = trans;
     }
@@ -198,118 +474,298 @@ impl VertexMesh {
         self.render_mode = self.render_mode.previous_mode();
     }
 
+    //Vertex stage for a single triangle: transform, backface cull and clip
+    #[allow(clippy::too_many_arguments)]
+    fn process_triangle(
+        &self,
+        vertices: &[Vertex],
+        indices: &[u32],
+        primitive: u32,
+        mv: &Mat4,
+        mvp: &Mat4,
+        inv_transpose: &Mat4,
+        reversed_z: bool,
+    ) -> ClipResult {
+        let tri_idx: [usize; 3] = [
+            indices[0] as usize,
+            indices[1] as usize,
+            indices[2] as usize,
+        ];
+
+        let clip0 = *mv * vertices[tri_idx[0]].position;
+        let clip1 = *mv * vertices[tri_idx[1]].position;
+        let clip2 = *mv * vertices[tri_idx[2]].position;
+
+        //Backface culling
+        if !Triangle::cull_triangle_backface(&clip0, &clip1, &clip2) {
+            return ClipResult::Clipped;
+        }
+
+        let mut triangle = Triangle::new([
+            Self::transform_vertex(&vertices[tri_idx[0]], mvp, inv_transpose),
+            Self::transform_vertex(&vertices[tri_idx[1]], mvp, inv_transpose),
+            Self::transform_vertex(&vertices[tri_idx[2]], mvp, inv_transpose),
+        ]);
+        triangle.primitive = primitive;
+        triangle.render_triangle(reversed_z)
+    }
+
+    //Moves a vertex to clip space and its normal to world space
+    fn transform_vertex(vertex: &Vertex, mvp: &Mat4, inv_transpose: &Mat4) -> Vertex {
+        //https://github.com/graphitemaster/normals_revisited
+        //let norm = render_utils::cofactor(model) * Vec4::from((vertex.normal, 0.0));
+        let norm = *inv_transpose * Vec4::from((vertex.normal, 0.0));
+
+        let mut copy = *vertex;
+        copy.position = *mvp * copy.position;
+        copy.normal = norm.xyz().normalize();
+
+        copy
+    }
+
+    //Vertex stage for lines: transform and clip every segment, keeps index buffer order
+    fn process_lines(
+        &self,
+        vertices: &[Vertex],
+        mvp: &Mat4,
+        inv_transpose: &Mat4,
+        reversed_z: bool,
+    ) -> Vec<Line> {
+        let (count, step) = match self.topology {
+            Topology::LineStrip => (self.indices.len().saturating_sub(1), 1),
+            _ => (self.indices.len() / 2, 2),
+        };
+
+        (0..count)
+            .into_par_iter()
+            .filter_map(|i| {
+                let v0 = &vertices[self.indices[i * step] as usize];
+                let v1 = &vertices[self.indices[i * step + 1] as usize];
+
+                let mut line = Line::new(
+                    [
+                        Self::transform_vertex(v0, mvp, inv_transpose),
+                        Self::transform_vertex(v1, mvp, inv_transpose),
+                    ],
+                    self.line_width,
+                );
+                line.primitive = i as u32;
+                line.render_line(reversed_z)
+            })
+            .collect()
+    }
+
+    fn process_points(&self, vertices: &[Vertex], mvp: &Mat4, inv_transpose: &Mat4) -> Vec<Point> {
+        self.indices
+            .par_iter()
+            .enumerate()
+            .filter_map(|(i, idx)| {
+                let vertex = Self::transform_vertex(&vertices[*idx as usize], mvp, inv_transpose);
+                let mut point = Point::new(vertex, self.point_size);
+                point.primitive = i as u32;
+                point.render_point()
+            })
+            .collect()
+    }
+
+    //Adds the weighted target deltas, None if no target has any weight
+    fn morph_vertices(&self) -> Option<Vec<Vertex>> {
+        let active: Vec<(&MorphTarget, f32)> = self
+            .morph_targets
+            .iter()
+            .zip(self.morph_weights.iter().copied())
+            .filter(|(_, weight)| *weight != 0.0)
+            .collect();
+
+        if active.is_empty() {
+            return None;
+        }
+
+        let morphed = self
+            .vertices
+            .par_iter()
+            .enumerate()
+            .map(|(idx, vertex)| {
+                let mut morphed = *vertex;
+                for (target, weight) in &active {
+                    morphed.position += Vec4::from((target.positions[idx] * *weight, 0.0));
+                    morphed.normal += target.normals[idx] * *weight;
+                }
+                morphed
+            })
+            .collect();
+
+        Some(morphed)
+    }
+
+    //Linear blend skinning, moves every vertex by the weighted joint matrices
+    fn skin_vertices(&self, vertices: &[Vertex], joint_matrices: &[Mat4]) -> Vec<Vertex> {
+        vertices
+            .par_iter()
+            .zip(self.joints.par_iter())
+            .zip(self.weights.par_iter())
+            .map(|((vertex, joints), weights)| {
+                let skin = (0..4)
+                    .map(|i| {
+                        let joint = joint_matrices
+                            .get(joints[i] as usize)
+                            .copied()
+                            .unwrap_or(Mat4::IDENTITY);
+                        joint * weights[i]
+                    })
+                    .fold(Mat4::ZERO, |acc, joint| acc + joint);
+
+                let mut skinned = *vertex;
+                skinned.position = skin * vertex.position;
+                skinned.normal = skin.transform_vector3(vertex.normal);
+                skinned
+            })
+            .collect()
+    }
+
     pub fn render(
         &self,
         slice_buff: &mut SlicedBuffers,
         camera: &Camera,
-        parent_trans: &Transform,
+        parent: &Mat4, //model space to world space
+        joint_matrices: Option<&[Mat4]>,
     ) {
-        let model = self.transform.local() * parent_trans.local();
+        let model = *parent * self.transform.local();
         let mv = camera.view() * model;
         let mvp = camera.perspective() * mv;
 
-        if self.cull_mesh_frustum(mvp) {
-            let inv_transpose = model.inverse().transpose();
-            let mut triangles_to_render: Vec<Triangle> = Vec::new();
-
-            for i in (0..self.indices.len()).step_by(3) {
-                let tri_idx: [usize; 3] = [
-                    self.indices[i] as usize,
-                    self.indices[i + 1] as usize,
-                    self.indices[i + 2] as usize,
-                ];
-
-                let clip0 = mv * self.vertices[tri_idx[0]].position;
-                let clip1 = mv * self.vertices[tri_idx[1]].position;
-                let clip2 = mv * self.vertices[tri_idx[2]].position;
-
-                //Backface culling
-                if !Triangle::cull_triangle_backface(&clip0, &clip1, &clip2) {
-                    continue;
-                }
+        //Morphed and skinned meshes get posed first, the bounds have to follow the pose
+        let morphed = self.morph_vertices();
+        let skinned = joint_matrices
+            .filter(|_| !self.joints.is_empty())
+            .map(|joint_matrices| {
+                let vertices = morphed.as_deref().unwrap_or(&self.vertices);
+                self.skin_vertices(vertices, joint_matrices)
+            });
+        let posed = skinned.or(morphed);
 
-                let clip0 = mvp * self.vertices[tri_idx[0]].position;
-                let clip1 = mvp * self.vertices[tri_idx[1]].position;
-                let clip2 = mvp * self.vertices[tri_idx[2]].position;
-
-                //https://github.com/graphitemaster/normals_revisited
-                //let norm0 = render_utils::cofactor(model) * Vec4::from((self.vertices[tri_idx[0]].normal, 0.0));
-                //let norm1 = render_utils::cofactor(model) * Vec4::from((self.vertices[tri_idx[1]].normal, 0.0));
-                //let norm2 = render_utils::cofactor(model) * Vec4::from((self.vertices[tri_idx[2]].normal, 0.0));
+        let (vertices, aa_bb) = match &posed {
+            Some(posed) => (posed.as_slice(), Self::get_vertex_min_max(posed)),
+            None => (self.vertices.as_slice(), self.aa_bb.unwrap()),
+        };
 
-                let norm0 = inv_transpose * Vec4::from((self.vertices[tri_idx[0]].normal, 0.0));
-                let norm1 = inv_transpose * Vec4::from((self.vertices[tri_idx[1]].normal, 0.0));
-                let norm2 = inv_transpose * Vec4::from((self.vertices[tri_idx[2]].normal, 0.0));
+        if Self::cull_mesh_frustum(aa_bb, mvp) {
+            let bounds = Self::screen_bounds(aa_bb, &mvp);
+            if let Some((min, max, depth)) = bounds {
+                if slice_buff.is_occluded(min, max, depth) {
+                    return;
+                }
+            }
 
-                let mut copy0 = self.vertices[tri_idx[0]];
-                let mut copy1 = self.vertices[tri_idx[1]];
-                let mut copy2 = self.vertices[tri_idx[2]];
+            let lod = self.select_lod(bounds.map(|(min, max, _)| (max - min).max_element()));
 
-                copy0.position = clip0;
-                copy1.position = clip1;
-                copy2.position = clip2;
+            let inv_transpose = model.inverse().transpose();
 
-                copy0.normal = norm0.xyz().normalize();
-                copy1.normal = norm1.xyz().normalize();
-                copy2.normal = norm2.xyz().normalize();
+            //Get Texture, rendering happens once for the whole frame after submitting
+            let texture = self.texture.and_then(|tex| {
+                let manager = TEXTURE_MANAGER.read().unwrap();
+                manager.get_texture(&tex).cloned()
+            });
 
-                let triangle = Triangle::new([copy0, copy1, copy2]);
+            let draw = DrawState {
+                render_mode: self.render_mode,
+                texture,
+                material: self.material,
+                wireframe: self.wireframe,
+                id: slice_buff.draw_id.map(|id| DrawId {
+                    lod: lod as u32,
+                    ..id
+                }),
+                lod,
+            };
 
-                //Collect all triangles here and pass them as a mesh to FS
-                match triangle.render_triangle() {
-                    crate::triangle::ClipResult::Clipped => { /* Fuck all */ }
-                    crate::triangle::ClipResult::One(tri) => {
-                        triangles_to_render.push(tri);
-                    }
-                    crate::triangle::ClipResult::Two(tri) => {
-                        triangles_to_render.push(tri.0);
-                        triangles_to_render.push(tri.1);
-                    }
+            match self.topology {
+                Topology::TriangleList => {}
+                Topology::LineList | Topology::LineStrip => {
+                    let mut lines =
+                        self.process_lines(vertices, &mvp, &inv_transpose, camera.reversed_z);
+                    slice_buff.submit_lines(&mut lines, draw);
+                    return;
+                }
+                Topology::PointList => {
+                    let mut points = self.process_points(vertices, &mvp, &inv_transpose);
+                    slice_buff.submit_points(&mut points, draw);
+                    return;
                 }
             }
 
-            slice_buff.external_aa_bb_comparison(triangles_to_render.as_mut_slice());
+            //Transform, cull and clip chunks of the index buffer in parallel,
+            //fold/reduce keeps the triangles in index buffer order
+            let mut triangles_to_render: Vec<Triangle> = self
+                .lod_indices(lod)
+                .par_chunks_exact(3)
+                .enumerate()
+                .fold(Vec::new, |mut triangles, (primitive, tri_idx)| {
+                    match self.process_triangle(
+                        vertices,
+                        tri_idx,
+                        primitive as u32,
+                        &mv,
+                        &mvp,
+                        &inv_transpose,
+                        camera.reversed_z,
+                    ) {
+                        ClipResult::Clipped => { /* Fuck all */ }
+                        ClipResult::One(tri) => {
+                            triangles.push(tri);
+                        }
+                        ClipResult::Two(tri) => {
+                            triangles.push(tri.0);
+                            triangles.push(tri.1);
+                        }
+                    }
+                    triangles
+                })
+                .reduce(Vec::new, |mut a, mut b| {
+                    a.append(&mut b);
+                    a
+                });
 
-            //Get Texture and render
-            if let Some(tex) = self.texture {
-                let manager = TEXTURE_MANAGER.read().unwrap();
-                let texture = manager.get_texture(&tex);
-                slice_buff.extern_render(
-                    &triangles_to_render,
-                    camera,
-                    &self.render_mode,
-                    texture,
-                    &self.material,
-                );
-            } else {
-                slice_buff.extern_render(
-                    &triangles_to_render,
-                    camera,
-                    &self.render_mode,
-                    None,
-                    &self.material,
-                );
+            if self.sort_front_to_back {
+                let compare = slice_buff.depth_compare;
+                triangles_to_render.par_sort_by(|a, b| {
+                    compare.front_to_back(a.nearest_depth(compare), b.nearest_depth(compare))
+                });
             }
 
-            slice_buff.clear_tiles();
+            slice_buff.submit(&mut triangles_to_render, draw);
         }
     }
 
-    pub fn gltf_load_mesh(primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Self {
+    //Loads a glTF primitive, missing attributes get defaulted or generated and reported in warnings.
+    //Errors mean the primitive can't be rendered at all
+    pub fn gltf_load_mesh(
+        primitive: &gltf::Primitive,
+        buffers: &[gltf::buffer::Data],
+        options: &LoadOptions,
+        warnings: &mut Vec<String>,
+    ) -> Result<Self, String> {
         let mut positions: Vec<Vec3> = Vec::new();
         let mut tex_coords: Vec<Vec2> = Vec::new();
+        let mut tex_coords1: Vec<Vec2> = Vec::new();
         let mut normals: Vec<Vec3> = Vec::new();
+        let mut colors: Vec<Vec4> = Vec::new();
+        let mut joints: Vec<[u16; 4]> = Vec::new();
+        let mut weights: Vec<Vec4> = Vec::new();
+        let mut morph_targets: Vec<MorphTarget> = Vec::new();
         let mut indices = vec![];
 
         let mut mesh_result = VertexMesh::default();
         let mut mat_result = Material::default();
 
-        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &**data));
         if let Some(indices_reader) = reader.read_indices() {
             indices_reader.into_u32().for_each(|i| indices.push(i));
         }
         if let Some(positions_reader) = reader.read_positions() {
             positions_reader.for_each(|p| positions.push(Vec3::new(p[0], p[1], p[2])));
+        } else {
+            return Err("no POSITION attribute".to_string());
         }
         if let Some(normals_reader) = reader.read_normals() {
             normals_reader.for_each(|n| normals.push(Vec3::new(n[0], n[1], n[2])));
@@ -319,12 +775,123 @@ impl VertexMesh {
                 .into_f32()
                 .for_each(|tc| tex_coords.push(Vec2::new(tc[0], tc[1])));
         }
+        if let Some(tex_coord_reader) = reader.read_tex_coords(1) {
+            tex_coord_reader
+                .into_f32()
+                .for_each(|tc| tex_coords1.push(Vec2::new(tc[0], tc[1])));
+        }
+        if let Some(colors_reader) = reader.read_colors(0) {
+            //Normalizes u8/u16 and fills in alpha for RGB
+            colors_reader
+                .into_rgba_f32()
+                .for_each(|c| colors.push(Vec4::from(c)));
+        }
+        if let Some(joints_reader) = reader.read_joints(0) {
+            joints_reader.into_u16().for_each(|j| joints.push(j));
+        }
+        if let Some(weights_reader) = reader.read_weights(0) {
+            weights_reader
+                .into_f32()
+                .for_each(|w| weights.push(Vec4::from(w)));
+        }
+        for (target_positions, target_normals, _) in reader.read_morph_targets() {
+            let mut target = MorphTarget::default();
+            if let Some(target_positions) = target_positions {
+                target.positions = target_positions.map(Vec3::from).collect();
+            }
+            if let Some(target_normals) = target_normals {
+                target.normals = target_normals.map(Vec3::from).collect();
+            }
+            morph_targets.push(target);
+        }
+
+        //Non-indexed primitives draw the vertices in order
+        if reader.read_indices().is_none() {
+            indices = (0..positions.len() as u32).collect();
+        }
+
+        if let Some(idx) = indices.iter().find(|idx| **idx as usize >= positions.len()) {
+            return Err(format!(
+                "index {} out of range for {} vertices",
+                idx,
+                positions.len()
+            ));
+        }
+
+        let (topology, mut indices) = Self::gltf_topology(primitive.mode(), indices);
+        mesh_result.topology = topology;
+
+        if tex_coords.len() != positions.len() {
+            warnings.push("no TEXCOORD_0, using (0, 0)".to_string());
+            tex_coords = vec![Vec2::ZERO; positions.len()];
+        }
+
+        //Without a second set, anything reading it gets the first one
+        let has_uv1 = tex_coords1.len() == positions.len();
+        if !has_uv1 {
+            tex_coords1 = tex_coords.clone();
+        }
+
+        if joints.len() != weights.len() || (!joints.is_empty() && joints.len() != positions.len())
+        {
+            warnings
+                .push("JOINTS_0 and WEIGHTS_0 don't match the vertices, not skinning".to_string());
+            joints.clear();
+            weights.clear();
+        }
+
+        //Weights have to add up to one or the vertex gets scaled
+        for weight in weights.iter_mut() {
+            let sum = weight.x + weight.y + weight.z + weight.w;
+            if sum > 0.0 {
+                *weight /= sum;
+            }
+        }
+
+        //Missing deltas don't move anything
+        for target in morph_targets.iter_mut() {
+            if target.positions.len() != positions.len() {
+                target.positions = vec![Vec3::ZERO; positions.len()];
+            }
+            if target.normals.len() != positions.len() {
+                target.normals = vec![Vec3::ZERO; positions.len()];
+            }
+        }
+
+        if options.random_colors {
+            let mut rng = rand::thread_rng();
+            let color = Vec4::new(rng.gen(), rng.gen(), rng.gen(), 1.0);
+            colors = vec![color; positions.len()];
+        } else if colors.len() != positions.len() {
+            colors = vec![Vec4::ONE; positions.len()];
+        }
+
+        if normals.len() != positions.len() {
+            if topology != Topology::TriangleList {
+                //Lines and points don't get lit, any normal works
+                normals = vec![Vec3::Z; positions.len()];
+            } else if options.normals == NormalGeneration::Flat {
+                warnings.push("no NORMAL, generating flat normals".to_string());
+                positions = Self::unweld(&positions, &indices);
+                tex_coords = Self::unweld(&tex_coords, &indices);
+                tex_coords1 = Self::unweld(&tex_coords1, &indices);
+                colors = Self::unweld(&colors, &indices);
+                if !joints.is_empty() {
+                    joints = Self::unweld(&joints, &indices);
+                    weights = Self::unweld(&weights, &indices);
+                }
+                for target in morph_targets.iter_mut() {
+                    target.positions = Self::unweld(&target.positions, &indices);
+                    target.normals = Self::unweld(&target.normals, &indices);
+                }
+                indices = (0..positions.len() as u32).collect();
+                normals = Self::flat_normals(&positions);
+            } else {
+                warnings.push("no NORMAL, generating smooth normals".to_string());
+                normals = Self::smooth_normals(&positions, &indices);
+            }
+        }
 
-        let mut rng = rand::thread_rng();
-        let colors: Vec<Vec3> = positions
-            .iter()
-            .map(|_| Vec3::new(rng.gen(), rng.gen(), rng.gen()))
-            .collect();
         println!("Num indices: {:?}", indices.len());
         println!("Tex_coords: {:?}", tex_coords.len());
         println!("Positions: {:?}", positions.len());
@@ -337,22 +904,177 @@ impl VertexMesh {
         //    .metallic_roughness_texture()
         //    .unwrap();
 
-        let mut base_col = -1;
-        if let Some(mat) = base_col_option {
-            base_col = mat.texture().source().index() as i32;
+        if let Some(info) = base_col_option {
+            mat_result.base_tex = Self::gltf_texture_slot(&info, has_uv1, warnings);
         }
 
         mat_result.base_color = Vec4::from(base_col_factor);
-        mat_result.base_tex_idx = base_col;
 
         mesh_result.material = mat_result;
-        mesh_result.add_section_from_buffers(&indices, &positions, &normals, &colors, &tex_coords);
+        mesh_result.joints = joints;
+        mesh_result.weights = weights;
+        mesh_result.morph_targets = morph_targets;
+        mesh_result.add_section_from_buffers(
+            &indices,
+            &positions,
+            &normals,
+            &colors,
+            &tex_coords,
+            &tex_coords1,
+        );
 
         let aa_bb = Self::get_vertex_min_max(&mesh_result.vertices);
 
         mesh_result.aa_bb = Some(aa_bb);
 
-        mesh_result
+        Ok(mesh_result)
+    }
+
+    //Which image and UV set a texture reads, KHR_texture_transform can override the set
+    fn gltf_texture_slot(
+        info: &gltf::texture::Info,
+        has_uv1: bool,
+        warnings: &mut Vec<String>,
+    ) -> TextureSlot {
+        let transform = info.texture_transform();
+
+        let mut tex_coord = transform
+            .as_ref()
+            .and_then(|transform| transform.tex_coord())
+            .unwrap_or(info.tex_coord());
+
+        if tex_coord > 1 {
+            warnings.push(format!(
+                "TEXCOORD_{tex_coord} isn't supported, using TEXCOORD_0"
+            ));
+            tex_coord = 0;
+        } else if tex_coord == 1 && !has_uv1 {
+            warnings
+                .push("texture uses TEXCOORD_1 but there is none, using TEXCOORD_0".to_string());
+        }
+
+        TextureSlot {
+            tex_idx: info.texture().source().index() as i32,
+            tex_coord,
+            transform: transform.map(|transform| UvTransform {
+                offset: Vec2::from(transform.offset()),
+                rotation: transform.rotation(),
+                scale: Vec2::from(transform.scale()),
+            }),
+        }
+    }
+
+    //One vertex per index, so no two triangles share a vertex
+    pub fn unweld<T: Copy>(attribute: &[T], indices: &[u32]) -> Vec<T> {
+        indices.iter().map(|idx| attribute[*idx as usize]).collect()
+    }
+
+    //Expects unwelded triangles, every vertex gets the normal of its face
+    pub fn flat_normals(positions: &[Vec3]) -> Vec<Vec3> {
+        positions
+            .chunks_exact(3)
+            .flat_map(|tri| {
+                let normal = (tri[1] - tri[0])
+                    .cross(tri[2] - tri[0])
+                    .try_normalize()
+                    .unwrap_or(Vec3::Z);
+                [normal; 3]
+            })
+            .collect()
+    }
+
+    //The cross product's length is twice the area, so bigger faces weigh more
+    pub fn smooth_normals(positions: &[Vec3], indices: &[u32]) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::ZERO; positions.len()];
+
+        for tri in indices.chunks_exact(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|idx| idx as usize);
+            let normal = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+
+            normals[a] += normal;
+            normals[b] += normal;
+            normals[c] += normal;
+        }
+
+        normals
+            .into_iter()
+            .map(|normal| normal.try_normalize().unwrap_or(Vec3::Z))
+            .collect()
+    }
+
+    //Maps a glTF mode to a topology we can render, strips, fans and loops get expanded
+    fn gltf_topology(mode: gltf::mesh::Mode, indices: Vec<u32>) -> (Topology, Vec<u32>) {
+        use gltf::mesh::Mode;
+
+        match mode {
+            Mode::Points => (Topology::PointList, indices),
+            Mode::Lines => (Topology::LineList, indices),
+            Mode::LineStrip => (Topology::LineStrip, indices),
+            Mode::LineLoop => {
+                let mut indices = indices;
+                if indices.len() > 2 {
+                    indices.push(indices[0]);
+                }
+                (Topology::LineStrip, indices)
+            }
+            Mode::Triangles => (Topology::TriangleList, indices),
+            Mode::TriangleStrip => (Topology::TriangleList, Self::strip_to_list(&indices)),
+            Mode::TriangleFan => (Topology::TriangleList, Self::fan_to_list(&indices)),
+        }
+    }
+
+    //Every odd triangle of a strip swaps its first two vertices to keep the winding
+    fn strip_to_list(indices: &[u32]) -> Vec<u32> {
+        indices
+            .windows(3)
+            .enumerate()
+            .map(|(i, w)| {
+                if i % 2 == 1 {
+                    [w[1], w[0], w[2]]
+                } else {
+                    [w[0], w[1], w[2]]
+                }
+            })
+            .filter(|tri| !Self::is_degenerate(tri))
+            .flatten()
+            .collect()
+    }
+
+    //Every triangle of a fan shares the first vertex
+    fn fan_to_list(indices: &[u32]) -> Vec<u32> {
+        if indices.len() < 3 {
+            return Vec::new();
+        }
+
+        indices[1..]
+            .windows(2)
+            .map(|w| [w[0], w[1], indices[0]])
+            .filter(|tri| !Self::is_degenerate(tri))
+            .flatten()
+            .collect()
+    }
+
+    //Strips use triangles with repeated indices to stitch parts together
+    fn is_degenerate(tri: &[u32; 3]) -> bool {
+        tri[0] == tri[1] || tri[1] == tri[2] || tri[0] == tri[2]
+    }
+
+    //For the loaders that build their own attribute arrays, the second UV set copies the first
+    pub fn from_buffers(
+        indices: &[u32],
+        positions: &[Vec3],
+        normals: &[Vec3],
+        colors: &[Vec4],
+        tex_coords: &[Vec2],
+        material: Material,
+    ) -> Self {
+        let mut mesh = Self {
+            material,
+            ..Default::default()
+        };
+        mesh.add_section_from_buffers(indices, positions, normals, colors, tex_coords, tex_coords);
+        mesh.aa_bb = Some(Self::get_vertex_min_max(&mesh.vertices));
+        mesh
     }
 
     pub fn add_ref_tex(&mut self, texture: i32) {
@@ -364,8 +1086,9 @@ impl VertexMesh {
         indices: &[u32],
         positions: &[Vec3],
         normals: &[Vec3],
-        colors: &[Vec3],
+        colors: &[Vec4],
         tex_coords: &[Vec2],
+        tex_coords1: &[Vec2],
     ) {
         for i in 0..positions.len() {
             let v = Vertex {
@@ -373,6 +1096,7 @@ impl VertexMesh {
                 normal: normals[i],
                 color: colors[i],
                 uv: tex_coords[i],
+                uv1: tex_coords1[i],
             };
             self.vertices.push(v);
         }
@@ -380,3 +1104,126 @@ impl VertexMesh {
         self.indices.extend_from_slice(indices);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec2;
+
+    //n x n quads with a bump in the middle, so there's something to simplify
+    fn grid(n: u32) -> VertexMesh {
+        let vertices: Vec<Vertex> = (0..=n)
+            .flat_map(|z| (0..=n).map(move |x| (x, z)))
+            .map(|(x, z)| {
+                let (x, z) = (x as f32 / n as f32, z as f32 / n as f32);
+                let y = (x * std::f32::consts::PI).sin() * (z * std::f32::consts::PI).sin();
+                Vertex::new(Vec4::new(x, y, z, 1.0), Vec3::Y, Vec4::ONE, Vec2::ZERO)
+            })
+            .collect();
+
+        let mut indices = Vec::new();
+        for z in 0..n {
+            for x in 0..n {
+                let i = z * (n + 1) + x;
+                indices.extend([i, i + n + 1, i + 1, i + 1, i + n + 1, i + n + 2]);
+            }
+        }
+
+        VertexMesh::new(&vertices, &indices, None, None)
+    }
+
+    #[test]
+    fn lods_halve_the_triangles() {
+        let mut mesh = grid(32);
+        mesh.generate_lods(3);
+        assert_eq!(mesh.lods.len(), 3);
+
+        let mut triangles = mesh.indices.len() / 3;
+        for lod in &mesh.lods {
+            assert!(lod.len() / 3 <= triangles / 2);
+            assert!(lod.iter().all(|v| (*v as usize) < mesh.vertices.len()));
+            triangles = lod.len() / 3;
+        }
+
+        //Lines have nothing to simplify
+        mesh.topology = Topology::LineList;
+        mesh.generate_lods(3);
+        assert!(mesh.lods.is_empty());
+    }
+
+    #[test]
+    fn lod_selection_has_hysteresis() {
+        let mut mesh = grid(32);
+        mesh.generate_lods(3);
+
+        assert_eq!(mesh.select_lod(Some(1000.0)), 0);
+        assert_eq!(mesh.select_lod(None), 0);
+
+        //Just under the first switching size isn't far enough to switch
+        let switch = LOD_FULL_DETAIL_SIZE;
+        assert_eq!(mesh.select_lod(Some(switch * 0.95)), 0);
+        assert_eq!(mesh.select_lod(Some(switch * 0.8)), 1);
+
+        //And just over it isn't far enough to switch back
+        assert_eq!(mesh.select_lod(Some(switch * 1.05)), 1);
+        assert_eq!(mesh.select_lod(Some(switch * 1.2)), 0);
+
+        //Tiny meshes get the coarsest level there is
+        assert_eq!(mesh.select_lod(Some(1.0)), 3);
+    }
+
+    #[test]
+    fn vertices_blend_their_joints_by_weight() {
+        use crate::animation::{Node, Skin};
+        use crate::transform::Transform;
+        use glam::Quat;
+
+        //An arm: the elbow sits one up from the shoulder in the bind pose
+        let node = |name: &str, parent, translation| Node {
+            name: name.to_string(),
+            parent,
+            transform: Transform::from_translation(translation),
+            weights: Vec::new(),
+        };
+        let mut nodes = vec![
+            node("Shoulder", None, Vec3::ZERO),
+            node("Elbow", Some(0), Vec3::Y),
+        ];
+        let skin = Skin {
+            joints: vec![0, 1],
+            inverse_bind: vec![Mat4::IDENTITY, Mat4::from_translation(Vec3::NEG_Y)],
+        };
+
+        let vertex = Vertex::new(
+            Vec4::new(0.0, 2.0, 0.0, 1.0),
+            Vec3::X,
+            Vec4::ONE,
+            Vec2::ZERO,
+        );
+        let mut mesh = VertexMesh::new(&[vertex], &[0, 0, 0], None, None);
+        mesh.joints = vec![[0, 1, 0, 0]];
+        mesh.weights = vec![Vec4::new(0.25, 0.75, 0.0, 0.0)];
+
+        //The bind pose leaves it where it is
+        let world = Node::world_matrices(&nodes, &[0, 1]);
+        let skinned = mesh.skin_vertices(&mesh.vertices, &skin.joint_matrices(&world));
+        assert!(skinned[0].position.abs_diff_eq(vertex.position, 1e-6));
+
+        //Shoulder moved over by 2, that alone puts it at (2, 2).
+        //The elbow bends 90 degrees, putting it at (1, 1)
+        nodes[0].transform.translation = Vec3::X * 2.0;
+        nodes[1].transform.rotation = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+
+        let world = Node::world_matrices(&nodes, &[0, 1]);
+        let skinned = mesh.skin_vertices(&mesh.vertices, &skin.joint_matrices(&world));
+        let expected = Vec3::new(2.0, 2.0, 0.0) * 0.25 + Vec3::new(1.0, 1.0, 0.0) * 0.75;
+        assert!(
+            skinned[0].position.abs_diff_eq(expected.extend(1.0), 1e-5),
+            "{}",
+            skinned[0].position
+        );
+        assert!(skinned[0]
+            .normal
+            .abs_diff_eq(Vec3::new(0.25, 0.75, 0.0), 1e-5));
+    }
+}
@@ -1,13 +1,80 @@
+use glam::{Mat4, Quat, Vec3, Vec4};
+use rayon::prelude::{IntoParallelRefMutIterator, ParallelIterator};
+
 use crate::{
-    camera::Camera, mesh::VertexMesh, sliced_buffer::SlicedBuffers, tex_manager::TEXTURE_MANAGER,
+    animation::{AnimationClip, Channel, Interpolation, Node, Playback, Property, Skin},
+    camera::Camera,
+    mesh::{RenderMode, VertexMesh, Wireframe},
+    obj_loader, ply_loader,
+    sliced_buffer::SlicedBuffers,
+    stl_loader,
+    tex_manager::TEXTURE_MANAGER,
     transform::Transform,
 };
-use gltf::{self, buffer::Data, Gltf, Node};
+use gltf::{self, animation::util::ReadOutputs, buffer::Data, Document};
+
+#[derive(Debug)]
+pub enum LoadError {
+    Gltf(gltf::Error),
+    Io(std::io::Error),
+    Parse(String),             //malformed OBJ, MTL, STL or PLY
+    UnsupportedFormat(String), //file extension no importer handles
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Gltf(err) => write!(f, "glTF error: {err}"),
+            LoadError::Io(err) => write!(f, "IO error: {err}"),
+            LoadError::Parse(err) => write!(f, "Parse error: {err}"),
+            LoadError::UnsupportedFormat(ext) => write!(f, "Unsupported format: {ext}"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<gltf::Error> for LoadError {
+    fn from(err: gltf::Error) -> Self {
+        LoadError::Gltf(err)
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(err: std::io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+//How normals get generated for primitives that don't have any
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum NormalGeneration {
+    Flat, //every triangle gets its own vertices and face normal
+    #[default]
+    Smooth, //area weighted average of the faces around a vertex
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoadOptions {
+    pub normals: NormalGeneration,
+    pub random_colors: bool, //debug view, one random color per primitive instead of COLOR_0
+}
 
 pub struct Model {
     pub meshes: Vec<VertexMesh>,
     pub transform: Transform,
-    textures: Vec<i32>, //all texture indices of models
+    pub occluder: bool, //rendered first so everything else gets Hi-Z culled against it
+    pub lod_levels: usize, //asked for in generate_lods, meshes can end up with fewer
+    pub warnings: Vec<String>, //problems with primitives that got fixed up or skipped while loading
+    pub sources: Vec<String>, //files the model was loaded from, textures aside
+    pub nodes: Vec<Node>, //every glTF node, indexed like the document
+    pub skins: Vec<Skin>,
+    pub animations: Vec<AnimationClip>,
+    pub playback: Playback,
+    textures: Vec<i32>,             //all texture indices of models
+    node_order: Vec<usize>,         //parents before children
+    node_world: Vec<Mat4>,          //model space matrix of every node
+    joint_matrices: Vec<Vec<Mat4>>, //per skin, updated every frame
 }
 
 impl Model {
@@ -15,43 +82,236 @@ impl Model {
         Self {
             meshes: Vec::new(),
             transform: Transform::IDENTITY,
+            occluder: false,
+            lod_levels: 0,
+            warnings: Vec::new(),
+            sources: Vec::new(),
+            nodes: Vec::new(),
+            skins: Vec::new(),
+            animations: Vec::new(),
+            playback: Playback::default(),
             textures: Vec::new(),
+            node_order: Vec::new(),
+            node_world: Vec::new(),
+            joint_matrices: Vec::new(),
         }
     }
 
-    pub fn from_filepath(filepath: &str) -> Self {
+    //Picks the importer from the file extension
+    pub fn from_file(filepath: &str) -> Result<Self, LoadError> {
+        Self::from_file_with_options(filepath, &LoadOptions::default())
+    }
+
+    pub fn from_file_with_options(
+        filepath: &str,
+        options: &LoadOptions,
+    ) -> Result<Self, LoadError> {
+        let extension = std::path::Path::new(filepath)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("")
+            .to_lowercase();
+
+        let (mesh, warnings) = match extension.as_str() {
+            "gltf" | "glb" => return Self::from_filepath_with_options(filepath, options),
+            "obj" => return Self::from_obj_with_options(filepath, options),
+            "stl" => {
+                let stl = stl_loader::load(filepath, options)?;
+                (stl.mesh, stl.warnings)
+            }
+            "ply" => {
+                let ply = ply_loader::load(filepath, options)?;
+                (ply.mesh, ply.warnings)
+            }
+            _ => return Err(LoadError::UnsupportedFormat(extension)),
+        };
+
+        for warning in &warnings {
+            println!("{filepath}: {warning}");
+        }
+
+        let mut model = Self::from_mesh(mesh, Transform::IDENTITY);
+        model.warnings = warnings;
+        model.sources = vec![filepath.to_string()];
+        Ok(model)
+    }
+
+    //.gltf with external or embedded (data URI) buffers and images, or .glb
+    pub fn from_filepath(filepath: &str) -> Result<Self, LoadError> {
+        Self::from_filepath_with_options(filepath, &LoadOptions::default())
+    }
+
+    pub fn from_filepath_with_options(
+        filepath: &str,
+        options: &LoadOptions,
+    ) -> Result<Self, LoadError> {
+        let (document, buffers, images) = gltf::import(filepath)?;
+        let sources = Self::gltf_sources(filepath, &document);
+
+        let mut model = Self::from_gltf(filepath, document, buffers, images, options)?;
+        model.sources = sources;
+        Ok(model)
+    }
+
+    //The file itself plus the external buffers and images it references
+    fn gltf_sources(filepath: &str, document: &Document) -> Vec<String> {
+        let dir = std::path::Path::new(filepath)
+            .parent()
+            .unwrap_or(std::path::Path::new(""));
+
+        let buffer_uris = document
+            .buffers()
+            .filter_map(|buffer| match buffer.source() {
+                gltf::buffer::Source::Uri(uri) => Some(uri),
+                gltf::buffer::Source::Bin => None,
+            });
+        let image_uris = document.images().filter_map(|image| match image.source() {
+            gltf::image::Source::Uri { uri, .. } => Some(uri),
+            gltf::image::Source::View { .. } => None,
+        });
+
+        std::iter::once(filepath.to_string())
+            .chain(
+                buffer_uris
+                    .chain(image_uris)
+                    .filter(|uri| !uri.starts_with("data:"))
+                    .map(|uri| dir.join(uri).to_string_lossy().into_owned()),
+            )
+            .collect()
+    }
+
+    //A .glb or a .gltf that embeds everything, external files can't be resolved from memory
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, LoadError> {
+        Self::from_slice_with_options(bytes, &LoadOptions::default())
+    }
+
+    pub fn from_slice_with_options(bytes: &[u8], options: &LoadOptions) -> Result<Self, LoadError> {
+        let (document, buffers, images) = gltf::import_slice(bytes)?;
+        Self::from_gltf("<memory>", document, buffers, images, options)
+    }
+
+    //Wavefront OBJ with MTL materials, map_Kd paths are relative to the MTL
+    pub fn from_obj(filepath: &str) -> Result<Self, LoadError> {
+        Self::from_obj_with_options(filepath, &LoadOptions::default())
+    }
+
+    pub fn from_obj_with_options(filepath: &str, options: &LoadOptions) -> Result<Self, LoadError> {
+        let obj = obj_loader::load(filepath, options)?;
         let mut model = Self::new();
+        model.warnings = obj.warnings;
+        model.sources = std::iter::once(filepath.to_string())
+            .chain(obj.materials)
+            .collect();
 
-        let gltf = Gltf::open(filepath);
+        {
+            let mut manager = TEXTURE_MANAGER.write().unwrap();
+            for path in &obj.textures {
+                let tex = manager.load_or_white(path, &mut model.warnings);
+                model.textures.push(tex);
+            }
+        }
 
-        let (document, buffers, images) = gltf::import(filepath).unwrap();
-        assert_eq!(buffers.len(), document.buffers().count());
-        assert_eq!(images.len(), document.images().count());
+        for mut mesh in obj.meshes {
+            if mesh.material.base_tex.tex_idx != -1 {
+                mesh.texture = model
+                    .textures
+                    .get(mesh.material.base_tex.tex_idx as usize)
+                    .copied();
+            }
+            model.meshes.push(mesh);
+        }
+
+        for warning in &model.warnings {
+            println!("{filepath}: {warning}");
+        }
+
+        Ok(model)
+    }
+
+    //Source is only used to label the warnings
+    fn from_gltf(
+        source: &str,
+        document: Document,
+        buffers: Vec<Data>,
+        images: Vec<gltf::image::Data>,
+        options: &LoadOptions,
+    ) -> Result<Self, LoadError> {
+        let mut model = Self::new();
 
         {
             let mut manager = TEXTURE_MANAGER.write().unwrap();
-            model.textures = manager
-                .load_from_gltf_images(images)
-                .expect("Textures not found");
+            model.textures = manager.load_from_gltf_images(images, &mut model.warnings);
         }
 
+        model.load_nodes(&document);
+
         for scene in document.scenes() {
             for node in scene.nodes() {
-                Self::load_data_from_node(&mut model, &node, &buffers);
+                Self::load_data_from_node(&mut model, &node, &buffers, options);
             }
         }
 
-        model
+        model.load_skins(&document, &buffers);
+        model.load_animations(&document, &buffers);
+
+        //Place the meshes and skins before the first frame
+        model.pose();
+
+        for warning in &model.warnings {
+            println!("{source}: {warning}");
+        }
+
+        Ok(model)
     }
 
-    fn load_data_from_node(model: &mut Model, node: &Node, buffers: &Vec<Data>) {
+    fn load_data_from_node(
+        model: &mut Model,
+        node: &gltf::Node,
+        buffers: &[Data],
+        options: &LoadOptions,
+    ) {
+        model.node_order.push(node.index());
+
         //Load mesh if there's on in the node
         if let Some(mesh) = node.mesh() {
+            let mesh_name = mesh.name().unwrap_or("unnamed").to_string();
+
             for primitive in mesh.primitives() {
-                let mut my_mesh = VertexMesh::gltf_load_mesh(&primitive, buffers);
+                let mut warnings = Vec::new();
+                let result =
+                    VertexMesh::gltf_load_mesh(&primitive, buffers, options, &mut warnings);
+
+                let prefix = format!("Mesh {} primitive {}", mesh_name, primitive.index());
+                model.warnings.extend(
+                    warnings
+                        .iter()
+                        .map(|warning| format!("{prefix}: {warning}")),
+                );
+
+                let mut my_mesh = match result {
+                    Ok(my_mesh) => my_mesh,
+                    Err(err) => {
+                        model.warnings.push(format!("{prefix}: skipped, {err}"));
+                        continue;
+                    }
+                };
+
+                //Node weights fall back to the mesh's defaults, missing ones are zero
+                my_mesh.node = Some(node.index());
+                my_mesh.morph_weights = model.nodes[node.index()].weights.clone();
+                my_mesh
+                    .morph_weights
+                    .resize(my_mesh.morph_targets.len(), 0.0);
 
-                if my_mesh.material.base_tex_idx != -1 {
-                    my_mesh.texture = Some(model.textures[my_mesh.material.base_tex_idx as usize]);
+                if !my_mesh.joints.is_empty() {
+                    my_mesh.skin = node.skin().map(|skin| skin.index());
+                }
+
+                if my_mesh.material.base_tex.tex_idx != -1 {
+                    my_mesh.texture = model
+                        .textures
+                        .get(my_mesh.material.base_tex.tex_idx as usize)
+                        .copied();
                 }
 
                 model.meshes.push(my_mesh);
@@ -60,7 +320,240 @@ impl Model {
 
         //check for children and load meshes from their nodes
         for child in node.children() {
-            Self::load_data_from_node(model, &child, buffers);
+            Self::load_data_from_node(model, &child, buffers, options);
+        }
+    }
+
+    fn load_nodes(&mut self, document: &Document) {
+        self.nodes = document
+            .nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+
+                Node {
+                    name: node.name().unwrap_or("unnamed").to_string(),
+                    parent: None,
+                    transform: Transform {
+                        translation: Vec3::from(translation),
+                        rotation: Quat::from_array(rotation),
+                        scale: Vec3::from(scale),
+                    },
+                    weights: node
+                        .weights()
+                        .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+                        .map(|weights| weights.to_vec())
+                        .unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        for node in document.nodes() {
+            for child in node.children() {
+                self.nodes[child.index()].parent = Some(node.index());
+            }
+        }
+    }
+
+    fn load_skins(&mut self, document: &Document, buffers: &[Data]) {
+        for skin in document.skins() {
+            let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+
+            let reader = skin.reader(|buffer| buffers.get(buffer.index()).map(|data| &**data));
+            let mut inverse_bind: Vec<Mat4> = reader
+                .read_inverse_bind_matrices()
+                .map(|matrices| {
+                    matrices
+                        .map(|matrix| Mat4::from_cols_array_2d(&matrix))
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            //No matrices means identity
+            if inverse_bind.len() != joints.len() {
+                if !inverse_bind.is_empty() {
+                    self.warnings.push(format!(
+                        "Skin {}: {} inverse bind matrices for {} joints",
+                        skin.index(),
+                        inverse_bind.len(),
+                        joints.len()
+                    ));
+                }
+                inverse_bind.resize(joints.len(), Mat4::IDENTITY);
+            }
+
+            self.skins.push(Skin {
+                joints,
+                inverse_bind,
+            });
+        }
+    }
+
+    fn load_animations(&mut self, document: &Document, buffers: &[Data]) {
+        for animation in document.animations() {
+            let name = animation
+                .name()
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("Animation {}", animation.index()));
+            let mut channels = Vec::new();
+
+            for (channel_idx, channel) in animation.channels().enumerate() {
+                let reader =
+                    channel.reader(|buffer| buffers.get(buffer.index()).map(|data| &**data));
+
+                let times: Vec<f32> = match reader.read_inputs() {
+                    Some(inputs) => inputs.collect(),
+                    None => Vec::new(),
+                };
+
+                let (property, values): (Property, Vec<f32>) = match reader.read_outputs() {
+                    Some(ReadOutputs::Translations(translations)) => {
+                        (Property::Translation, translations.flatten().collect())
+                    }
+                    Some(ReadOutputs::Rotations(rotations)) => {
+                        (Property::Rotation, rotations.into_f32().flatten().collect())
+                    }
+                    Some(ReadOutputs::Scales(scales)) => {
+                        (Property::Scale, scales.flatten().collect())
+                    }
+                    Some(ReadOutputs::MorphTargetWeights(weights)) => {
+                        (Property::Weights, weights.into_f32().collect())
+                    }
+                    None => (Property::Translation, Vec::new()),
+                };
+
+                let interpolation = match channel.sampler().interpolation() {
+                    gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                    gltf::animation::Interpolation::Step => Interpolation::Step,
+                    gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+                };
+
+                let values_per_key = match interpolation {
+                    Interpolation::CubicSpline => 3,
+                    _ => 1,
+                };
+
+                //Weights have one value per morph target, it's whatever is left per key
+                let width = match property {
+                    Property::Translation | Property::Scale => 3,
+                    Property::Rotation => 4,
+                    Property::Weights => values.len() / (times.len() * values_per_key).max(1),
+                };
+
+                if times.is_empty()
+                    || width == 0
+                    || values.len() != times.len() * values_per_key * width
+                {
+                    self.warnings.push(format!(
+                        "{name} channel {}: skipped, {} keyframes with {} values",
+                        channel_idx,
+                        times.len(),
+                        values.len()
+                    ));
+                    continue;
+                }
+
+                channels.push(Channel {
+                    node: channel.target().node().index(),
+                    property,
+                    interpolation,
+                    times,
+                    values,
+                    width,
+                });
+            }
+
+            self.animations.push(AnimationClip::new(name, channels));
+        }
+    }
+
+    //Advances the current clip, dt is in seconds
+    pub fn update(&mut self, dt: f32) {
+        let Some(clip) = self.animations.get(self.playback.clip) else {
+            return;
+        };
+
+        if self.playback.playing {
+            self.playback.time += dt * self.playback.speed;
+
+            if self.playback.looping && clip.duration > 0.0 {
+                self.playback.time = self.playback.time.rem_euclid(clip.duration);
+            } else if self.playback.time >= clip.duration {
+                //Stays on the last frame
+                self.playback.time = clip.duration;
+                self.playback.playing = false;
+            }
+        }
+
+        self.pose();
+    }
+
+    //Applies the current clip at the playback time to the nodes, meshes and skins
+    fn pose(&mut self) {
+        if let Some(clip) = self.animations.get(self.playback.clip) {
+            clip.apply(self.playback.time, &mut self.nodes);
+        }
+
+        for mesh in self.meshes.iter_mut() {
+            let weights = mesh.node.map(|node| &self.nodes[node].weights);
+            if let Some(weights) = weights.filter(|weights| !weights.is_empty()) {
+                mesh.morph_weights.clone_from(weights);
+                mesh.morph_weights.resize(mesh.morph_targets.len(), 0.0);
+            }
+        }
+
+        self.node_world = Node::world_matrices(&self.nodes, &self.node_order);
+        self.joint_matrices = self
+            .skins
+            .iter()
+            .map(|skin| skin.joint_matrices(&self.node_world))
+            .collect();
+    }
+
+    pub fn play(&mut self) {
+        //Playing a finished clip starts it over
+        let finished = self
+            .animations
+            .get(self.playback.clip)
+            .is_some_and(|clip| self.playback.time >= clip.duration);
+        if finished && !self.playback.looping {
+            self.playback.time = 0.0;
+        }
+
+        self.playback.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playback.playing = false;
+    }
+
+    pub fn toggle_playback(&mut self) {
+        if self.playback.playing {
+            self.pause();
+        } else {
+            self.play();
+        }
+    }
+
+    pub fn set_looping(&mut self, looping: bool) {
+        self.playback.looping = looping;
+    }
+
+    //Jumps to a time in seconds, clamped to the clip
+    pub fn seek(&mut self, time: f32) {
+        let duration = self
+            .animations
+            .get(self.playback.clip)
+            .map_or(0.0, |clip| clip.duration);
+
+        self.playback.time = time.clamp(0.0, duration);
+        self.pose();
+    }
+
+    pub fn next_clip(&mut self) {
+        if !self.animations.is_empty() {
+            self.playback.clip = (self.playback.clip + 1) % self.animations.len();
+            self.seek(0.0);
+            println!("Playing {}", self.animations[self.playback.clip].name);
         }
     }
 
@@ -71,12 +564,52 @@ impl Model {
         model
     }
 
+    fn joint_matrices(&self, mesh: &VertexMesh) -> Option<&[Mat4]> {
+        mesh.skin
+            .and_then(|skin| self.joint_matrices.get(skin))
+            .map(|matrices| matrices.as_slice())
+    }
+
+    //Model space matrix the mesh's own transform is relative to. Skinned meshes ignore
+    //their node, the joints already are in model space
+    pub fn mesh_parent(&self, mesh: &VertexMesh) -> Mat4 {
+        match (self.joint_matrices(mesh), mesh.node) {
+            (None, Some(node)) => self.node_world[node],
+            _ => Mat4::IDENTITY,
+        }
+    }
+
     pub fn render(&self, slice_buff: &mut SlicedBuffers, camera: &Camera) {
+        self.render_at(slice_buff, camera, &self.transform.local());
+    }
+
+    //World is the model's transform with its scene graph parents applied
+    pub fn render_at(&self, slice_buff: &mut SlicedBuffers, camera: &Camera, world: &Mat4) {
         for mesh in &self.meshes {
-            mesh.render(slice_buff, camera, &self.transform)
+            self.render_mesh(slice_buff, camera, world, mesh);
         }
     }
 
+    //One of this model's meshes, for drawing meshes of different models interleaved
+    pub fn render_mesh(
+        &self,
+        slice_buff: &mut SlicedBuffers,
+        camera: &Camera,
+        world: &Mat4,
+        mesh: &VertexMesh,
+    ) {
+        let parent = *world * self.mesh_parent(mesh);
+        mesh.render(slice_buff, camera, &parent, self.joint_matrices(mesh))
+    }
+
+    //Simplified versions of every mesh, in parallel since big models take a while
+    pub fn generate_lods(&mut self, levels: usize) {
+        self.lod_levels = levels;
+        self.meshes
+            .par_iter_mut()
+            .for_each(|mesh| mesh.generate_lods(levels));
+    }
+
     pub fn next_render_mode(&mut self) {
         for mesh in &mut self.meshes {
             mesh.next_render_mode();
@@ -88,6 +621,62 @@ impl Model {
             mesh.prev_render_mode();
         }
     }
+
+    //Carries what got changed at runtime over to a reloaded copy of the model
+    pub fn keep_state_from(&mut self, old: &Model) {
+        self.transform = old.transform;
+        self.occluder = old.occluder;
+
+        if let Some(old_mesh) = old.meshes.first() {
+            for mesh in &mut self.meshes {
+                mesh.render_mode = old_mesh.render_mode;
+                mesh.wireframe = old_mesh.wireframe;
+                mesh.sort_front_to_back = old_mesh.sort_front_to_back;
+            }
+        }
+
+        //The clips may have changed, only keep the playback if the clip is still there
+        if old.playback.clip < self.animations.len() {
+            self.playback.clip = old.playback.clip;
+            self.playback.speed = old.playback.speed;
+            self.playback.playing = old.playback.playing;
+            self.playback.looping = old.playback.looping;
+            self.seek(old.playback.time);
+        }
+    }
+
+    pub fn set_render_mode(&mut self, render_mode: RenderMode) {
+        for mesh in &mut self.meshes {
+            mesh.render_mode = render_mode;
+        }
+    }
+
+    pub fn set_base_color(&mut self, color: Vec4) {
+        for mesh in &mut self.meshes {
+            mesh.material.base_color = color;
+        }
+    }
+
+    //Replaces every mesh's texture, the model owns it from now on
+    pub fn set_texture(&mut self, tex: i32) {
+        self.textures.push(tex);
+        for mesh in &mut self.meshes {
+            mesh.texture = Some(tex);
+            mesh.material.base_tex.tex_idx = self.textures.len() as i32 - 1;
+        }
+    }
+
+    pub fn set_wireframe(&mut self, wireframe: Option<Wireframe>) {
+        for mesh in &mut self.meshes {
+            mesh.wireframe = wireframe;
+        }
+    }
+
+    pub fn set_front_to_back(&mut self, enabled: bool) {
+        for mesh in &mut self.meshes {
+            mesh.sort_front_to_back = enabled;
+        }
+    }
 }
 
 impl Drop for Model {
@@ -99,3 +688,221 @@ impl Drop for Model {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::Topology;
+
+    //Pads a chunk to 4 bytes like the spec wants, json with spaces and bin with zeros
+    fn pad(mut bytes: Vec<u8>, fill: u8) -> Vec<u8> {
+        bytes.resize(bytes.len().next_multiple_of(4), fill);
+        bytes
+    }
+
+    fn glb(json: &str, bin: &[u8]) -> Vec<u8> {
+        let json = pad(json.as_bytes().to_vec(), b' ');
+        let bin = pad(bin.to_vec(), 0);
+        let length = 12 + 8 + json.len() + 8 + bin.len();
+
+        let mut out = Vec::with_capacity(length);
+        out.extend_from_slice(b"glTF");
+        out.extend_from_slice(&2u32.to_le_bytes());
+        out.extend_from_slice(&(length as u32).to_le_bytes());
+
+        out.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"JSON");
+        out.extend_from_slice(&json);
+
+        out.extend_from_slice(&(bin.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"BIN\0");
+        out.extend_from_slice(&bin);
+        out
+    }
+
+    fn base64(bytes: &[u8]) -> String {
+        const TABLE: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut out = String::new();
+
+        for chunk in bytes.chunks(3) {
+            let b = [
+                chunk[0],
+                *chunk.get(1).unwrap_or(&0),
+                *chunk.get(2).unwrap_or(&0),
+            ];
+            let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    out.push(TABLE[(n >> (18 - i * 6)) as usize & 63] as char);
+                } else {
+                    out.push('=');
+                }
+            }
+        }
+        out
+    }
+
+    //One triangle, positions (36 bytes) then u16 indices (6 bytes)
+    fn triangle_bin() -> Vec<u8> {
+        let positions: [f32; 9] = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+        let mut bin: Vec<u8> = positions.iter().flat_map(|f| f.to_le_bytes()).collect();
+        bin.extend([0u16, 1, 2].iter().flat_map(|i| i.to_le_bytes()));
+        bin
+    }
+
+    fn triangle_json(buffer: &str) -> String {
+        format!(
+            r#"{{
+            "asset": {{ "version": "2.0" }},
+            "scene": 0,
+            "scenes": [{{ "nodes": [0] }}],
+            "nodes": [{{ "mesh": 0 }}],
+            "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "indices": 1 }}] }}],
+            "buffers": [{buffer}],
+            "bufferViews": [
+                {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                {{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }}
+            ],
+            "accessors": [
+                {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                   "min": [0, 0, 0], "max": [1, 1, 0] }},
+                {{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+            ]
+        }}"#
+        )
+    }
+
+    #[test]
+    fn loads_cube() {
+        let model = Model::from_filepath("resources/cube/Cube.gltf").unwrap();
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].indices.len(), 36);
+        assert!(model.meshes[0].texture.is_some());
+    }
+
+    #[test]
+    fn loads_caspia_wheel() {
+        //Buffer is a data URI, the images live in buffer views
+        let model = Model::from_filepath("resources/caspia/CaspiaWheel.gltf").unwrap();
+        assert!(!model.meshes.is_empty());
+        assert!(!model.textures.is_empty());
+    }
+
+    #[test]
+    #[ignore = "Sponza.bin isn't in the repository"]
+    fn loads_sponza() {
+        let model = Model::from_filepath("resources/sponza/Sponza.gltf").unwrap();
+        assert!(!model.meshes.is_empty());
+    }
+
+    #[test]
+    #[ignore = "the helmet's albedo and metal-roughness images aren't in the repository"]
+    fn loads_helmet() {
+        let model = Model::from_filepath("resources/helmet/Helmet.gltf").unwrap();
+        assert_eq!(model.meshes.len(), 1);
+    }
+
+    #[test]
+    fn loads_glb_slice() {
+        let bytes = glb(&triangle_json(r#"{ "byteLength": 42 }"#), &triangle_bin());
+        let model = Model::from_slice(&bytes).unwrap();
+
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].indices, vec![0, 1, 2]);
+        assert_eq!(model.meshes[0].topology, Topology::TriangleList);
+        //No TEXCOORD_0 and no NORMAL
+        assert_eq!(model.warnings.len(), 2);
+    }
+
+    #[test]
+    fn loads_glb_strip_without_indices() {
+        let positions: [f32; 12] = [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0];
+        let bin: Vec<u8> = positions.iter().flat_map(|f| f.to_le_bytes()).collect();
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "mode": 5 }] }],
+            "buffers": [{ "byteLength": 48 }],
+            "bufferViews": [{ "buffer": 0, "byteLength": 48 }],
+            "accessors": [{ "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3",
+                            "min": [0, 0, 0], "max": [1, 1, 0] }]
+        }"#;
+
+        let model = Model::from_slice(&glb(json, &bin)).unwrap();
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].indices.len(), 6);
+    }
+
+    #[test]
+    fn loads_glb_with_embedded_png() {
+        let mut png = Vec::new();
+        image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 0, 255]))
+            .write_to(
+                &mut std::io::Cursor::new(&mut png),
+                image::ImageOutputFormat::Png,
+            )
+            .unwrap();
+
+        let mut bin = triangle_bin();
+        let uvs: [f32; 6] = [0.0, 0.0, 1.0, 0.0, 0.0, 1.0];
+        bin.extend_from_slice(&[0, 0]); //align the uvs
+        bin.extend(uvs.iter().flat_map(|f| f.to_le_bytes()));
+        bin.extend_from_slice(&png);
+
+        let json = format!(
+            r#"{{
+            "asset": {{ "version": "2.0" }},
+            "scenes": [{{ "nodes": [0] }}],
+            "nodes": [{{ "mesh": 0 }}],
+            "meshes": [{{ "primitives": [{{
+                "attributes": {{ "POSITION": 0, "TEXCOORD_0": 2 }}, "indices": 1, "material": 0
+            }}] }}],
+            "materials": [{{ "pbrMetallicRoughness": {{ "baseColorTexture": {{ "index": 0 }} }} }}],
+            "textures": [{{ "source": 0 }}],
+            "images": [{{ "bufferView": 3, "mimeType": "image/png" }}],
+            "buffers": [{{ "byteLength": {} }}],
+            "bufferViews": [
+                {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                {{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }},
+                {{ "buffer": 0, "byteOffset": 44, "byteLength": 24 }},
+                {{ "buffer": 0, "byteOffset": 68, "byteLength": {} }}
+            ],
+            "accessors": [
+                {{ "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                   "min": [0, 0, 0], "max": [1, 1, 0] }},
+                {{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }},
+                {{ "bufferView": 2, "componentType": 5126, "count": 3, "type": "VEC2" }}
+            ]
+        }}"#,
+            bin.len(),
+            png.len()
+        );
+
+        let model = Model::from_slice(&glb(&json, &bin)).unwrap();
+        assert_eq!(model.textures.len(), 1);
+        assert!(model.meshes[0].texture.is_some());
+        assert!(model.warnings.iter().all(|w| !w.contains("TEXCOORD_0")));
+    }
+
+    #[test]
+    fn loads_data_uri_slice() {
+        let buffer = format!(
+            r#"{{ "byteLength": 42, "uri": "data:application/octet-stream;base64,{}" }}"#,
+            base64(&triangle_bin())
+        );
+
+        let model = Model::from_slice(triangle_json(&buffer).as_bytes()).unwrap();
+        assert_eq!(model.meshes.len(), 1);
+        assert_eq!(model.meshes[0].indices.len(), 3);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(matches!(
+            Model::from_slice(b"not a gltf"),
+            Err(LoadError::Gltf(_))
+        ));
+    }
+}
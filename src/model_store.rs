@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use crate::model::Model;
+
+//Stays valid until its model is removed. The slot gets a new generation then, so an old
+//handle can't reach whatever gets stored there next
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ModelHandle {
+    index: u32,
+    generation: u32,
+}
+
+struct Entry {
+    name: String,
+    model: Model,
+    draw_order: i32,
+}
+
+struct Slot {
+    generation: u32,
+    entry: Option<Entry>,
+}
+
+//Named models kept in insertion order
+#[derive(Default)]
+pub struct ModelStore {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    order: Vec<ModelHandle>, //insertion order
+    names: HashMap<String, ModelHandle>,
+}
+
+impl ModelStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //A model with the same name gets replaced in place, keeping its handle, draw order
+    //and spot in the insertion order
+    pub fn insert(&mut self, name: &str, model: Model) -> ModelHandle {
+        if let Some(handle) = self.find(name) {
+            if let Some(entry) = self.entry_mut(handle) {
+                entry.model = model;
+            }
+            return handle;
+        }
+
+        let entry = Entry {
+            name: name.to_string(),
+            model,
+            draw_order: 0,
+        };
+
+        let handle = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.entry = Some(entry);
+                ModelHandle {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    entry: Some(entry),
+                });
+                ModelHandle {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        };
+
+        self.order.push(handle);
+        self.names.insert(name.to_string(), handle);
+        handle
+    }
+
+    pub fn remove(&mut self, handle: ModelHandle) -> Option<Model> {
+        self.entry(handle)?;
+
+        let slot = &mut self.slots[handle.index as usize];
+        let entry = slot.entry.take()?;
+        slot.generation += 1;
+
+        self.free.push(handle.index);
+        self.order.retain(|other| *other != handle);
+        self.names.remove(&entry.name);
+        Some(entry.model)
+    }
+
+    fn entry(&self, handle: ModelHandle) -> Option<&Entry> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.entry.as_ref())
+    }
+
+    fn entry_mut(&mut self, handle: ModelHandle) -> Option<&mut Entry> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.entry.as_mut())
+    }
+
+    pub fn get(&self, handle: ModelHandle) -> Option<&Model> {
+        self.entry(handle).map(|entry| &entry.model)
+    }
+
+    pub fn get_mut(&mut self, handle: ModelHandle) -> Option<&mut Model> {
+        self.entry_mut(handle).map(|entry| &mut entry.model)
+    }
+
+    pub fn find(&self, name: &str) -> Option<ModelHandle> {
+        self.names.get(name).copied()
+    }
+
+    pub fn name(&self, handle: ModelHandle) -> Option<&str> {
+        self.entry(handle).map(|entry| entry.name.as_str())
+    }
+
+    pub fn contains(&self, handle: ModelHandle) -> bool {
+        self.entry(handle).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn draw_order(&self, handle: ModelHandle) -> Option<i32> {
+        self.entry(handle).map(|entry| entry.draw_order)
+    }
+
+    //Lower draws first, ties keep the insertion order
+    pub fn set_draw_order(&mut self, handle: ModelHandle, draw_order: i32) {
+        if let Some(entry) = self.entry_mut(handle) {
+            entry.draw_order = draw_order;
+        }
+    }
+
+    //Insertion order
+    pub fn iter(&self) -> impl Iterator<Item = (ModelHandle, &str, &Model)> {
+        self.order.iter().filter_map(|handle| {
+            let entry = self.entry(*handle)?;
+            Some((*handle, entry.name.as_str(), &entry.model))
+        })
+    }
+
+    //Slot order, for changes where the order doesn't matter
+    pub fn models_mut(&mut self) -> impl Iterator<Item = &mut Model> {
+        self.slots
+            .iter_mut()
+            .filter_map(|slot| slot.entry.as_mut().map(|entry| &mut entry.model))
+    }
+
+    //Insertion order stably sorted by draw order
+    pub fn draw_list(&self) -> Vec<ModelHandle> {
+        let mut handles = self.order.clone();
+        handles.sort_by_key(|handle| self.draw_order(*handle).unwrap_or(0));
+        handles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model() -> Model {
+        Model::from_mesh(Default::default(), Default::default())
+    }
+
+    fn names(store: &ModelStore, handles: &[ModelHandle]) -> Vec<String> {
+        handles
+            .iter()
+            .map(|handle| store.name(*handle).unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_insertion_order() {
+        let mut store = ModelStore::new();
+        for name in ["c", "a", "d", "b"] {
+            store.insert(name, model());
+        }
+
+        let order: Vec<&str> = store.iter().map(|(_, name, _)| name).collect();
+        assert_eq!(order, ["c", "a", "d", "b"]);
+
+        //Replacing keeps the spot, draw order sorts stably
+        let a = store.find("a").unwrap();
+        let replaced = store.insert("a", model());
+        assert_eq!(replaced, a);
+
+        store.set_draw_order(store.find("b").unwrap(), -1);
+        store.set_draw_order(store.find("c").unwrap(), 1);
+        assert_eq!(names(&store, &store.draw_list()), ["b", "a", "d", "c"]);
+    }
+
+    #[test]
+    fn stale_handles_miss() {
+        let mut store = ModelStore::new();
+        let first = store.insert("first", model());
+        assert!(store.remove(first).is_some());
+        assert!(store.remove(first).is_none());
+
+        //Same slot, newer generation
+        let second = store.insert("second", model());
+        assert!(store.get(first).is_none());
+        assert!(store.get(second).is_some());
+        assert_eq!(store.find("first"), None);
+        assert_eq!(store.len(), 1);
+    }
+}
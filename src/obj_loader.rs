@@ -0,0 +1,703 @@
+use std::{collections::HashMap, path::Path};
+
+use glam::{Vec2, Vec3, Vec4};
+use rand::Rng;
+
+use crate::{
+    material::{Material, TextureSlot, UvTransform},
+    mesh::VertexMesh,
+    model::{LoadError, LoadOptions, NormalGeneration},
+};
+
+//Everything a Model needs from an OBJ, the textures still have to be loaded
+pub struct ObjModel {
+    pub meshes: Vec<VertexMesh>,
+    pub textures: Vec<String>, //file paths, indexed by the materials' tex_idx
+    pub materials: Vec<String>, //MTL file paths, including missing ones
+    pub warnings: Vec<String>,
+}
+
+#[derive(Clone, Copy)]
+struct Corner {
+    position: usize,
+    tex_coord: Option<usize>,
+    normal: Option<usize>,
+}
+
+struct Face {
+    corners: Vec<Corner>,
+    smoothing: u32, //0 is off
+}
+
+//Faces of one object/group that use the same material, becomes one mesh
+struct Section {
+    name: String,
+    material: Option<String>,
+    faces: Vec<Face>,
+}
+
+#[derive(Default)]
+struct ObjFile {
+    positions: Vec<Vec3>,
+    colors: Vec<Vec3>, //non-standard `v x y z r g b`, only used if every vertex has one
+    tex_coords: Vec<Vec2>,
+    normals: Vec<Vec3>,
+    sections: Vec<Section>,
+    mtllibs: Vec<String>,
+    has_smoothing: bool, //whether there's any `s` statement at all
+    warnings: Vec<String>,
+}
+
+struct ObjMaterial {
+    color: Vec4,             //Kd and d
+    texture: Option<String>, //map_Kd
+    transform: Option<UvTransform>,
+}
+
+impl Default for ObjMaterial {
+    fn default() -> Self {
+        Self {
+            color: Vec4::ONE,
+            texture: None,
+            transform: None,
+        }
+    }
+}
+
+//Which vertices can share a normal
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum NormalKey {
+    File(usize), //vn from the file
+    Smooth(u32), //smoothing group, averaged per position
+    Flat(usize), //face index, smoothing is off
+}
+
+pub fn load(filepath: &str, options: &LoadOptions) -> Result<ObjModel, LoadError> {
+    let text = std::fs::read_to_string(filepath)?;
+    let mut obj = parse_obj(&text).map_err(|err| LoadError::Parse(format!("{filepath}: {err}")))?;
+    let mut warnings = std::mem::take(&mut obj.warnings);
+
+    //A missing MTL isn't fatal, the meshes just get the default material
+    let dir = Path::new(filepath).parent().unwrap_or(Path::new(""));
+    let mut materials = HashMap::new();
+    let mut mtl_paths = Vec::new();
+    for lib in &obj.mtllibs {
+        let mtl_path = dir.join(lib);
+        mtl_paths.push(mtl_path.to_string_lossy().into_owned());
+        match std::fs::read_to_string(&mtl_path) {
+            Ok(text) => materials.extend(
+                parse_mtl(&text, mtl_path.parent().unwrap_or(dir))
+                    .map_err(|err| LoadError::Parse(format!("{}: {err}", mtl_path.display())))?,
+            ),
+            Err(err) => warnings.push(format!("{}: {err}", mtl_path.display())),
+        }
+    }
+
+    let mut textures = Vec::new();
+    let meshes = build_meshes(&obj, &materials, options, &mut textures, &mut warnings);
+
+    Ok(ObjModel {
+        meshes,
+        textures,
+        materials: mtl_paths,
+        warnings,
+    })
+}
+
+fn parse_floats(args: &[&str]) -> Result<Vec<f32>, String> {
+    args.iter()
+        .map(|arg| {
+            arg.parse::<f32>()
+                .map_err(|_| format!("invalid number `{arg}`"))
+        })
+        .collect()
+}
+
+//OBJ indices start at 1, negative ones count back from the last element read so far
+fn resolve(token: &str, count: usize) -> Result<usize, String> {
+    let idx: i64 = token
+        .parse()
+        .map_err(|_| format!("invalid index `{token}`"))?;
+    let resolved = if idx > 0 { idx - 1 } else { count as i64 + idx };
+
+    if idx == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!("index {idx} out of range for {count} elements"));
+    }
+
+    Ok(resolved as usize)
+}
+
+//v, v/vt, v//vn or v/vt/vn
+fn parse_corner(token: &str, obj: &ObjFile) -> Result<Corner, String> {
+    let mut parts = token.split('/');
+    let position = resolve(parts.next().unwrap_or(""), obj.positions.len())?;
+
+    let tex_coord = match parts.next() {
+        None | Some("") => None,
+        Some(part) => Some(resolve(part, obj.tex_coords.len())?),
+    };
+    let normal = match parts.next() {
+        None | Some("") => None,
+        Some(part) => Some(resolve(part, obj.normals.len())?),
+    };
+
+    Ok(Corner {
+        position,
+        tex_coord,
+        normal,
+    })
+}
+
+fn parse_obj(text: &str) -> Result<ObjFile, String> {
+    let mut obj = ObjFile::default();
+    let mut object = String::new();
+    let mut group = String::new();
+    let mut material: Option<String> = None;
+    let mut smoothing = 0;
+    let mut section: Option<usize> = None; //reset whenever the name or material changes
+    let mut ignored: Vec<String> = Vec::new();
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let args: Vec<&str> = tokens.collect();
+        let line_err = |err: String| format!("line {}: {err}", line_idx + 1);
+
+        match keyword {
+            "v" => {
+                let v = parse_floats(&args).map_err(line_err)?;
+                if v.len() < 3 {
+                    return Err(line_err("vertex needs 3 coordinates".to_string()));
+                }
+
+                obj.positions.push(Vec3::new(v[0], v[1], v[2]));
+                if v.len() >= 6 {
+                    obj.colors.push(Vec3::new(v[3], v[4], v[5]));
+                }
+            }
+            "vt" => {
+                let vt = parse_floats(&args).map_err(line_err)?;
+                if vt.is_empty() {
+                    return Err(line_err("tex coord needs a u coordinate".to_string()));
+                }
+
+                //OBJ's v goes up, our textures start at the top
+                let v = vt.get(1).copied().unwrap_or(0.0);
+                obj.tex_coords.push(Vec2::new(vt[0], 1.0 - v));
+            }
+            "vn" => {
+                let vn = parse_floats(&args).map_err(line_err)?;
+                if vn.len() < 3 {
+                    return Err(line_err("normal needs 3 coordinates".to_string()));
+                }
+
+                obj.normals.push(Vec3::new(vn[0], vn[1], vn[2]));
+            }
+            "f" => {
+                let corners = args
+                    .iter()
+                    .map(|arg| parse_corner(arg, &obj))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(line_err)?;
+
+                if corners.len() < 3 {
+                    obj.warnings.push(line_err(format!(
+                        "face with {} vertices, skipped",
+                        corners.len()
+                    )));
+                    continue;
+                }
+
+                let idx = *section.get_or_insert_with(|| {
+                    let name = match (object.is_empty(), group.is_empty()) {
+                        (true, true) => "default".to_string(),
+                        (false, true) => object.clone(),
+                        (true, false) => group.clone(),
+                        (false, false) => format!("{object}/{group}"),
+                    };
+
+                    obj.sections
+                        .iter()
+                        .position(|section| section.name == name && section.material == material)
+                        .unwrap_or_else(|| {
+                            obj.sections.push(Section {
+                                name,
+                                material: material.clone(),
+                                faces: Vec::new(),
+                            });
+                            obj.sections.len() - 1
+                        })
+                });
+
+                obj.sections[idx].faces.push(Face { corners, smoothing });
+            }
+            "o" => {
+                object = args.join(" ");
+                group.clear();
+                section = None;
+            }
+            "g" => {
+                group = args.join(" ");
+                section = None;
+            }
+            "usemtl" => {
+                material = Some(args.join(" "));
+                section = None;
+            }
+            "mtllib" => obj.mtllibs.extend(args.iter().map(|arg| arg.to_string())),
+            "s" => {
+                obj.has_smoothing = true;
+                smoothing = match args.first() {
+                    None | Some(&"off") => 0,
+                    Some(arg) => arg.parse().unwrap_or(1), //`s on`
+                };
+            }
+            _ => {
+                if !ignored.iter().any(|k| k == keyword) {
+                    ignored.push(keyword.to_string());
+                    obj.warnings
+                        .push(format!("`{keyword}` statements aren't supported, ignored"));
+                }
+            }
+        }
+    }
+
+    Ok(obj)
+}
+
+fn parse_mtl(text: &str, dir: &Path) -> Result<HashMap<String, ObjMaterial>, String> {
+    let mut materials = HashMap::new();
+    let mut current: Option<String> = None;
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            continue;
+        };
+        let args: Vec<&str> = tokens.collect();
+        let line_err = |err: String| format!("line {}: {err}", line_idx + 1);
+
+        if keyword == "newmtl" {
+            let name = args.join(" ");
+            materials.insert(name.clone(), ObjMaterial::default());
+            current = Some(name);
+            continue;
+        }
+
+        //Anything before the first newmtl has nothing to go to
+        let Some(material) = current.as_ref().and_then(|name| materials.get_mut(name)) else {
+            continue;
+        };
+
+        match keyword {
+            "Kd" => {
+                let kd = parse_floats(&args).map_err(line_err)?;
+                if kd.len() < 3 {
+                    return Err(line_err("Kd needs 3 values".to_string()));
+                }
+                material.color = Vec4::new(kd[0], kd[1], kd[2], material.color.w);
+            }
+            "d" | "Tr" => {
+                //`d -halo 0.5` has the value last
+                let value = parse_floats(&args[args.len().saturating_sub(1)..])
+                    .map_err(line_err)?
+                    .first()
+                    .copied()
+                    .ok_or_else(|| line_err(format!("{keyword} needs a value")))?;
+                material.color.w = if keyword == "d" { value } else { 1.0 - value };
+            }
+            "map_Kd" => {
+                let (path, transform) = parse_texture_map(&args).map_err(line_err)?;
+                material.texture = Some(dir.join(path).to_string_lossy().into_owned());
+                material.transform = transform;
+            }
+            _ => {} //the rest is for lighting models we don't have
+        }
+    }
+
+    Ok(materials)
+}
+
+//map_Kd [-option values...] file, -o and -s become a uv transform
+fn parse_texture_map(args: &[&str]) -> Result<(String, Option<UvTransform>), String> {
+    let mut offset = Vec2::ZERO;
+    let mut scale = Vec2::ONE;
+    let mut i = 0;
+
+    while i < args.len() && args[i].starts_with('-') {
+        let option = args[i];
+        i += 1;
+
+        //-o, -s and -t take 1 to 3 numbers, the rest a fixed count
+        let count = match option {
+            "-o" | "-s" | "-t" => args[i..]
+                .iter()
+                .take(3)
+                .take_while(|arg| arg.parse::<f32>().is_ok())
+                .count(),
+            "-mm" => 2,
+            "-blendu" | "-blendv" | "-boost" | "-bm" | "-cc" | "-clamp" | "-imfchan"
+            | "-texres" | "-type" => 1,
+            _ => return Err(format!("unknown texture option `{option}`")),
+        };
+
+        let values = args
+            .get(i..i + count)
+            .ok_or_else(|| format!("{option} needs {count} values"))?;
+        let numbers: Vec<f32> = values.iter().filter_map(|arg| arg.parse().ok()).collect();
+
+        match option {
+            "-o" if !numbers.is_empty() => {
+                offset = Vec2::new(numbers[0], numbers.get(1).copied().unwrap_or(0.0))
+            }
+            "-s" if !numbers.is_empty() => {
+                scale = Vec2::new(numbers[0], numbers.get(1).copied().unwrap_or(1.0))
+            }
+            _ => {}
+        }
+
+        i += count;
+    }
+
+    if i >= args.len() {
+        return Err("map_Kd without a file".to_string());
+    }
+
+    //The tex coords got flipped vertically, so the offset has to flip with them
+    let transform = (offset != Vec2::ZERO || scale != Vec2::ONE).then(|| UvTransform {
+        offset: Vec2::new(offset.x, 1.0 - scale.y - offset.y),
+        rotation: 0.0,
+        scale,
+    });
+
+    Ok((args[i..].join(" "), transform))
+}
+
+//Newell's method, works for any planar polygon and the length is twice the area
+fn face_normal(face: &Face, positions: &[Vec3]) -> Vec3 {
+    let mut normal = Vec3::ZERO;
+
+    for (i, corner) in face.corners.iter().enumerate() {
+        let a = positions[corner.position];
+        let b = positions[face.corners[(i + 1) % face.corners.len()].position];
+        normal += a.cross(b);
+    }
+
+    normal
+}
+
+fn build_meshes(
+    obj: &ObjFile,
+    materials: &HashMap<String, ObjMaterial>,
+    options: &LoadOptions,
+    textures: &mut Vec<String>,
+    warnings: &mut Vec<String>,
+) -> Vec<VertexMesh> {
+    let mut meshes = Vec::new();
+
+    //Files without any `s` statement get whatever the options ask for
+    let default_group = match options.normals {
+        NormalGeneration::Flat => 0,
+        NormalGeneration::Smooth => 1,
+    };
+
+    for section in &obj.sections {
+        let mut vertex_map: HashMap<(usize, Option<usize>, NormalKey), u32> = HashMap::new();
+        let mut sources: Vec<(usize, NormalKey)> = Vec::new();
+        let mut positions = Vec::new();
+        let mut tex_coords = Vec::new();
+        let mut indices = Vec::new();
+        let mut smooth: HashMap<(usize, u32), Vec3> = HashMap::new();
+        let mut flat = Vec::new();
+        let mut missing_tex_coords = false;
+
+        for (face_idx, face) in section.faces.iter().enumerate() {
+            let normal = face_normal(face, &obj.positions);
+            flat.push(normal.try_normalize().unwrap_or(Vec3::Z));
+
+            let group = if obj.has_smoothing {
+                face.smoothing
+            } else {
+                default_group
+            };
+
+            let mut face_indices = Vec::with_capacity(face.corners.len());
+            for corner in &face.corners {
+                let key = match (corner.normal, group) {
+                    (Some(normal), _) => NormalKey::File(normal),
+                    (None, 0) => NormalKey::Flat(face_idx),
+                    (None, group) => {
+                        *smooth.entry((corner.position, group)).or_default() += normal;
+                        NormalKey::Smooth(group)
+                    }
+                };
+
+                missing_tex_coords |= corner.tex_coord.is_none();
+
+                let idx = *vertex_map
+                    .entry((corner.position, corner.tex_coord, key))
+                    .or_insert_with(|| {
+                        sources.push((corner.position, key));
+                        positions.push(obj.positions[corner.position]);
+                        tex_coords.push(corner.tex_coord.map_or(Vec2::ZERO, |t| obj.tex_coords[t]));
+                        positions.len() as u32 - 1
+                    });
+                face_indices.push(idx);
+            }
+
+            //Fan triangulation, fine for the convex polygons exporters write
+            for i in 1..face_indices.len() - 1 {
+                indices.extend([face_indices[0], face_indices[i], face_indices[i + 1]]);
+            }
+        }
+
+        if indices.is_empty() {
+            continue;
+        }
+
+        let normals: Vec<Vec3> = sources
+            .iter()
+            .map(|(position, key)| match key {
+                NormalKey::File(normal) => obj.normals[*normal],
+                NormalKey::Smooth(group) => smooth[&(*position, *group)]
+                    .try_normalize()
+                    .unwrap_or(Vec3::Z),
+                NormalKey::Flat(face) => flat[*face],
+            })
+            .collect();
+
+        let colors = if options.random_colors {
+            let mut rng = rand::thread_rng();
+            vec![Vec4::new(rng.gen(), rng.gen(), rng.gen(), 1.0); positions.len()]
+        } else if obj.colors.len() == obj.positions.len() {
+            sources
+                .iter()
+                .map(|(position, _)| Vec4::from((obj.colors[*position], 1.0)))
+                .collect()
+        } else {
+            vec![Vec4::ONE; positions.len()]
+        };
+
+        let mut material = Material::default();
+        if let Some(name) = &section.material {
+            match materials.get(name) {
+                Some(obj_material) => {
+                    material.base_color = obj_material.color;
+
+                    if let Some(path) = &obj_material.texture {
+                        let tex_idx =
+                            textures.iter().position(|t| t == path).unwrap_or_else(|| {
+                                textures.push(path.clone());
+                                textures.len() - 1
+                            });
+
+                        material.base_tex = TextureSlot {
+                            tex_idx: tex_idx as i32,
+                            transform: obj_material.transform,
+                            ..Default::default()
+                        };
+
+                        if missing_tex_coords {
+                            warnings.push(format!(
+                                "{}: textured but some vertices have no vt, using (0, 0)",
+                                section.name
+                            ));
+                        }
+                    }
+                }
+                None => warnings.push(format!(
+                    "{}: material `{name}` not found, using the default",
+                    section.name
+                )),
+            }
+        }
+
+        meshes.push(VertexMesh::from_buffers(
+            &indices,
+            &positions,
+            &normals,
+            &colors,
+            &tex_coords,
+            material,
+        ));
+    }
+
+    meshes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{model::Model, test_utils::temp_dir};
+
+    fn meshes(text: &str, options: &LoadOptions) -> Vec<VertexMesh> {
+        let obj = parse_obj(text).unwrap();
+        build_meshes(
+            &obj,
+            &HashMap::new(),
+            options,
+            &mut Vec::new(),
+            &mut Vec::new(),
+        )
+    }
+
+    const TWO_TRIANGLES: &str = "
+        v 0 0 0
+        v 1 0 0
+        v 0 1 0
+        v 1 1 0.5
+        f 1 2 3
+        f 3 2 4
+    ";
+
+    #[test]
+    fn triangulates_polygons_with_negative_indices() {
+        let text = "
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            v 0.5 1.5 0
+            f -5 -4 -3 -2 -1
+        ";
+
+        let meshes = meshes(text, &LoadOptions::default());
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes[0].vertices.len(), 5);
+        assert_eq!(meshes[0].indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+        assert!(meshes[0].vertices.iter().all(|v| v.normal == Vec3::Z));
+    }
+
+    #[test]
+    fn smoothing_groups_share_vertices() {
+        let smooth = meshes(&format!("s 1\n{TWO_TRIANGLES}"), &LoadOptions::default());
+        assert_eq!(smooth[0].vertices.len(), 4);
+
+        let flat = meshes(&format!("s off\n{TWO_TRIANGLES}"), &LoadOptions::default());
+        assert_eq!(flat[0].vertices.len(), 6);
+
+        //No `s` at all goes by the options
+        let options = LoadOptions {
+            normals: NormalGeneration::Flat,
+            ..Default::default()
+        };
+        assert_eq!(meshes(TWO_TRIANGLES, &options)[0].vertices.len(), 6);
+    }
+
+    #[test]
+    fn splits_objects_groups_and_materials() {
+        let text = "
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            o Car
+            usemtl red
+            f 1 2 3
+            usemtl blue
+            f 1 2 3
+            g Wheel
+            f 1 2 3
+            o Car
+            usemtl red
+            f 1 2 3
+        ";
+
+        let obj = parse_obj(text).unwrap();
+        let names: Vec<_> = obj.sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["Car", "Car", "Car/Wheel"]);
+        assert_eq!(obj.sections[0].faces.len(), 2);
+    }
+
+    #[test]
+    fn uses_file_normals_and_tex_coords() {
+        let text = "
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            vt 0 0
+            vt 1 0.25
+            vn 0 0 -1
+            f 1/1/1 2/2/1 3//1
+        ";
+
+        let meshes = meshes(text, &LoadOptions::default());
+        let vertices = &meshes[0].vertices;
+        assert!(vertices.iter().all(|v| v.normal == Vec3::NEG_Z));
+        assert_eq!(vertices[1].uv, Vec2::new(1.0, 0.75));
+        assert_eq!(vertices[2].uv, Vec2::ZERO);
+    }
+
+    #[test]
+    fn parses_materials() {
+        let text = "
+            newmtl glass
+            Kd 0.5 0.25 1
+            d 0.5
+            map_Kd -s 2 2 1 -clamp on textures/glass file.png
+            newmtl solid
+            Kd 1 0 0
+            Tr 0.25
+        ";
+
+        let materials = parse_mtl(text, Path::new("dir")).unwrap();
+        let glass = &materials["glass"];
+        assert_eq!(glass.color, Vec4::new(0.5, 0.25, 1.0, 0.5));
+        assert_eq!(
+            glass.texture.as_deref().map(Path::new),
+            Some(Path::new("dir/textures/glass file.png"))
+        );
+        assert_eq!(glass.transform.unwrap().scale, Vec2::splat(2.0));
+        assert_eq!(materials["solid"].color, Vec4::new(1.0, 0.0, 0.0, 0.75));
+    }
+
+    #[test]
+    fn rejects_out_of_range_indices() {
+        assert!(parse_obj("v 0 0 0\nf 1 2 3").is_err());
+        assert!(parse_obj("v 0 0 0\nf 0 1 1").is_err());
+        assert!(parse_obj("v 0 0 x").is_err());
+    }
+
+    #[test]
+    fn loads_obj_with_mtl_and_texture() {
+        let dir = temp_dir("obj");
+
+        image::RgbImage::from_pixel(2, 2, image::Rgb([0, 255, 0]))
+            .save(dir.join("green.png"))
+            .unwrap();
+        std::fs::write(
+            dir.join("quad.mtl"),
+            "newmtl green\nKd 1 1 1\nmap_Kd green.png\nnewmtl red\nKd 1 0 0\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("quad.obj"),
+            "mtllib quad.mtl missing.mtl
+            v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0
+            vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1
+            usemtl green
+            f 1/1 2/2 3/3 4/4
+            usemtl red
+            f 1 2 3
+            usemtl unknown
+            f 1 3 4",
+        )
+        .unwrap();
+
+        let model = Model::from_obj(dir.join("quad.obj").to_str().unwrap()).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(model.meshes.len(), 3);
+        assert!(model.meshes[0].texture.is_some());
+        assert_eq!(
+            model.meshes[1].material.base_color,
+            Vec4::new(1.0, 0.0, 0.0, 1.0)
+        );
+        assert!(model.meshes[2].texture.is_none());
+        //The missing MTL and the unknown material
+        assert_eq!(model.warnings.len(), 2);
+    }
+}
@@ -0,0 +1,540 @@
+use glam::{Vec2, Vec3, Vec4};
+use rand::Rng;
+
+use crate::{
+    material::Material,
+    mesh::{Topology, VertexMesh},
+    model::{LoadError, LoadOptions, NormalGeneration},
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    //Integer colors go from 0 to the type's max, floats are already 0 to 1
+    fn color_scale(&self) -> f32 {
+        match self {
+            Scalar::U8 => 1.0 / 255.0,
+            Scalar::U16 => 1.0 / 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+enum Property {
+    Scalar {
+        name: String,
+        ty: Scalar,
+    },
+    List {
+        name: String,
+        count: Scalar,
+        item: Scalar,
+    },
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+    body_start: usize, //byte offset after end_header
+}
+
+//Reads values one by one, either as whitespace separated text or as raw bytes
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary {
+        bytes: &'a [u8],
+        pos: usize,
+        big_endian: bool,
+    },
+}
+
+impl Body<'_> {
+    fn read(&mut self, ty: Scalar) -> Result<f64, String> {
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens.next().ok_or("unexpected end of file")?;
+                token
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid number `{token}`"))
+            }
+            Body::Binary {
+                bytes,
+                pos,
+                big_endian,
+            } => {
+                let data = bytes
+                    .get(*pos..*pos + ty.size())
+                    .ok_or("unexpected end of file")?;
+                *pos += ty.size();
+
+                macro_rules! read {
+                    ($t:ty) => {{
+                        let data = data.try_into().unwrap();
+                        if *big_endian {
+                            <$t>::from_be_bytes(data) as f64
+                        } else {
+                            <$t>::from_le_bytes(data) as f64
+                        }
+                    }};
+                }
+
+                Ok(match ty {
+                    Scalar::I8 => read!(i8),
+                    Scalar::U8 => read!(u8),
+                    Scalar::I16 => read!(i16),
+                    Scalar::U16 => read!(u16),
+                    Scalar::I32 => read!(i32),
+                    Scalar::U32 => read!(u32),
+                    Scalar::F32 => read!(f32),
+                    Scalar::F64 => read!(f64),
+                })
+            }
+        }
+    }
+}
+
+pub struct PlyModel {
+    pub mesh: VertexMesh,
+    pub warnings: Vec<String>,
+}
+
+pub fn load(filepath: &str, options: &LoadOptions) -> Result<PlyModel, LoadError> {
+    let bytes = std::fs::read(filepath)?;
+    parse(&bytes, options).map_err(|err| LoadError::Parse(format!("{filepath}: {err}")))
+}
+
+fn parse_header(bytes: &[u8]) -> Result<Header, String> {
+    const END: &[u8] = b"end_header";
+
+    let end = bytes
+        .windows(END.len())
+        .position(|window| window == END)
+        .ok_or("no end_header")?;
+    //The body starts after the line break, which can be \r\n
+    let body_start = bytes[end..]
+        .iter()
+        .position(|b| *b == b'\n')
+        .map_or(bytes.len(), |offset| end + offset + 1);
+
+    let text = std::str::from_utf8(&bytes[..end]).map_err(|err| err.to_string())?;
+    let mut lines = text.lines();
+
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err("missing ply magic".to_string());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+
+    for line in lines {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        match tokens.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(format!("unknown format `{name}`")),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| format!("invalid element count `{count}`"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let parse = |ty: &str| Scalar::from_name(ty).ok_or(format!("unknown type `{ty}`"));
+                let property = Property::List {
+                    name: name.to_string(),
+                    count: parse(count)?,
+                    item: parse(item)?,
+                };
+                elements
+                    .last_mut()
+                    .ok_or("property before any element")?
+                    .properties
+                    .push(property);
+            }
+            ["property", ty, name] => {
+                let property = Property::Scalar {
+                    name: name.to_string(),
+                    ty: Scalar::from_name(ty).ok_or(format!("unknown type `{ty}`"))?,
+                };
+                elements
+                    .last_mut()
+                    .ok_or("property before any element")?
+                    .properties
+                    .push(property);
+            }
+            [] | ["comment", ..] | ["obj_info", ..] => {}
+            _ => return Err(format!("invalid header line `{line}`")),
+        }
+    }
+
+    Ok(Header {
+        format: format.ok_or("no format line")?,
+        elements,
+        body_start,
+    })
+}
+
+//Vertex positions, normals, colors and tex coords plus faces. Without faces the vertices
+//become a point cloud
+pub fn parse(bytes: &[u8], options: &LoadOptions) -> Result<PlyModel, String> {
+    let mut warnings = Vec::new();
+    let header = parse_header(bytes)?;
+    let body_bytes = &bytes[header.body_start..];
+
+    let mut body = match header.format {
+        Format::Ascii => Body::Ascii(
+            std::str::from_utf8(body_bytes)
+                .map_err(|err| err.to_string())?
+                .split_ascii_whitespace(),
+        ),
+        format => Body::Binary {
+            bytes: body_bytes,
+            pos: 0,
+            big_endian: format == Format::BinaryBigEndian,
+        },
+    };
+
+    let mut positions: Vec<Vec3> = Vec::new();
+    let mut normals: Vec<Vec3> = Vec::new();
+    let mut colors: Vec<Vec4> = Vec::new();
+    let mut tex_coords: Vec<Vec2> = Vec::new();
+    let mut indices: Vec<u32> = Vec::new();
+    let mut has_faces = false;
+
+    for element in &header.elements {
+        //Where each property lands in the row of scalars
+        let find = |names: &[&str]| {
+            element.properties.iter().position(
+                |property| matches!(property, Property::Scalar { name, .. } if names.contains(&name.as_str())),
+            )
+        };
+        let scalar_type = |idx: usize| match &element.properties[idx] {
+            Property::Scalar { ty, .. } => *ty,
+            Property::List { item, .. } => *item,
+        };
+
+        let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+        let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+        let color = [
+            find(&["red", "r"]),
+            find(&["green", "g"]),
+            find(&["blue", "b"]),
+        ];
+        let alpha = find(&["alpha", "a"]);
+        let tex_coord = [
+            find(&["s", "u", "texture_u", "texture_s"]),
+            find(&["t", "v", "texture_v", "texture_t"]),
+        ];
+        let color_scale = color[0].map_or(1.0, |idx| scalar_type(idx).color_scale());
+        let alpha_scale = alpha.map_or(1.0, |idx| scalar_type(idx).color_scale());
+
+        let is_vertex = element.name == "vertex";
+        let is_face = element.name == "face";
+        if is_vertex && position.iter().any(Option::is_none) {
+            return Err("vertex element without x, y and z".to_string());
+        }
+        if !is_vertex && !is_face {
+            warnings.push(format!(
+                "`{}` elements aren't supported, skipped",
+                element.name
+            ));
+        }
+
+        let mut row = vec![0.0; element.properties.len()];
+        let mut list = Vec::new();
+
+        for _ in 0..element.count {
+            list.clear();
+
+            for (idx, property) in element.properties.iter().enumerate() {
+                match property {
+                    Property::Scalar { ty, .. } => row[idx] = body.read(*ty)?,
+                    Property::List { name, count, item } => {
+                        let count = body.read(*count)? as usize;
+                        let wanted =
+                            is_face && (name == "vertex_indices" || name == "vertex_index");
+
+                        for _ in 0..count {
+                            let value = body.read(*item)?;
+                            if wanted {
+                                list.push(value as u32);
+                            }
+                        }
+                    }
+                }
+            }
+
+            let get = |idx: Option<usize>| idx.map(|idx| row[idx] as f32);
+
+            if is_vertex {
+                let [x, y, z] = position.map(|idx| get(idx).unwrap());
+                positions.push(Vec3::new(x, y, z));
+
+                if let [Some(x), Some(y), Some(z)] = normal.map(get) {
+                    normals.push(Vec3::new(x, y, z));
+                }
+                if let [Some(r), Some(g), Some(b)] = color.map(get) {
+                    let a = get(alpha).map_or(1.0, |a| a * alpha_scale);
+                    colors.push(Vec4::new(r, g, b, 0.0) * color_scale + Vec4::W * a);
+                }
+                if let [Some(s), Some(t)] = tex_coord.map(get) {
+                    //Same convention as OBJ, v goes up
+                    tex_coords.push(Vec2::new(s, 1.0 - t));
+                }
+            } else if is_face {
+                has_faces = true;
+                if list.len() < 3 {
+                    warnings.push(format!("face with {} vertices, skipped", list.len()));
+                    continue;
+                }
+
+                for i in 1..list.len() - 1 {
+                    indices.extend([list[0], list[i], list[i + 1]]);
+                }
+            }
+        }
+    }
+
+    if let Some(idx) = indices.iter().find(|idx| **idx as usize >= positions.len()) {
+        return Err(format!(
+            "index {} out of range for {} vertices",
+            idx,
+            positions.len()
+        ));
+    }
+
+    let topology = if has_faces {
+        Topology::TriangleList
+    } else {
+        indices = (0..positions.len() as u32).collect();
+        Topology::PointList
+    };
+
+    if options.random_colors {
+        let mut rng = rand::thread_rng();
+        colors = vec![Vec4::new(rng.gen(), rng.gen(), rng.gen(), 1.0); positions.len()];
+    } else if colors.len() != positions.len() {
+        colors = vec![Vec4::ONE; positions.len()];
+    }
+    if tex_coords.len() != positions.len() {
+        tex_coords = vec![Vec2::ZERO; positions.len()];
+    }
+
+    if normals.len() != positions.len() {
+        if topology == Topology::PointList {
+            //Points don't get lit, any normal works
+            normals = vec![Vec3::Z; positions.len()];
+        } else if options.normals == NormalGeneration::Flat {
+            warnings.push("no normals, generating flat normals".to_string());
+            positions = VertexMesh::unweld(&positions, &indices);
+            colors = VertexMesh::unweld(&colors, &indices);
+            tex_coords = VertexMesh::unweld(&tex_coords, &indices);
+            indices = (0..positions.len() as u32).collect();
+            normals = VertexMesh::flat_normals(&positions);
+        } else {
+            warnings.push("no normals, generating smooth normals".to_string());
+            normals = VertexMesh::smooth_normals(&positions, &indices);
+        }
+    }
+
+    let mut mesh = VertexMesh::from_buffers(
+        &indices,
+        &positions,
+        &normals,
+        &colors,
+        &tex_coords,
+        Material::default(),
+    );
+    mesh.topology = topology;
+
+    Ok(PlyModel { mesh, warnings })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{model::Model, test_utils::temp_dir};
+
+    #[test]
+    fn parses_ascii_with_colors_and_polygons() {
+        let text = "ply
+format ascii 1.0
+comment made by hand
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 0 0
+1 0 0 0 255 0
+1 1 0 0 0 255
+0 1 0 255 255 255
+4 0 1 2 3
+";
+
+        let PlyModel { mesh, warnings } = parse(text.as_bytes(), &LoadOptions::default()).unwrap();
+
+        assert_eq!(mesh.topology, Topology::TriangleList);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.vertices[0].color, Vec4::new(1.0, 0.0, 0.0, 1.0));
+        assert_eq!(mesh.vertices[2].color, Vec4::new(0.0, 0.0, 1.0, 1.0));
+        assert_eq!(mesh.vertices[1].normal, Vec3::Z);
+        assert_eq!(warnings.len(), 1); //generated normals
+    }
+
+    fn binary_point_cloud(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian {
+            "binary_big_endian"
+        } else {
+            "binary_little_endian"
+        };
+        let mut bytes = format!(
+            "ply\r\nformat {format} 1.0\r\nelement vertex 2\r\nproperty float x\r\n\
+             property float y\r\nproperty float z\r\nproperty double nx\r\nproperty double ny\r\n\
+             property double nz\r\nproperty ushort red\r\nproperty ushort green\r\n\
+             property ushort blue\r\nelement camera 1\r\nproperty list uchar float view\r\n\
+             end_header\r\n"
+        )
+        .into_bytes();
+
+        for (i, position) in [Vec3::ONE, Vec3::new(-1.0, 2.0, 3.0)].iter().enumerate() {
+            for f in position.to_array() {
+                bytes.extend(if big_endian {
+                    f.to_be_bytes()
+                } else {
+                    f.to_le_bytes()
+                });
+            }
+            for f in [0.0f64, 1.0, 0.0] {
+                bytes.extend(if big_endian {
+                    f.to_be_bytes()
+                } else {
+                    f.to_le_bytes()
+                });
+            }
+            for c in [65535u16, 0, i as u16 * 65535] {
+                bytes.extend(if big_endian {
+                    c.to_be_bytes()
+                } else {
+                    c.to_le_bytes()
+                });
+            }
+        }
+
+        //The camera gets skipped but still has to be read past
+        bytes.push(2);
+        bytes.extend([0.5f32, 0.5].iter().flat_map(|f| {
+            if big_endian {
+                f.to_be_bytes()
+            } else {
+                f.to_le_bytes()
+            }
+        }));
+        bytes
+    }
+
+    #[test]
+    fn parses_binary_point_clouds() {
+        for big_endian in [false, true] {
+            let bytes = binary_point_cloud(big_endian);
+            let PlyModel { mesh, warnings } = parse(&bytes, &LoadOptions::default()).unwrap();
+
+            assert_eq!(mesh.topology, Topology::PointList);
+            assert_eq!(mesh.indices, vec![0, 1]);
+            assert_eq!(
+                mesh.vertices[1].position.truncate(),
+                Vec3::new(-1.0, 2.0, 3.0)
+            );
+            assert_eq!(mesh.vertices[1].normal, Vec3::Y);
+            assert_eq!(mesh.vertices[1].color, Vec4::new(1.0, 0.0, 1.0, 1.0));
+            assert_eq!(warnings.len(), 1); //the camera element
+        }
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let options = LoadOptions::default();
+        assert!(parse(b"ply\nformat ascii 1.0\n", &options).is_err());
+
+        let truncated = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\n\
+                         property float y\nproperty float z\nend_header\n0 0 0\n";
+        assert!(parse(truncated.as_bytes(), &options).is_err());
+
+        let out_of_range = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n\
+                            property float y\nproperty float z\nelement face 1\n\
+                            property list uchar uint vertex_index\nend_header\n0 0 0\n3 0 1 2\n";
+        assert!(parse(out_of_range.as_bytes(), &options).is_err());
+    }
+
+    #[test]
+    fn dispatches_on_extension() {
+        let dir = temp_dir("ply");
+        let ply = dir.join("cloud.PLY");
+        std::fs::write(&ply, binary_point_cloud(false)).unwrap();
+
+        let model = Model::from_file(ply.to_str().unwrap());
+        let unsupported = Model::from_file(dir.join("cloud.fbx").to_str().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(model.unwrap().meshes[0].topology, Topology::PointList);
+        assert!(matches!(unsupported, Err(LoadError::UnsupportedFormat(_))));
+    }
+}
@@ -0,0 +1,111 @@
+use glam::{IVec2, Vec2, Vec4Swizzles};
+
+use crate::{
+    data::Vertex,
+    line::shade_unlit,
+    render_utils,
+    sliced_buffer::{DepthCompare, DepthState, DrawState, FrameStats, PixelId, Primitive},
+};
+
+#[derive(Copy, Clone)]
+pub struct Point {
+    pub v: Vertex,
+    pub ssc: Vec2, //screen coordinates
+    pub size: f32, //diameter in pixels
+    pub aabb: Option<[Vec2; 2]>,
+    pub primitive: u32, //idx of the point in its mesh, for the ID buffer
+}
+
+impl Point {
+    pub fn new(vertex: Vertex, size: f32) -> Self {
+        Self {
+            v: vertex,
+            ssc: Vec2::splat(0.0),
+            size,
+            aabb: None,
+            primitive: 0,
+        }
+    }
+
+    //Points are either fully in or out, no clipping needed.
+    //z < 0 and z > w are the near and far plane, swapped with reversed-Z
+    pub fn render_point(&self) -> Option<Point> {
+        let pos = self.v.position;
+
+        if pos.z < 0.0 || pos.z > pos.w {
+            return None;
+        }
+
+        let mut point = *self;
+        point.v.position *= 1.0 / pos.w;
+        point.ssc = render_utils::ndc_to_screen(point.v.position);
+
+        //Half the size plus a pixel for the anti-aliased edge
+        let extent = Vec2::splat(point.size * 0.5 + 1.0);
+        point.aabb = Some(render_utils::clamp_aabb(
+            point.ssc - extent,
+            point.ssc + extent,
+        ));
+
+        Some(point)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_fragments(
+        &self,
+        pos: IVec2,
+        size: IVec2,
+        color_buff: &mut [u32],
+        depth_buff: &mut [f32],
+        mut id_buff: Option<&mut [Option<PixelId>]>,
+        draw: &DrawState,
+        depth_state: DepthState,
+        stats: &mut FrameStats,
+    ) {
+        let depth = depth_state.format.quantize(self.v.position.z);
+
+        for idx_x in 0..size.x {
+            for idx_y in 0..size.y {
+                let x = pos.x + idx_x;
+                let y = pos.y + idx_y;
+
+                let p = Vec2::new(x as f32, y as f32) + 0.5;
+                let idx: usize = (idx_x + idx_y * size.x) as usize;
+
+                //Round anti-aliased points
+                let coverage = (self.size * 0.5 + 0.5 - p.distance(self.ssc)).clamp(0.0, 1.0);
+                if coverage <= 0.0 {
+                    continue;
+                }
+
+                stats.fragments_tested += 1;
+                if !depth_state.compare.test(depth, depth_buff[idx]) {
+                    continue;
+                }
+
+                if depth_state.write {
+                    depth_buff[idx] = depth;
+                }
+
+                if let Some(id_buff) = id_buff.as_deref_mut() {
+                    id_buff[idx] = draw.id.map(|id| id.pixel(self.primitive));
+                }
+
+                stats.fragments_shaded += 1;
+
+                let fc = shade_unlit(draw, self.v.color.xyz(), self.v.normal, depth);
+                render_utils::blend(color_buff, idx, fc, coverage);
+            }
+        }
+    }
+}
+
+impl Primitive for Point {
+    fn aabb(&self) -> [Vec2; 2] {
+        self.aabb.unwrap()
+    }
+
+    fn nearest_depth(&self, _compare: DepthCompare) -> f32 {
+        self.v.position.z
+    }
+}
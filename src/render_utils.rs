@@ -146,3 +146,32 @@ where
 {
     b1 + (v - a1) * (b2 - b1) / (a2 - a1)
 }
+
+// NDC [-1 ; 1] to pixel coordinates of the buffer, y points down
+pub fn ndc_to_screen(ndc: Vec4) -> Vec2 {
+    Vec2::new(
+        map_to_range(ndc.x, -1.0, 1.0, 0.0, crate::BUFF_WIDTH as f32),
+        map_to_range(-ndc.y, -1.0, 1.0, 0.0, crate::BUFF_HEIGHT as f32),
+    )
+}
+
+// Clamps a screen space AABB to the buffer
+pub fn clamp_aabb(min: Vec2, max: Vec2) -> [Vec2; 2] {
+    [
+        min.max(Vec2::ZERO),
+        max.min(Vec2::new(
+            crate::BUFF_WIDTH as f32 - 1.0,
+            crate::BUFF_HEIGHT as f32 - 1.0,
+        )),
+    ]
+}
+
+// Blends the color over what's already in the buffer, coverage gives the anti-aliasing
+pub fn blend(color_buff: &mut [u32], idx: usize, color: Vec3, coverage: f32) {
+    let dst = u32_to_argb8(color_buff[idx]);
+    let dst = Vec3::new(dst[1] as f32, dst[2] as f32, dst[3] as f32);
+
+    let fc = lerp(dst, color, coverage);
+
+    color_buff[idx] = argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
+}
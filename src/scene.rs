@@ -1,68 +1,1033 @@
 use std::collections::HashMap;
 
+use glam::{Mat4, Vec3};
 use minifb::{MouseButton, Window};
 
 use crate::{
-    camera::Camera, mesh::VertexMesh, model::Model, sliced_buffer::SlicedBuffers,
+    bvh::{Bvh, Ray},
+    camera::Camera,
+    file_watcher::FileWatcher,
+    gltf_exporter::{self, ExportError},
+    light::Lighting,
+    mesh::{VertexMesh, Wireframe},
+    model::{LoadError, Model},
+    model_store::{ModelHandle, ModelStore},
+    scene_file::{CameraDesc, ModelDesc, SceneFile},
+    scene_graph::SceneGraph,
+    sliced_buffer::{DrawId, SlicedBuffers},
     transform::Transform,
 };
 
+//Closest triangle a ray hits, position is in world space
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    pub model: ModelHandle,
+    pub mesh: usize, //idx into the model's meshes
+    pub distance: f32,
+    pub position: Vec3,
+}
+
+//What a pixel of the last frame shows, from the ID buffer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pick {
+    pub model: ModelHandle,
+    pub mesh: usize,      //idx into the model's meshes
+    pub lod: usize,       //level of detail that was drawn, 0 is full detail
+    pub primitive: usize, //triangle, line or point idx within that level's indices
+    pub position: Vec3,   //world space, from the pixel's depth
+}
+
 pub struct Scene {
     name: String,
-    camera: Camera,
-    render_models: HashMap<String, Model>,
+    camera: Option<CameraDesc>, //where the camera goes when switching to the scene
+    models: ModelStore,
+    sort_by_texture: bool, //groups meshes with the same texture when drawing
+    front_to_back: bool,   //applied to every model, including ones added later
+    graph: SceneGraph,     //a node per model plus empty ones, named like the models
+    bvh: Bvh,              //world bounds of every mesh that isn't skinned or morphed
+    bvh_items: Vec<(ModelHandle, usize)>, //model and mesh idx of each BVH item
+    drawn: Vec<ModelHandle>, //models of the last render, PixelId::model indexes into it
+    camera_parent: Option<String>, //node the camera rides on
+    descs: Vec<ModelDesc>, //how to load each model again, in scene file order
+    filepath: Option<String>,
+    watcher: FileWatcher, //the scene file and every model's files
+    pub clear_color: Vec3,
+    pub lighting: Lighting,
 }
 
 impl Scene {
     pub fn new(name: String) -> Self {
         Self {
             name,
-            camera: Camera::default(),
-            render_models: HashMap::new(),
+            camera: None,
+            models: ModelStore::new(),
+            sort_by_texture: false,
+            front_to_back: false,
+            graph: SceneGraph::new(),
+            bvh: Bvh::default(),
+            bvh_items: Vec::new(),
+            drawn: Vec::new(),
+            camera_parent: None,
+            descs: Vec::new(),
+            filepath: None,
+            watcher: FileWatcher::default(),
+            clear_color: Vec3::new(255.0, 0.0, 0.0),
+            lighting: Lighting::default(),
+        }
+    }
+
+    //Models that fail to load are reported and skipped, only a broken scene file is an error
+    pub fn from_file(filepath: &str) -> Result<Self, LoadError> {
+        let mut scene = Self::from_desc(SceneFile::from_file(filepath)?);
+        scene.filepath = Some(filepath.to_string());
+        scene.rewatch();
+        Ok(scene)
+    }
+
+    pub fn from_desc(desc: SceneFile) -> Self {
+        let mut scene = Self::new(desc.name.clone());
+        scene.camera_parent = desc
+            .camera
+            .as_ref()
+            .and_then(|camera| camera.parent.clone());
+        scene.camera = desc.camera.clone();
+        scene.clear_color = Vec3::from(desc.clear_color.map(f32::from));
+        scene.lighting = desc.lighting();
+
+        for model_desc in desc.models {
+            match model_desc.load() {
+                Ok(model) => {
+                    scene.place(&model_desc.name, model);
+                }
+                Err(err) => println!("Failed to load {}: {err}", model_desc.name),
+            }
+
+            //Kept even if loading failed so saving doesn't drop it
+            scene.descs.push(model_desc);
+        }
+
+        scene.apply_placement();
+        scene.rewatch();
+        scene
+    }
+
+    //Meshes added from code have nothing to load them from and are left out
+    pub fn to_desc(&self) -> SceneFile {
+        let models = self
+            .descs
+            .iter()
+            .map(|desc| self.current_desc(desc))
+            .collect();
+
+        SceneFile {
+            name: self.name.clone(),
+            clear_color: self.clear_color.to_array().map(|c| c as u8),
+            camera: self.camera.clone().map(|camera| CameraDesc {
+                parent: self.camera_parent.clone(),
+                ..camera
+            }),
+            ambient: self.lighting.ambient.to_array(),
+            lights: self.lighting.lights.iter().map(Into::into).collect(),
+            models,
+        }
+    }
+
+    pub fn save(&mut self, filepath: &str) -> std::io::Result<()> {
+        let json = serde_json::to_string_pretty(&self.to_desc())?;
+        std::fs::write(filepath, json)?;
+
+        //Already matches what's in memory, no need to reload it
+        if self.watcher.is_watched(filepath) {
+            self.watcher.watch(filepath);
+        }
+        Ok(())
+    }
+
+    //Polls the scene file and the models' files, reloading only what changed. Texture
+    //files are watched by the TEXTURE_MANAGER
+    pub fn hot_reload(&mut self) {
+        for path in self.watcher.poll() {
+            if self.filepath.as_deref() == Some(path.as_str()) {
+                self.reload_file();
+                continue;
+            }
+
+            //Models that failed to load are retried when their file shows up
+            let names: Vec<String> = self
+                .descs
+                .iter()
+                .filter(|desc| {
+                    desc.path.as_deref() == Some(path.as_str())
+                        || self
+                            .model_named(&desc.name)
+                            .is_some_and(|model| model.sources.contains(&path))
+                })
+                .map(|desc| desc.name.clone())
+                .collect();
+
+            for name in names {
+                self.reload_model(&name);
+            }
+        }
+
+        self.rewatch();
+    }
+
+    fn reload_model(&mut self, name: &str) {
+        let Some(desc) = self.descs.iter().find(|desc| desc.name == name) else {
+            return;
+        };
+
+        match desc.load() {
+            Ok(mut model) => {
+                if let Some(old) = self.model_named(name) {
+                    model.keep_state_from(old);
+                }
+                self.place(name, model);
+                println!("Reloaded {name}");
+            }
+            Err(err) => println!("Failed to reload {name}: {err}"),
+        }
+    }
+
+    //Models whose entry didn't change are left alone, ones that only moved are updated in
+    //place. The camera stays where it is
+    fn reload_file(&mut self) {
+        let Some(filepath) = self.filepath.clone() else {
+            return;
+        };
+
+        let desc = match SceneFile::from_file(&filepath) {
+            Ok(desc) => desc,
+            Err(err) => {
+                println!("Failed to reload {filepath}: {err}");
+                return;
+            }
+        };
+
+        self.name = desc.name.clone();
+        self.camera_parent = desc
+            .camera
+            .as_ref()
+            .and_then(|camera| camera.parent.clone());
+        self.camera = desc.camera.clone();
+        self.clear_color = Vec3::from(desc.clear_color.map(f32::from));
+        self.lighting = desc.lighting();
+
+        let old_descs = std::mem::take(&mut self.descs);
+        for old in &old_descs {
+            if !desc.models.iter().any(|new| new.name == old.name) {
+                self.unplace(&old.name);
+            }
+        }
+
+        for new in desc.models {
+            let old = old_descs.iter().find(|old| old.name == new.name);
+            let model = self.model_named_mut(&new.name);
+
+            match (old, model) {
+                (Some(old), Some(_)) if *old == new => {}
+                (Some(old), Some(model)) if old.same_source(&new) => new.apply(model),
+                _ => match new.load() {
+                    Ok(model) => {
+                        self.place(&new.name, model);
+                    }
+                    Err(err) => println!("Failed to load {}: {err}", new.name),
+                },
+            }
+
+            self.descs.push(new);
+        }
+
+        self.apply_placement();
+        println!("Reloaded {filepath}");
+    }
+
+    //How often hot_reload looks at the files, the textures are polled on their own
+    pub fn set_reload_interval(&mut self, interval: std::time::Duration) {
+        self.watcher.set_interval(interval);
+    }
+
+    fn rewatch(&mut self) {
+        let model_paths = self.descs.iter().filter_map(|desc| desc.path.as_deref());
+        let sources = self
+            .models
+            .iter()
+            .flat_map(|(_, _, model)| model.sources.iter().map(String::as_str));
+
+        let paths: Vec<&str> = self
+            .filepath
+            .as_deref()
+            .into_iter()
+            .chain(model_paths)
+            .chain(sources)
+            .collect();
+        self.watcher.set_paths(paths);
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    //The scene file this was loaded from
+    pub fn filepath(&self) -> Option<&str> {
+        self.filepath.as_deref()
+    }
+
+    pub fn apply_camera(&self, camera: &mut Camera) {
+        if let Some(desc) = &self.camera {
+            desc.apply(camera);
+        }
+    }
+
+    //Saved as the starting camera next time
+    pub fn set_camera(&mut self, camera: &Camera) {
+        self.camera = Some(CameraDesc::from(camera));
+    }
+
+    //The camera's transform becomes relative to the node, None puts it back in the world
+    pub fn attach_camera(&mut self, node: Option<&str>) -> Result<(), String> {
+        if let Some(node) = node.filter(|node| self.graph.find(node).is_none()) {
+            return Err(format!("no node named {node}"));
+        }
+        self.camera_parent = node.map(str::to_string);
+        Ok(())
+    }
+
+    //Call every frame so the camera follows the node it's attached to
+    pub fn update_camera(&self, camera: &mut Camera) {
+        camera.parent = self
+            .camera_parent
+            .as_deref()
+            .and_then(|node| self.world_matrix(node))
+            .unwrap_or(Mat4::IDENTITY);
+    }
+
+    fn model_named(&self, name: &str) -> Option<&Model> {
+        self.models.get(self.models.find(name)?)
+    }
+
+    fn model_named_mut(&mut self, name: &str) -> Option<&mut Model> {
+        self.models.get_mut(self.models.find(name)?)
+    }
+
+    //Models live in a graph node of the same name, replacing a model keeps its node and
+    //its handle
+    fn place(&mut self, name: &str, mut model: Model) -> ModelHandle {
+        model.set_front_to_back(self.front_to_back);
+        self.graph.add(name, model.transform);
+        self.models.insert(name, model)
+    }
+
+    fn unplace(&mut self, name: &str) {
+        if let Some(handle) = self.models.find(name) {
+            self.models.remove(handle);
+        }
+        if let Some(id) = self.graph.find(name) {
+            self.graph.remove(id);
+        }
+    }
+
+    fn insert(&mut self, name: &str, model: Model, desc: Option<ModelDesc>) -> ModelHandle {
+        self.descs.retain(|desc| desc.name != name);
+        self.descs.extend(desc);
+        let handle = self.place(name, model);
+        self.rewatch();
+        handle
+    }
+
+    //An empty node to group models under
+    pub fn add_node(&mut self, name: &str, transform: Transform) {
+        self.graph.add(name, transform);
+    }
+
+    //Both are model or node names. The child keeps its local transform, so it moves
+    //with the parent from now on
+    pub fn attach(&mut self, child: &str, parent: &str) -> Result<(), String> {
+        let find = |name: &str| {
+            self.graph
+                .find(name)
+                .ok_or_else(|| format!("no node named {name}"))
+        };
+        let (child, parent) = (find(child)?, find(parent)?);
+        self.graph.attach(child, parent)
+    }
+
+    pub fn detach(&mut self, child: &str) {
+        if let Some(child) = self.graph.find(child) {
+            self.graph.detach(child);
+        }
+    }
+
+    pub fn parent_of(&self, name: &str) -> Option<&str> {
+        let parent = self.graph.get(self.graph.find(name)?)?.parent()?;
+        self.graph.get(parent).map(|parent| parent.name.as_str())
+    }
+
+    //Walks up from the node with the current transforms, so it's right even before the
+    //cached matrices get updated
+    pub fn world_matrix(&self, name: &str) -> Option<Mat4> {
+        let mut id = self.graph.find(name)?;
+        let mut world = Mat4::IDENTITY;
+
+        loop {
+            let node = self.graph.get(id)?;
+            let local = match self.model_named(&node.name) {
+                Some(model) => model.transform.local(),
+                None => node.transform().local(),
+            };
+            world = local * world;
+
+            match node.parent() {
+                Some(parent) => id = parent,
+                None => return Some(world),
+            }
         }
     }
 
-    pub fn add_mesh(&mut self, name: &str, mesh: VertexMesh) {
-        self.render_models.insert(
-            name.to_string(),
-            Model::from_mesh(mesh, Transform::default()),
+    //Models can be moved through get_model, the nodes pick that up here before the
+    //dirty subtrees get their world matrices recomputed
+    fn update_graph(&mut self) {
+        for (_, name, model) in self.models.iter() {
+            let Some(id) = self.graph.find(name) else {
+                continue;
+            };
+
+            if self
+                .graph
+                .get(id)
+                .is_some_and(|node| *node.transform() != model.transform)
+            {
+                self.graph.set_transform(id, model.transform);
+            }
+        }
+
+        self.graph.update();
+    }
+
+    //The node's world matrix from the last graph update
+    fn cached_world(&self, handle: ModelHandle) -> Mat4 {
+        let (Some(model), Some(name)) = (self.models.get(handle), self.models.name(handle)) else {
+            return Mat4::IDENTITY;
+        };
+
+        self.graph
+            .find(name)
+            .and_then(|id| self.graph.get(id))
+            .map_or(model.transform.local(), |node| node.world())
+    }
+
+    //Refits while the same meshes are around, models coming or going rebuild it
+    fn update_bvh(&mut self) {
+        let mut items = Vec::new();
+        let mut bounds = Vec::new();
+
+        for (handle, _, model) in self.models.iter() {
+            let world = self.cached_world(handle);
+            for (mesh_idx, mesh) in model.meshes.iter().enumerate() {
+                if mesh.is_posed() {
+                    continue;
+                }
+                if let Some(aabb) = mesh.world_bounds(&(world * model.mesh_parent(mesh))) {
+                    items.push((handle, mesh_idx));
+                    bounds.push(aabb);
+                }
+            }
+        }
+
+        if items == self.bvh_items {
+            self.bvh.refit(&bounds);
+        } else {
+            self.bvh = Bvh::build(&bounds);
+            self.bvh_items = items;
+        }
+    }
+
+    //Which BVH items are in the frustum. With a buffer, subtrees hidden behind what's
+    //already in the Hi-Z pyramid get skipped too
+    fn visible_items(&self, view_proj: &Mat4, mut hi_z: Option<&mut SlicedBuffers>) -> Vec<bool> {
+        let mut visible = vec![false; self.bvh.len()];
+
+        self.bvh.traverse(
+            |aabb| {
+                if !aabb.in_frustum(view_proj) {
+                    return false;
+                }
+
+                let Some(buffer) = hi_z.as_deref_mut() else {
+                    return true;
+                };
+                match VertexMesh::screen_bounds([aabb.min, aabb.max], view_proj) {
+                    Some((min, max, depth)) => !buffer.is_node_occluded(min, max, depth),
+                    None => true,
+                }
+            },
+            |item| visible[item as usize] = true,
         );
+
+        visible
+    }
+
+    //Skinned and morphed meshes can't be hit, their triangles move every frame
+    pub fn ray_cast(&mut self, ray: &Ray) -> Option<RayHit> {
+        self.update_graph();
+        self.update_bvh();
+
+        let (item, distance) = self.bvh.ray_cast(ray, f32::INFINITY, |item, max_t| {
+            let (handle, mesh_idx) = self.bvh_items[item as usize];
+            let model = self.models.get(handle)?;
+            let mesh = &model.meshes[mesh_idx];
+            let parent = self.cached_world(handle) * model.mesh_parent(mesh);
+            mesh.ray_cast(ray, &parent, max_t)
+        })?;
+
+        let (model, mesh) = self.bvh_items[item as usize];
+        Some(RayHit {
+            model,
+            mesh,
+            distance,
+            position: ray.at(distance),
+        })
+    }
+
+    fn apply_placement(&mut self) {
+        let parents: Vec<(String, Option<String>)> = self
+            .descs
+            .iter()
+            .filter(|desc| self.models.find(&desc.name).is_some())
+            .map(|desc| (desc.name.clone(), desc.parent.clone()))
+            .collect();
+
+        for desc in &self.descs {
+            if let Some(handle) = self.models.find(&desc.name) {
+                self.models.set_draw_order(handle, desc.draw_order);
+            }
+        }
+
+        for (name, parent) in parents {
+            match parent {
+                Some(parent) => {
+                    if let Err(err) = self.attach(&name, &parent) {
+                        println!("Failed to attach {name} to {parent}: {err}");
+                    }
+                }
+                None => self.detach(&name),
+            }
+        }
+    }
+
+    //Adding under a name that's already taken replaces that model and keeps its handle
+    pub fn add_mesh(&mut self, name: &str, mesh: VertexMesh) -> ModelHandle {
+        self.insert(name, Model::from_mesh(mesh, Transform::default()), None)
+    }
+
+    //glTF, OBJ, STL or PLY depending on the extension
+    pub fn add_model(&mut self, name: &str, filepath: &str) -> Result<ModelHandle, LoadError> {
+        let model = Model::from_file(filepath)?;
+        Ok(self.insert(name, model, Some(ModelDesc::from_path(name, filepath))))
+    }
+
+    //Loaded the way a scene file entry describes it, parent and draw order included
+    pub fn add_desc(&mut self, desc: ModelDesc) -> Result<ModelHandle, LoadError> {
+        let model = desc.load()?;
+        let name = desc.name.clone();
+        let (parent, draw_order) = (desc.parent.clone(), desc.draw_order);
+
+        let handle = self.insert(&name, model, Some(desc));
+        self.models.set_draw_order(handle, draw_order);
+        if let Some(parent) = parent {
+            if let Err(err) = self.attach(&name, &parent) {
+                println!("Failed to attach {name} to {parent}: {err}");
+            }
+        }
+        Ok(handle)
+    }
+
+    pub fn add_gltf(&mut self, name: &str, filepath: &str) -> Result<ModelHandle, LoadError> {
+        let model = Model::from_filepath(filepath)?;
+        Ok(self.insert(name, model, Some(ModelDesc::from_path(name, filepath))))
+    }
+
+    pub fn add_gltf_slice(&mut self, name: &str, bytes: &[u8]) -> Result<ModelHandle, LoadError> {
+        Ok(self.insert(name, Model::from_slice(bytes)?, None))
+    }
+
+    pub fn add_obj(&mut self, name: &str, filepath: &str) -> Result<ModelHandle, LoadError> {
+        let model = Model::from_obj(filepath)?;
+        Ok(self.insert(name, model, Some(ModelDesc::from_path(name, filepath))))
+    }
+
+    //In insertion order so exports come out the same every time
+    fn world_models(&self) -> Vec<(&str, &Model, Mat4)> {
+        self.models
+            .iter()
+            .map(|(_, name, model)| {
+                let world = self.world_matrix(name).unwrap_or(model.transform.local());
+                (name, model, world)
+            })
+            .collect()
     }
 
-    pub fn add_gltf(&mut self, name: &str, filepath: &str) {
-        self.render_models
-            .insert(name.to_string(), Model::from_filepath(filepath));
+    //.gltf (plus a .bin next to it) or .glb depending on the extension
+    pub fn export_gltf(&self, filepath: &str) -> Result<(), ExportError> {
+        gltf_exporter::write(filepath, &self.name, &self.world_models())
     }
 
-    pub fn remove_model(&mut self, name: &str) {
-        self.render_models.remove_entry(name);
+    pub fn to_glb(&self) -> Result<Vec<u8>, ExportError> {
+        gltf_exporter::to_glb(&self.name, &self.world_models())
     }
 
-    pub fn get_model(&mut self, key: &str) -> Option<&mut Model> {
-        self.render_models.get_mut(key)
+    //Its children move up to its parent. Stale handles are ignored
+    pub fn remove_model(&mut self, handle: ModelHandle) {
+        let Some(name) = self.models.name(handle).map(str::to_string) else {
+            return;
+        };
+
+        self.unplace(&name);
+        self.descs.retain(|desc| desc.name != name);
+        self.rewatch();
+    }
+
+    pub fn get_model(&mut self, handle: ModelHandle) -> Option<&mut Model> {
+        self.models.get_mut(handle)
+    }
+
+    pub fn find_model(&self, name: &str) -> Option<ModelHandle> {
+        self.models.find(name)
+    }
+
+    //How to load the model again, as it would be saved
+    pub fn model_desc(&self, name: &str) -> Option<ModelDesc> {
+        let desc = self.descs.iter().find(|desc| desc.name == name)?;
+        Some(self.current_desc(desc))
+    }
+
+    //The entry with whatever changed since it was loaded
+    fn current_desc(&self, desc: &ModelDesc) -> ModelDesc {
+        let mut desc = desc.clone();
+        if let Some(handle) = self.models.find(&desc.name) {
+            if let Some(model) = self.models.get(handle) {
+                desc.update_from(model);
+            }
+            desc.parent = self.parent_of(&desc.name).map(str::to_string);
+            desc.draw_order = self.models.draw_order(handle).unwrap_or(0);
+        }
+        desc
+    }
+
+    pub fn model_name(&self, handle: ModelHandle) -> Option<&str> {
+        self.models.name(handle)
+    }
+
+    //Lower draws first, models with the same draw order go in the order they were added
+    pub fn set_draw_order(&mut self, handle: ModelHandle, draw_order: i32) {
+        self.models.set_draw_order(handle, draw_order);
+    }
+
+    //Groups meshes with the same texture within each draw order
+    pub fn set_sort_by_texture(&mut self, enabled: bool) {
+        self.sort_by_texture = enabled;
     }
 
     pub fn change_render_mode(&mut self, window: &Window) {
         if window.get_mouse_down(MouseButton::Left) {
-            for model in &mut self.render_models {
-                model.1.next_render_mode();
+            for model in self.models.models_mut() {
+                model.next_render_mode();
             }
 
             std::thread::sleep(std::time::Duration::from_millis(100));
         }
 
         if window.get_mouse_down(MouseButton::Right) {
-            for model in &mut self.render_models {
-                model.1.prev_render_mode();
+            for model in self.models.models_mut() {
+                model.prev_render_mode();
             }
 
             std::thread::sleep(std::time::Duration::from_millis(100));
         }
     }
 
+    pub fn set_wireframe(&mut self, wireframe: Option<Wireframe>) {
+        for model in self.models.models_mut() {
+            model.set_wireframe(wireframe);
+        }
+    }
+
+    pub fn set_front_to_back(&mut self, enabled: bool) {
+        self.front_to_back = enabled;
+        for model in self.models.models_mut() {
+            model.set_front_to_back(enabled);
+        }
+    }
+
+    //Advances animations, dt is in seconds
+    pub fn update(&mut self, dt: f32) {
+        for model in self.models.models_mut() {
+            model.update(dt);
+        }
+
+        self.update_graph();
+    }
+
+    pub fn toggle_playback(&mut self) {
+        for model in self.models.models_mut() {
+            model.toggle_playback();
+        }
+    }
+
+    pub fn set_looping(&mut self, looping: bool) {
+        for model in self.models.models_mut() {
+            model.set_looping(looping);
+        }
+    }
+
+    //Moves every model's playback by delta seconds
+    pub fn seek_by(&mut self, delta: f32) {
+        for model in self.models.models_mut() {
+            model.seek(model.playback.time + delta);
+        }
+    }
+
+    pub fn next_clip(&mut self) {
+        for model in self.models.models_mut() {
+            model.next_clip();
+        }
+    }
+
     pub fn render(&mut self, buffer: &mut SlicedBuffers, camera: &Camera) {
-        for model in &self.render_models {
-            model.1.render(buffer, camera);
+        buffer.lighting.clone_from(&self.lighting);
+        self.update_graph();
+        self.update_bvh();
+
+        let items: HashMap<(ModelHandle, usize), usize> = self
+            .bvh_items
+            .iter()
+            .enumerate()
+            .map(|(item, key)| (*key, item))
+            .collect();
+
+        //Ids in the ID buffer are positions in the draw list
+        self.drawn = self.models.draw_list();
+
+        let mut meshes: Vec<MeshDraw> = Vec::new();
+        for (model_id, handle) in self.drawn.iter().enumerate() {
+            let Some(model) = self.models.get(*handle) else {
+                continue;
+            };
+
+            let world = self.cached_world(*handle);
+            let draw_order = self.models.draw_order(*handle).unwrap_or(0);
+            meshes.extend(
+                model
+                    .meshes
+                    .iter()
+                    .enumerate()
+                    .map(|(mesh_idx, mesh)| MeshDraw {
+                        draw_order,
+                        model,
+                        mesh,
+                        world,
+                        item: items.get(&(*handle, mesh_idx)).copied(),
+                        id: DrawId {
+                            model: model_id as u32,
+                            mesh: mesh_idx as u32,
+                            lod: 0, //the mesh fills in the one it picks
+                        },
+                    }),
+            );
+        }
+
+        //Stable, so equal textures keep the order they'd have had anyway
+        if self.sort_by_texture {
+            meshes.sort_by_key(|draw| (draw.draw_order, draw.mesh.texture));
+        }
+
+        let view_proj = camera.perspective() * camera.view();
+        let is_visible =
+            |visible: &[bool], draw: &MeshDraw| draw.item.is_none_or(|item| visible[item]);
+
+        //Occluders go first so the Hi-Z pyramid has their depth when the rest gets culled
+        if meshes.iter().any(|draw| draw.model.occluder) {
+            let visible = self.visible_items(&view_proj, None);
+            for draw in meshes.iter().filter(|draw| draw.model.occluder) {
+                if is_visible(&visible, draw) {
+                    draw.submit(buffer, camera);
+                }
+            }
+
+            buffer.render(camera);
+        }
+
+        let visible = self.visible_items(&view_proj, Some(buffer));
+        for draw in meshes.iter().filter(|draw| !draw.model.occluder) {
+            if is_visible(&visible, draw) {
+                draw.submit(buffer, camera);
+            }
+        }
+
+        buffer.draw_id = None;
+        buffer.render(camera);
+    }
+
+    //What's under a window position from minifb's get_mouse_pos in the last frame rendered,
+    //camera has to be the one it was rendered with. Needs the buffer's ID buffer on
+    pub fn pick(
+        &self,
+        buffer: &SlicedBuffers,
+        camera: &Camera,
+        mouse_pos: (f32, f32),
+        window_size: (usize, usize),
+    ) -> Option<Pick> {
+        let x = (mouse_pos.0 / window_size.0 as f32 * crate::BUFF_WIDTH as f32).floor();
+        let y = (mouse_pos.1 / window_size.1 as f32 * crate::BUFF_HEIGHT as f32).floor();
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+
+        let id = buffer.id_at(x as usize, y as usize)?;
+        let depth = buffer.depth_at(x as usize, y as usize)?;
+        let model = *self.drawn.get(id.model as usize)?;
+        if !self.models.contains(model) {
+            return None;
         }
+
+        //Back from the pixel center and its depth to world space
+        let ndc = Vec3::new(
+            (x + 0.5) / crate::BUFF_WIDTH as f32 * 2.0 - 1.0,
+            1.0 - (y + 0.5) / crate::BUFF_HEIGHT as f32 * 2.0,
+            depth,
+        );
+        let inv_view_proj = (camera.perspective() * camera.view()).inverse();
+
+        Some(Pick {
+            model,
+            mesh: id.mesh as usize,
+            lod: id.lod as usize,
+            primitive: id.primitive as usize,
+            position: inv_view_proj.project_point3(ndc),
+        })
+    }
+}
+
+//A mesh getting drawn this frame
+struct MeshDraw<'a> {
+    draw_order: i32,
+    model: &'a Model,
+    mesh: &'a VertexMesh,
+    world: Mat4,         //the model's node's cached world matrix
+    item: Option<usize>, //BVH item, posed meshes have none and always get submitted
+    id: DrawId,
+}
+
+impl MeshDraw<'_> {
+    fn submit(&self, buffer: &mut SlicedBuffers, camera: &Camera) {
+        buffer.draw_id = Some(self.id);
+        self.model
+            .render_mesh(buffer, camera, &self.world, self.mesh);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Vertex;
+    use crate::scene_file::Shape;
+    use glam::{Quat, Vec2, Vec4};
+
+    fn cube_at(scene: &mut Scene, name: &str, z: f32) -> ModelHandle {
+        let handle = scene.add_mesh(name, Shape::Cube.mesh());
+        scene.get_model(handle).unwrap().transform = Transform::from_translation(Vec3::Z * z);
+        handle
+    }
+
+    #[test]
+    fn ray_cast_hits_the_closest_model() {
+        let mut scene = Scene::new("Rays".to_string());
+        let near = cube_at(&mut scene, "Near", -5.0);
+        let far = cube_at(&mut scene, "Far", -10.0);
+        let ray = Ray::new(Vec3::ZERO, Vec3::NEG_Z);
+
+        let hit = scene.ray_cast(&ray).unwrap();
+        assert_eq!(hit.model, near);
+        assert!((hit.distance - 4.0).abs() < 1e-5);
+        assert!(hit.position.abs_diff_eq(Vec3::new(0.0, 0.0, -4.0), 1e-5));
+
+        //Moving a model refits, removing one rebuilds
+        scene.get_model(near).unwrap().transform = Transform::from_translation(Vec3::X * 5.0);
+        assert_eq!(scene.ray_cast(&ray).unwrap().model, far);
+
+        scene.remove_model(far);
+        assert_eq!(scene.ray_cast(&ray), None);
+
+        //Children are hit where their parent puts them
+        cube_at(&mut scene, "Child", -10.0);
+        scene.attach("Child", "Near").unwrap();
+        let sideways = Ray::new(Vec3::new(5.0, 0.0, -20.0), Vec3::Z);
+        let hit = scene.ray_cast(&sideways).unwrap();
+        assert_eq!(scene.model_name(hit.model), Some("Child"));
+        assert!((hit.distance - 9.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn models_added_later_sort_front_to_back() {
+        let mut scene = Scene::new("Sorting".to_string());
+        let before = cube_at(&mut scene, "Before", -5.0);
+        scene.set_front_to_back(true);
+        let after = cube_at(&mut scene, "After", -10.0);
+
+        for handle in [before, after] {
+            let model = scene.get_model(handle).unwrap();
+            assert!(model.meshes.iter().all(|mesh| mesh.sort_front_to_back));
+        }
+    }
+
+    //Facing +Z, two triangles
+    fn quad_at(scene: &mut Scene, name: &str, z: f32) -> ModelHandle {
+        let vertices = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+            .map(|(x, y)| Vertex::new(Vec4::new(x, y, 0.0, 1.0), Vec3::Z, Vec4::ONE, Vec2::ZERO));
+        let mesh = VertexMesh::new(&vertices, &[0, 1, 2, 0, 2, 3], None, None);
+
+        let handle = scene.add_mesh(name, mesh);
+        scene.get_model(handle).unwrap().transform = Transform::from_translation(Vec3::Z * z);
+        handle
+    }
+
+    #[test]
+    fn hi_z_after_the_pre_pass_keeps_surfaces_at_the_farthest_depth() {
+        let mut scene = Scene::new("Pre-pass".to_string());
+        quad_at(&mut scene, "Quad", -2.0);
+
+        let color = vec![0; crate::BUFF_WIDTH * crate::BUFF_HEIGHT];
+        let depth = vec![f32::INFINITY; crate::BUFF_WIDTH * crate::BUFF_HEIGHT];
+        let mut buffer = SlicedBuffers::from_buffers(&color, &depth, crate::TILE_SIZE);
+        let camera = Camera::default();
+
+        let mut shaded = |buffer: &mut SlicedBuffers| {
+            buffer.clear_depth(camera.depth_clear_value());
+            buffer.clear_stats();
+            scene.render(buffer, &camera);
+            buffer.stats
+        };
+
+        buffer.depth_prepass = true;
+        let expected = shaded(&mut buffer).fragments_shaded;
+        assert!(expected > 0);
+
+        //A flat quad facing the camera has its nearest depth at the tiles' farthest depth
+        buffer.hiz_culling = true;
+        let stats = shaded(&mut buffer);
+        assert_eq!(stats.fragments_shaded, expected);
+        assert_eq!(stats.tris_occluded, 0);
+    }
+
+    #[test]
+    fn pick_reads_the_id_buffer() {
+        let mut scene = Scene::new("Picking".to_string());
+        let quad = quad_at(&mut scene, "Quad", -5.0);
+        quad_at(&mut scene, "Hidden", -10.0);
+
+        let color = vec![0; crate::BUFF_WIDTH * crate::BUFF_HEIGHT];
+        let depth = vec![f32::INFINITY; crate::BUFF_WIDTH * crate::BUFF_HEIGHT];
+        let mut buffer = SlicedBuffers::from_buffers(&color, &depth, crate::TILE_SIZE);
+        let camera = Camera::default();
+        let window = (crate::WIN_WIDTH, crate::WIN_HEIGHT);
+        let center = (
+            crate::WIN_WIDTH as f32 * 0.5,
+            crate::WIN_HEIGHT as f32 * 0.5,
+        );
+
+        //Off by default
+        buffer.clear_depth(camera.depth_clear_value());
+        buffer.clear_ids();
+        scene.render(&mut buffer, &camera);
+        assert_eq!(scene.pick(&buffer, &camera, center, window), None);
+
+        buffer.id_buffer = true;
+        buffer.clear_depth(camera.depth_clear_value());
+        buffer.clear_ids();
+        scene.render(&mut buffer, &camera);
+
+        let pick = scene.pick(&buffer, &camera, center, window).unwrap();
+        assert_eq!(pick.model, quad);
+        assert_eq!(pick.mesh, 0);
+        assert_eq!(pick.lod, 0);
+        assert!(pick.primitive < 2);
+        assert!((pick.position.z + 5.0).abs() < 0.01, "{}", pick.position);
+        assert!(pick.position.truncate().abs_diff_eq(Vec2::ZERO, 0.05));
+
+        //Background
+        assert_eq!(scene.pick(&buffer, &camera, (1.0, 1.0), window), None);
+    }
+
+    //Where a world space point lands in the window
+    fn window_pos(camera: &Camera, point: Vec3) -> (f32, f32) {
+        let ndc = (camera.perspective() * camera.view()).project_point3(point);
+        (
+            (ndc.x * 0.5 + 0.5) * crate::WIN_WIDTH as f32,
+            (0.5 - ndc.y * 0.5) * crate::WIN_HEIGHT as f32,
+        )
+    }
+
+    #[test]
+    fn pick_reports_full_detail_across_the_near_plane() {
+        //n x n cells over [-1, 1] facing +Z, each the triangle below its diagonal then the one above
+        let n = 16;
+        let vertices: Vec<Vertex> = (0..=n)
+            .flat_map(|y| (0..=n).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let (x, y) = (
+                    x as f32 / n as f32 * 2.0 - 1.0,
+                    y as f32 / n as f32 * 2.0 - 1.0,
+                );
+                Vertex::new(Vec4::new(x, y, 0.0, 1.0), Vec3::Z, Vec4::ONE, Vec2::ZERO)
+            })
+            .collect();
+        let mut indices = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                let i = y * (n + 1) + x;
+                indices.extend([i, i + 1, i + n + 2, i, i + n + 2, i + n + 1]);
+            }
+        }
+        let mut mesh = VertexMesh::new(&vertices, &indices, None, None);
+        mesh.generate_lods(3);
+        assert!(!mesh.lods.is_empty());
+
+        let mut scene = Scene::new("Picking".to_string());
+        let grid = scene.add_mesh("Grid", mesh);
+
+        let color = vec![0; crate::BUFF_WIDTH * crate::BUFF_HEIGHT];
+        let depth = vec![f32::INFINITY; crate::BUFF_WIDTH * crate::BUFF_HEIGHT];
+        let mut buffer = SlicedBuffers::from_buffers(&color, &depth, crate::TILE_SIZE);
+        buffer.id_buffer = true;
+        let camera = Camera::default();
+        let window = (crate::WIN_WIDTH, crate::WIN_HEIGHT);
+
+        let mut pick = |scene: &mut Scene, at: Vec3| {
+            buffer.clear_depth(camera.depth_clear_value());
+            buffer.clear_ids();
+            scene.render(&mut buffer, &camera);
+            scene.pick(&buffer, &camera, window_pos(&camera, at), window)
+        };
+
+        //Far away it draws coarse
+        scene.get_model(grid).unwrap().transform = Transform::from_translation(Vec3::Z * -60.0);
+        let far = pick(&mut scene, Vec3::new(0.0, 0.0, -60.0)).unwrap();
+        assert!(far.lod > 0);
+
+        //Laid down as a floor running under the camera, its bounds can't be projected
+        let floor = Transform {
+            translation: Vec3::NEG_Y,
+            rotation: Quat::from_rotation_x(-std::f32::consts::FRAC_PI_2),
+            scale: Vec3::splat(10.0),
+        };
+        scene.get_model(grid).unwrap().transform = floor;
+
+        //A quarter along the bottom edge and up into cell (9, 12) of the full detail indices
+        let local = Vec3::new(9.75 / 8.0 - 1.0, 12.25 / 8.0 - 1.0, 0.0);
+        let near = pick(&mut scene, floor.local().transform_point3(local)).unwrap();
+        assert_eq!(near.model, grid);
+        assert_eq!(near.lod, 0);
+        assert_eq!(near.primitive, 2 * (12 * n as usize + 9));
     }
 }
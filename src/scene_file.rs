@@ -0,0 +1,540 @@
+use glam::{EulerRot, Quat, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    camera::Camera,
+    data,
+    light::{DirectionalLight, Lighting},
+    mesh::{RenderMode, VertexMesh},
+    model::{LoadError, Model},
+    tex_manager::TEXTURE_MANAGER,
+    transform::Transform,
+};
+
+//A scene as JSON. Everything but the model names can be left out, paths are relative to
+//the working directory
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct SceneFile {
+    pub name: String,
+    pub clear_color: [u8; 3],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub camera: Option<CameraDesc>,
+    pub ambient: [f32; 3],
+    pub lights: Vec<LightDesc>,
+    pub models: Vec<ModelDesc>,
+}
+
+impl Default for SceneFile {
+    fn default() -> Self {
+        let lighting = Lighting::default();
+
+        Self {
+            name: "Untitled".to_string(),
+            clear_color: [255, 0, 0],
+            camera: None,
+            ambient: lighting.ambient.to_array(),
+            lights: lighting.lights.iter().map(LightDesc::from).collect(),
+            models: Vec::new(),
+        }
+    }
+}
+
+impl SceneFile {
+    pub fn from_file(filepath: &str) -> Result<Self, LoadError> {
+        let json = std::fs::read_to_string(filepath)?;
+        serde_json::from_str(&json).map_err(|err| LoadError::Parse(format!("{filepath}: {err}")))
+    }
+
+    pub fn lighting(&self) -> Lighting {
+        Lighting {
+            ambient: Vec3::from(self.ambient),
+            lights: self.lights.iter().map(DirectionalLight::from).collect(),
+        }
+    }
+}
+
+//Angles in degrees. With a parent the position and angles are relative to that node
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct CameraDesc {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fov: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        Self::from(&Camera::default())
+    }
+}
+
+impl From<&Camera> for CameraDesc {
+    fn from(camera: &Camera) -> Self {
+        Self {
+            position: camera.transform.translation.to_array(),
+            yaw: camera.yaw.to_degrees(),
+            pitch: camera.pitch.to_degrees(),
+            fov: camera.fov.to_degrees(),
+            parent: None,
+        }
+    }
+}
+
+impl CameraDesc {
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.set_position(Vec3::from(self.position));
+        camera.yaw = self.yaw.to_radians();
+        camera.pitch = self.pitch.to_radians();
+        camera.fov = self.fov.to_radians();
+        camera.mouse_rotation(0.0, 0.0); //rebuilds the rotation from yaw and pitch
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct LightDesc {
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl Default for LightDesc {
+    fn default() -> Self {
+        Self::from(&Lighting::default().lights[0])
+    }
+}
+
+impl From<&DirectionalLight> for LightDesc {
+    fn from(light: &DirectionalLight) -> Self {
+        Self {
+            direction: light.direction.to_array(),
+            color: light.color.to_array(),
+            intensity: light.intensity,
+        }
+    }
+}
+
+impl From<&LightDesc> for DirectionalLight {
+    fn from(desc: &LightDesc) -> Self {
+        Self {
+            direction: Vec3::from(desc.direction).normalize_or_zero(),
+            color: Vec3::from(desc.color),
+            intensity: desc.intensity,
+        }
+    }
+}
+
+//The meshes from data.rs
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    Cube,
+    Triangle,
+    Plane,
+    Rhombus,
+    Pyramid,
+}
+
+impl Shape {
+    pub fn mesh(&self) -> VertexMesh {
+        let (vertices, indices): (&[data::Vertex], &[u32]) = match self {
+            Shape::Cube => (&data::CUBE_VERTICES, &data::CUBE_INDICES),
+            Shape::Triangle => (&data::PLANE_DATA, &[0, 3, 2]),
+            Shape::Plane => (&data::PLANE_DATA, &[0, 2, 1, 0, 3, 2]),
+            Shape::Rhombus => (&data::RHOMBUS_VERTICES, &data::RHOMBUS_INDEX),
+            Shape::Pyramid => (&data::PYRAMID_VERTEX, &data::PYRAMID_INDEX),
+        };
+        VertexMesh::new(vertices, indices, None, None)
+    }
+}
+
+//Rotation is XYZ euler angles in degrees
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct TransformDesc {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 3],
+    pub scale: [f32; 3],
+}
+
+impl Default for TransformDesc {
+    fn default() -> Self {
+        Self::from(&Transform::IDENTITY)
+    }
+}
+
+impl From<&Transform> for TransformDesc {
+    fn from(transform: &Transform) -> Self {
+        let (x, y, z) = transform.rotation.to_euler(EulerRot::XYZ);
+
+        Self {
+            translation: transform.translation.to_array(),
+            rotation: [x.to_degrees(), y.to_degrees(), z.to_degrees()],
+            scale: transform.scale.to_array(),
+        }
+    }
+}
+
+impl From<&TransformDesc> for Transform {
+    fn from(desc: &TransformDesc) -> Self {
+        let [x, y, z] = desc.rotation.map(f32::to_radians);
+
+        Self {
+            translation: Vec3::from(desc.translation),
+            rotation: Quat::from_euler(EulerRot::XYZ, x, y, z),
+            scale: Vec3::from(desc.scale),
+        }
+    }
+}
+
+//Replaces what the model file says for every mesh
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct MaterialDesc {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_color: Option<[f32; 4]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub texture: Option<String>,
+}
+
+//Either a model file or one of the builtin shapes. The transform is relative to the
+//parent, another model in the scene
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ModelDesc {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shape: Option<Shape>,
+    #[serde(default)]
+    pub transform: TransformDesc,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub render_mode: Option<RenderMode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub material: Option<MaterialDesc>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub occluder: bool,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub draw_order: i32, //lower draws first, ties go in file order
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub lods: usize, //levels of detail generated below the full meshes
+}
+
+fn is_zero<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+impl ModelDesc {
+    pub fn from_path(name: &str, path: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            parent: None,
+            path: Some(path.to_string()),
+            shape: None,
+            transform: TransformDesc::default(),
+            render_mode: None,
+            material: None,
+            occluder: false,
+            draw_order: 0,
+            lods: 0,
+        }
+    }
+
+    pub fn load(&self) -> Result<Model, LoadError> {
+        let mut model = match (&self.path, self.shape) {
+            (Some(path), _) => Model::from_file(path)?,
+            (None, Some(shape)) => Model::from_mesh(shape.mesh(), Transform::IDENTITY),
+            (None, None) => {
+                return Err(LoadError::Parse(format!(
+                    "model {} has neither a path nor a shape",
+                    self.name
+                )))
+            }
+        };
+
+        self.apply(&mut model);
+        if self.lods > 0 {
+            model.generate_lods(self.lods);
+        }
+
+        if let Some(material) = &self.material {
+            if let Some(color) = material.base_color {
+                model.set_base_color(Vec4::from(color));
+            }
+
+            if let Some(path) = &material.texture {
+                let mut warnings = Vec::new();
+                let tex = TEXTURE_MANAGER
+                    .write()
+                    .unwrap()
+                    .load_or_white(path, &mut warnings);
+                model.set_texture(tex);
+
+                for warning in warnings {
+                    println!("{}: {warning}", self.name);
+                    model.warnings.push(warning);
+                }
+            }
+        }
+
+        Ok(model)
+    }
+
+    //What can change without loading the model again
+    pub fn apply(&self, model: &mut Model) {
+        model.transform = Transform::from(&self.transform);
+        model.occluder = self.occluder;
+
+        if let Some(render_mode) = self.render_mode {
+            model.set_render_mode(render_mode);
+        }
+    }
+
+    //Whether both load the same meshes and textures
+    pub fn same_source(&self, other: &ModelDesc) -> bool {
+        self.path == other.path
+            && self.shape == other.shape
+            && self.material == other.material
+            && self.lods == other.lods
+    }
+
+    //Picks up whatever got changed at runtime
+    pub fn update_from(&mut self, model: &Model) {
+        self.transform = TransformDesc::from(&model.transform);
+        self.occluder = model.occluder;
+        self.lods = model.lod_levels;
+
+        if let Some(mesh) = model.meshes.first() {
+            if self.render_mode.is_some() || mesh.render_mode != RenderMode::Default {
+                self.render_mode = Some(mesh.render_mode);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scene::Scene,
+        test_utils::{temp_dir, touch},
+    };
+
+    const SCENE: &str = r#"{
+        "name": "Test",
+        "clear_color": [0, 0, 255],
+        "camera": { "position": [1.0, 2.0, 3.0], "yaw": 90.0 },
+        "lights": [],
+        "models": [
+            {
+                "name": "Box",
+                "shape": "cube",
+                "transform": { "translation": [0.0, 1.0, 0.0], "rotation": [0.0, 90.0, 0.0] },
+                "render_mode": "normal",
+                "material": { "base_color": [1.0, 0.0, 0.0, 1.0] }
+            },
+            { "name": "Wheel", "path": "resources/caspia/CaspiaWheel.gltf", "occluder": true, "draw_order": -1 },
+            { "name": "Missing", "path": "resources/missing.gltf" }
+        ]
+    }"#;
+
+    #[test]
+    fn loads_models_and_settings() {
+        let mut scene = Scene::from_desc(serde_json::from_str(SCENE).unwrap());
+
+        assert_eq!(scene.name(), "Test");
+        assert_eq!(scene.clear_color, Vec3::new(0.0, 0.0, 255.0));
+        assert!(scene.lighting.lights.is_empty());
+        assert_eq!(scene.lighting.ambient, Lighting::default().ambient);
+
+        let mut camera = Camera::default();
+        scene.apply_camera(&mut camera);
+        assert_eq!(camera.transform.translation, Vec3::new(1.0, 2.0, 3.0));
+        assert!((camera.yaw - 90f32.to_radians()).abs() < 1e-5);
+
+        let model = scene.get_model(scene.find_model("Box").unwrap()).unwrap();
+        assert_eq!(model.transform.translation, Vec3::Y);
+        assert!(
+            model
+                .transform
+                .rotation
+                .angle_between(Quat::from_rotation_y(90f32.to_radians()))
+                < 1e-5
+        );
+        assert_eq!(model.meshes[0].render_mode, RenderMode::Normal);
+        assert_eq!(
+            model.meshes[0].material.base_color,
+            Vec4::new(1.0, 0.0, 0.0, 1.0)
+        );
+
+        assert!(
+            scene
+                .get_model(scene.find_model("Wheel").unwrap())
+                .unwrap()
+                .occluder
+        );
+        assert!(scene.find_model("Missing").is_none());
+    }
+
+    #[test]
+    fn save_round_trips() {
+        let mut scene = Scene::from_desc(serde_json::from_str(SCENE).unwrap());
+        scene
+            .get_model(scene.find_model("Wheel").unwrap())
+            .unwrap()
+            .transform
+            .scale = Vec3::splat(2.0);
+        scene.add_mesh("Code", Shape::Pyramid.mesh());
+
+        let saved = scene.to_desc();
+        let json = serde_json::to_string_pretty(&saved).unwrap();
+        let reloaded: SceneFile = serde_json::from_str(&json).unwrap();
+        assert_eq!(saved, reloaded);
+
+        //Failed models stay, code built ones can't be saved
+        let names: Vec<&str> = saved.models.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["Box", "Wheel", "Missing"]);
+        assert_eq!(saved.models[1].transform.scale, [2.0; 3]);
+        assert_eq!(saved.models[0].render_mode, Some(RenderMode::Normal));
+        assert_eq!(saved.models[1].draw_order, -1);
+        assert_eq!(saved.models[0].draw_order, 0);
+        assert_eq!(saved.models[1].render_mode, None);
+        assert_eq!(saved.clear_color, [0, 0, 255]);
+        assert_eq!(saved.camera.unwrap().position, [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn hot_reloads_only_what_changed() {
+        let dir = temp_dir("scene_reload");
+        let scene_path = dir.join("scene.json");
+        let obj_path = dir.join("quad.obj");
+
+        let scene_json = |box_x: f32, extra: &str| {
+            format!(
+                r#"{{ "name": "Reload", "models": [
+                    {{ "name": "Box", "shape": "cube", "transform": {{ "translation": [{box_x}, 0, 0] }} }},
+                    {{ "name": "Quad", "path": {:?} }}{extra}
+                ] }}"#,
+                obj_path.to_str().unwrap()
+            )
+        };
+        touch(&obj_path, "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\n", 1000);
+        touch(&scene_path, &scene_json(0.0, ""), 1000);
+
+        let mut scene = Scene::from_file(scene_path.to_str().unwrap()).unwrap();
+        scene.set_reload_interval(std::time::Duration::ZERO);
+        scene
+            .get_model(scene.find_model("Quad").unwrap())
+            .unwrap()
+            .transform
+            .translation = Vec3::Y;
+
+        //The model file changed, it gets reloaded but keeps where it was moved to
+        touch(
+            &obj_path,
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n",
+            2000,
+        );
+        scene.hot_reload();
+
+        let quad = scene.get_model(scene.find_model("Quad").unwrap()).unwrap();
+        assert_eq!(quad.meshes[0].indices.len(), 6);
+        assert_eq!(quad.transform.translation, Vec3::Y);
+
+        //The scene file changed, only the edited and the new entry are touched
+        let plane = r#", { "name": "Plane", "shape": "plane" }"#;
+        touch(&scene_path, &scene_json(5.0, plane), 2000);
+        scene.hot_reload();
+
+        assert_eq!(
+            scene
+                .get_model(scene.find_model("Box").unwrap())
+                .unwrap()
+                .transform
+                .translation,
+            Vec3::X * 5.0
+        );
+        assert_eq!(
+            scene
+                .get_model(scene.find_model("Quad").unwrap())
+                .unwrap()
+                .transform
+                .translation,
+            Vec3::Y
+        );
+        assert!(scene.find_model("Plane").is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parents_models_and_camera() {
+        //Children can come before their parent in the file
+        let json = r#"{
+            "camera": { "position": [0.0, 1.0, 0.0], "parent": "Car" },
+            "models": [
+                { "name": "Wheel", "shape": "cube", "parent": "Car",
+                  "transform": { "translation": [1.0, 0.0, 0.0] } },
+                { "name": "Car", "shape": "cube",
+                  "transform": { "translation": [10.0, 0.0, 0.0] } }
+            ]
+        }"#;
+        let mut scene = Scene::from_desc(serde_json::from_str(json).unwrap());
+        assert_eq!(scene.parent_of("Wheel"), Some("Car"));
+
+        scene
+            .get_model(scene.find_model("Car").unwrap())
+            .unwrap()
+            .transform
+            .translation = Vec3::X * 20.0;
+        assert_eq!(scene.world_matrix("Wheel").unwrap().w_axis.x, 21.0);
+
+        let mut camera = Camera::default();
+        scene.apply_camera(&mut camera);
+        scene.update_camera(&mut camera);
+        assert_eq!(camera.parent.w_axis.x, 20.0);
+        assert_eq!(
+            camera.view().transform_point3(Vec3::new(20.0, 1.0, 0.0)),
+            Vec3::ZERO
+        );
+
+        let saved = scene.to_desc();
+        assert_eq!(saved.models[0].parent.as_deref(), Some("Car"));
+        assert_eq!(saved.camera.unwrap().parent.as_deref(), Some("Car"));
+
+        assert!(scene.attach("Car", "Wheel").is_err());
+        scene.remove_model(scene.find_model("Car").unwrap());
+        assert_eq!(scene.parent_of("Wheel"), None);
+        assert_eq!(scene.world_matrix("Wheel").unwrap().w_axis.x, 1.0);
+    }
+
+    #[test]
+    fn rejects_models_without_source() {
+        let desc: ModelDesc = serde_json::from_str(r#"{ "name": "Nothing" }"#).unwrap();
+        assert!(matches!(desc.load(), Err(LoadError::Parse(_))));
+        assert!(serde_json::from_str::<SceneFile>(r#"{ "models": [{}] }"#).is_err());
+    }
+
+    #[test]
+    fn keeps_lods_through_save_and_respawn() {
+        let json = r#"{ "models": [ { "name": "Box", "shape": "cube", "occluder": true } ] }"#;
+        let mut scene = Scene::from_desc(serde_json::from_str(json).unwrap());
+        let handle = scene.find_model("Box").unwrap();
+        scene.get_model(handle).unwrap().generate_lods(2);
+        assert_eq!(scene.to_desc().models[0].lods, 2);
+
+        //Taken out and put back the way it was described
+        let desc = scene.model_desc("Box").unwrap();
+        scene.remove_model(handle);
+        let handle = scene.add_desc(desc).unwrap();
+        let model = scene.get_model(handle).unwrap();
+        assert_eq!(model.lod_levels, 2);
+        assert!(model.occluder);
+    }
+}
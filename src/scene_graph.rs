@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+
+use glam::Mat4;
+
+use crate::transform::Transform;
+
+pub type NodeId = usize;
+
+pub struct SceneNode {
+    pub name: String,
+    transform: Transform, //relative to the parent
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    world: Mat4, //cached, valid after SceneGraph::update
+    dirty: bool, //the world matrix of this node and everything below it is stale
+}
+
+impl SceneNode {
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    pub fn world(&self) -> Mat4 {
+        self.world
+    }
+}
+
+//Named nodes with arbitrary nesting. Ids of removed nodes are reused
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<Option<SceneNode>>,
+    free: Vec<NodeId>,
+    roots: Vec<NodeId>,
+    names: HashMap<String, NodeId>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //Replaces the transform of a node with the same name instead of adding another one
+    pub fn add(&mut self, name: &str, transform: Transform) -> NodeId {
+        if let Some(id) = self.find(name) {
+            self.set_transform(id, transform);
+            return id;
+        }
+
+        let node = SceneNode {
+            name: name.to_string(),
+            transform,
+            parent: None,
+            children: Vec::new(),
+            world: transform.local(),
+            dirty: true,
+        };
+
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.nodes[id] = Some(node);
+                id
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+
+        self.roots.push(id);
+        self.names.insert(name.to_string(), id);
+        id
+    }
+
+    //The children move up to the removed node's parent, keeping their local transforms
+    pub fn remove(&mut self, id: NodeId) {
+        let Some(node) = self.nodes.get_mut(id).and_then(Option::take) else {
+            return;
+        };
+
+        self.unlink(id, node.parent);
+        for child in &node.children {
+            self.link(*child, node.parent);
+        }
+
+        self.names.remove(&node.name);
+        self.free.push(id);
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.names.get(name).copied()
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&SceneNode> {
+        self.nodes.get(id).and_then(Option::as_ref)
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn set_transform(&mut self, id: NodeId, transform: Transform) {
+        if let Some(node) = self.nodes.get_mut(id).and_then(Option::as_mut) {
+            node.transform = transform;
+            node.dirty = true;
+        }
+    }
+
+    //Fails if either node doesn't exist or the parent is the child or below it
+    pub fn attach(&mut self, child: NodeId, parent: NodeId) -> Result<(), String> {
+        let (Some(child_node), Some(parent_node)) = (self.get(child), self.get(parent)) else {
+            return Err("no such node".to_string());
+        };
+
+        if self.is_descendant(parent, child) {
+            return Err(format!(
+                "{} can't be attached to {}, it's above it",
+                child_node.name, parent_node.name
+            ));
+        }
+
+        let old_parent = child_node.parent;
+        self.unlink(child, old_parent);
+        self.link(child, Some(parent));
+        Ok(())
+    }
+
+    //Makes the node a root again, its local transform becomes its world transform
+    pub fn detach(&mut self, child: NodeId) {
+        if let Some(parent) = self.get(child).and_then(|node| node.parent) {
+            self.unlink(child, Some(parent));
+            self.link(child, None);
+        }
+    }
+
+    //Whether node is ancestor or somewhere below it
+    fn is_descendant(&self, mut node: NodeId, ancestor: NodeId) -> bool {
+        loop {
+            if node == ancestor {
+                return true;
+            }
+            match self.get(node).and_then(|node| node.parent) {
+                Some(parent) => node = parent,
+                None => return false,
+            }
+        }
+    }
+
+    fn unlink(&mut self, id: NodeId, parent: Option<NodeId>) {
+        let siblings = match parent.and_then(|parent| self.nodes[parent].as_mut()) {
+            Some(parent) => &mut parent.children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|sibling| *sibling != id);
+    }
+
+    fn link(&mut self, id: NodeId, parent: Option<NodeId>) {
+        match parent.and_then(|parent| self.nodes[parent].as_mut()) {
+            Some(parent_node) => parent_node.children.push(id),
+            None => self.roots.push(id),
+        }
+
+        if let Some(node) = self.nodes[id].as_mut() {
+            node.parent = parent;
+            node.dirty = true;
+        }
+    }
+
+    //Recomputes the world matrices below dirty nodes, clean subtrees cost just the walk
+    pub fn update(&mut self) {
+        let mut stack: Vec<(NodeId, Mat4, bool)> = self
+            .roots
+            .iter()
+            .map(|root| (*root, Mat4::IDENTITY, false))
+            .collect();
+
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let Some(node) = self.nodes[id].as_mut() else {
+                continue;
+            };
+
+            let changed = node.dirty || parent_changed;
+            if changed {
+                node.world = parent_world * node.transform.local();
+                node.dirty = false;
+            }
+
+            let world = node.world;
+            stack.extend(node.children.iter().map(|child| (*child, world, changed)));
+        }
+    }
+
+    //Depth first, parents before their children
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &SceneNode)> {
+        let mut stack: Vec<NodeId> = self.roots.iter().rev().copied().collect();
+
+        std::iter::from_fn(move || {
+            let id = stack.pop()?;
+            let node = self.nodes[id].as_ref()?;
+            stack.extend(node.children.iter().rev());
+            Some((id, node))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{Quat, Vec3};
+
+    fn translation(x: f32) -> Transform {
+        Transform::from_translation(Vec3::X * x)
+    }
+
+    fn world_x(graph: &SceneGraph, id: NodeId) -> f32 {
+        graph.get(id).unwrap().world().w_axis.x
+    }
+
+    #[test]
+    fn nested_world_matrices() {
+        let mut graph = SceneGraph::new();
+        let car = graph.add("Car", translation(10.0));
+        let wheel = graph.add("Wheel", translation(1.0));
+        let bolt = graph.add("Bolt", translation(0.5));
+        graph.attach(wheel, car).unwrap();
+        graph.attach(bolt, wheel).unwrap();
+        graph.update();
+
+        assert_eq!(world_x(&graph, bolt), 11.5);
+        assert_eq!(graph.roots(), [car]);
+
+        //Moving the parent carries the whole subtree
+        graph.set_transform(car, translation(20.0));
+        graph.update();
+        assert_eq!(world_x(&graph, wheel), 21.0);
+        assert_eq!(world_x(&graph, bolt), 21.5);
+
+        //Rotation applies to the children's offsets
+        let turned = Transform::from_rotation_quat(Quat::from_rotation_z(90f32.to_radians()));
+        graph.set_transform(car, turned);
+        graph.update();
+        let bolt_pos = graph.get(bolt).unwrap().world().w_axis.truncate();
+        assert!(bolt_pos.abs_diff_eq(Vec3::Y * 1.5, 1e-5));
+
+        let order: Vec<&str> = graph.iter().map(|(_, node)| node.name.as_str()).collect();
+        assert_eq!(order, ["Car", "Wheel", "Bolt"]);
+    }
+
+    #[test]
+    fn attach_detach_and_remove() {
+        let mut graph = SceneGraph::new();
+        let car = graph.add("Car", translation(10.0));
+        let wheel = graph.add("Wheel", translation(1.0));
+        let bolt = graph.add("Bolt", translation(0.5));
+        graph.attach(wheel, car).unwrap();
+        graph.attach(bolt, wheel).unwrap();
+
+        assert!(graph.attach(car, bolt).is_err());
+        assert!(graph.attach(car, car).is_err());
+
+        graph.detach(wheel);
+        graph.update();
+        assert_eq!(world_x(&graph, bolt), 1.5);
+
+        //The bolt moves up to where the wheel was
+        graph.attach(wheel, car).unwrap();
+        graph.remove(wheel);
+        graph.update();
+        assert_eq!(graph.get(bolt).unwrap().parent(), Some(car));
+        assert_eq!(world_x(&graph, bolt), 10.5);
+        assert_eq!(graph.find("Wheel"), None);
+
+        //Freed ids get reused
+        assert_eq!(graph.add("Spoiler", translation(0.0)), wheel);
+    }
+}
@@ -0,0 +1,364 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use glam::{DVec3, Vec3};
+
+//Sum of squared distances to a set of planes. Symmetric, so only the upper triangle of
+//the 4x4 matrix gets stored
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    //Plane through point with a unit normal, weighted by the area it came from
+    fn plane(normal: DVec3, point: DVec3, weight: f64) -> Self {
+        let [a, b, c] = normal.to_array();
+        let d = -normal.dot(point);
+        Self([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+        .scaled(weight)
+    }
+
+    fn scaled(mut self, weight: f64) -> Self {
+        self.0.iter_mut().for_each(|value| *value *= weight);
+        self
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (value, other) in self.0.iter_mut().zip(other.0) {
+            *value += other;
+        }
+    }
+
+    fn error(&self, p: DVec3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+
+        q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9]
+    }
+}
+
+//Moving from onto to, cheapest pops first. Stale once either vertex changed since
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| (other.from, other.to).cmp(&(self.from, self.to)))
+    }
+}
+
+struct Simplifier<'a> {
+    positions: &'a [Vec3],
+    triangles: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    vertex_tris: Vec<Vec<usize>>, //can hold dead triangles, check alive
+    quadrics: Vec<Quadric>,
+    versions: Vec<u32>,
+    locked: Vec<bool>, //on a border or an attribute seam, moving it would open a crack
+    removed: Vec<bool>,
+    heap: BinaryHeap<Collapse>,
+}
+
+//Quadric error edge collapses (Garland & Heckbert) down to about target triangles.
+//Vertices only ever move onto other vertices, so the result indexes the same vertex
+//buffer. Vertices on borders stay put, attribute seams are borders too since the
+//vertices on either side are different ones
+pub fn simplify(positions: &[Vec3], indices: &[u32], target: usize) -> Vec<u32> {
+    let mut simplifier = Simplifier::new(positions, indices);
+    simplifier.run(target);
+    simplifier.indices()
+}
+
+impl<'a> Simplifier<'a> {
+    fn new(positions: &'a [Vec3], indices: &[u32]) -> Self {
+        let triangles: Vec<[u32; 3]> = indices
+            .chunks_exact(3)
+            .map(|tri| [tri[0], tri[1], tri[2]])
+            .filter(|[a, b, c]| a != b && b != c && a != c)
+            .collect();
+
+        let mut vertex_tris = vec![Vec::new(); positions.len()];
+        let mut quadrics = vec![Quadric::default(); positions.len()];
+        let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+
+        for (tri_idx, tri) in triangles.iter().enumerate() {
+            let [a, b, c] = tri.map(|v| positions[v as usize].as_dvec3());
+            let cross = (b - a).cross(c - a);
+            let area = cross.length() * 0.5;
+
+            if area > 0.0 {
+                let plane = Quadric::plane(cross.normalize(), a, area);
+                tri.iter().for_each(|v| quadrics[*v as usize].add(&plane));
+            }
+
+            for i in 0..3 {
+                vertex_tris[tri[i] as usize].push(tri_idx);
+                let (v0, v1) = (tri[i], tri[(i + 1) % 3]);
+                *edges.entry((v0.min(v1), v0.max(v1))).or_default() += 1;
+            }
+        }
+
+        //Anything but two triangles per edge is a border or non-manifold
+        let mut locked = vec![false; positions.len()];
+        for ((v0, v1), count) in &edges {
+            if *count != 2 {
+                locked[*v0 as usize] = true;
+                locked[*v1 as usize] = true;
+            }
+        }
+
+        let mut simplifier = Self {
+            positions,
+            alive: vec![true; triangles.len()],
+            triangles,
+            vertex_tris,
+            quadrics,
+            versions: vec![0; positions.len()],
+            locked,
+            removed: vec![false; positions.len()],
+            heap: BinaryHeap::new(),
+        };
+
+        let mut keys: Vec<(u32, u32)> = edges.into_keys().collect();
+        keys.sort_unstable();
+        for (v0, v1) in keys {
+            simplifier.push(v0, v1);
+            simplifier.push(v1, v0);
+        }
+
+        simplifier
+    }
+
+    fn push(&mut self, from: u32, to: u32) {
+        if self.locked[from as usize] {
+            return;
+        }
+
+        let mut quadric = self.quadrics[from as usize];
+        quadric.add(&self.quadrics[to as usize]);
+
+        self.heap.push(Collapse {
+            cost: quadric.error(self.positions[to as usize].as_dvec3()),
+            from,
+            to,
+            versions: (self.versions[from as usize], self.versions[to as usize]),
+        });
+    }
+
+    fn alive_tris(&self, vertex: u32) -> impl Iterator<Item = usize> + '_ {
+        self.vertex_tris[vertex as usize]
+            .iter()
+            .copied()
+            .filter(|tri| self.alive[*tri])
+    }
+
+    fn neighbors(&self, vertex: u32) -> Vec<u32> {
+        let mut neighbors: Vec<u32> = self
+            .alive_tris(vertex)
+            .flat_map(|tri| self.triangles[tri])
+            .filter(|v| *v != vertex)
+            .collect();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+        neighbors
+    }
+
+    //Collapsing must not fold triangles over or pinch the surface into a non-manifold
+    //edge, which happens when the two share more neighbors than triangles
+    fn can_collapse(&self, from: u32, to: u32) -> bool {
+        let to_neighbors = self.neighbors(to);
+        let shared = self
+            .neighbors(from)
+            .iter()
+            .filter(|v| to_neighbors.binary_search(v).is_ok())
+            .count();
+        let shared_tris = self
+            .alive_tris(from)
+            .filter(|tri| self.triangles[*tri].contains(&to))
+            .count();
+        if shared != shared_tris {
+            return false;
+        }
+
+        let target = self.positions[to as usize];
+        self.alive_tris(from)
+            .filter(|tri| !self.triangles[*tri].contains(&to))
+            .all(|tri| {
+                let tri = self.triangles[tri];
+                let before = tri.map(|v| self.positions[v as usize]);
+                let after = tri.map(|v| {
+                    if v == from {
+                        target
+                    } else {
+                        self.positions[v as usize]
+                    }
+                });
+
+                let normal_before = (before[1] - before[0]).cross(before[2] - before[0]);
+                let normal_after = (after[1] - after[0]).cross(after[2] - after[0]);
+                normal_after.dot(normal_before)
+                    > 0.2 * normal_after.length() * normal_before.length()
+            })
+    }
+
+    fn run(&mut self, target: usize) {
+        let mut triangle_count = self.triangles.len();
+
+        while triangle_count > target {
+            let Some(Collapse {
+                from, to, versions, ..
+            }) = self.heap.pop()
+            else {
+                break;
+            };
+
+            let stale = self.removed[from as usize]
+                || self.removed[to as usize]
+                || versions != (self.versions[from as usize], self.versions[to as usize]);
+            if stale || !self.can_collapse(from, to) {
+                continue;
+            }
+
+            let from_tris: Vec<usize> = self.alive_tris(from).collect();
+            for tri in from_tris {
+                let vertices = &mut self.triangles[tri];
+                if vertices.contains(&to) {
+                    self.alive[tri] = false;
+                    triangle_count -= 1;
+                } else {
+                    vertices.iter_mut().for_each(|v| {
+                        if *v == from {
+                            *v = to;
+                        }
+                    });
+                    self.vertex_tris[to as usize].push(tri);
+                }
+            }
+
+            let from_quadric = self.quadrics[from as usize];
+            self.quadrics[to as usize].add(&from_quadric);
+            self.removed[from as usize] = true;
+            self.versions[to as usize] += 1;
+
+            for neighbor in self.neighbors(to) {
+                self.push(to, neighbor);
+                self.push(neighbor, to);
+            }
+        }
+    }
+
+    //The remaining triangles in their original order
+    fn indices(&self) -> Vec<u32> {
+        self.triangles
+            .iter()
+            .zip(&self.alive)
+            .filter(|(_, alive)| **alive)
+            .flat_map(|(tri, _)| *tri)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //n x n quads on the xz plane, bumped up along a ridge at x = 0.5
+    fn grid(n: u32, ridge: f32) -> (Vec<Vec3>, Vec<u32>) {
+        let positions = (0..=n)
+            .flat_map(|z| (0..=n).map(move |x| (x, z)))
+            .map(|(x, z)| {
+                let (x, z) = (x as f32 / n as f32, z as f32 / n as f32);
+                Vec3::new(x, (0.5 - (x - 0.5).abs()) * ridge, z)
+            })
+            .collect();
+
+        let mut indices = Vec::new();
+        for z in 0..n {
+            for x in 0..n {
+                let i = z * (n + 1) + x;
+                indices.extend([i, i + n + 1, i + 1, i + 1, i + n + 1, i + n + 2]);
+            }
+        }
+
+        (positions, indices)
+    }
+
+    fn normal(positions: &[Vec3], tri: &[u32]) -> Vec3 {
+        let [a, b, c] = [0, 1, 2].map(|i| positions[tri[i] as usize]);
+        (b - a).cross(c - a)
+    }
+
+    #[test]
+    fn flat_grid_collapses_to_its_border() {
+        let (positions, indices) = grid(8, 0.0);
+        let simplified = simplify(&positions, &indices, 0);
+
+        //Only the border vertices are left, and none of the triangles flipped
+        assert!(simplified.len() / 3 < indices.len() / 3 / 3);
+        for v in &simplified {
+            let p = positions[*v as usize];
+            assert!(p.x == 0.0 || p.x == 1.0 || p.z == 0.0 || p.z == 1.0, "{p}");
+        }
+        for tri in simplified.chunks_exact(3) {
+            assert!(normal(&positions, tri).y > 0.0);
+        }
+    }
+
+    #[test]
+    fn keeps_features_and_hits_the_target() {
+        let (positions, indices) = grid(16, 1.0);
+        let simplified = simplify(&positions, &indices, 128);
+        assert!(simplified.len() / 3 <= 128);
+
+        //The ridge is the most expensive thing to lose, its top vertices have to stay
+        let ridge = |v: &u32| (positions[*v as usize].x - 0.5).abs() < 1e-6;
+        let ridge_before = indices.iter().filter(|v| ridge(v)).count();
+        let ridge_after = simplified.iter().filter(|v| ridge(v)).count();
+        assert!(ridge_before > 0 && ridge_after > 0);
+
+        //Asking for more than there is changes nothing
+        assert_eq!(simplify(&positions, &indices, usize::MAX), indices);
+    }
+}
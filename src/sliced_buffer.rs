@@ -1,19 +1,263 @@
 use std::sync::Arc;
 
 use glam::{IVec2, Vec2};
-use rayon::prelude::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use rayon::prelude::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator,
+    ParallelSlice,
+};
 
 use crate::{
-    camera::Camera, material::Material, mesh::RenderMode, texture::Texture, triangle::Triangle,
+    camera::Camera,
+    hi_z::HiZ,
+    light::Lighting,
+    line::Line,
+    material::Material,
+    mesh::{RenderMode, Wireframe},
+    point::Point,
+    texture::Texture,
+    triangle::Triangle,
 };
 
+//Smallest amount of primitives a single binning task works on
+const BIN_CHUNK_MIN: usize = 256;
+
+//Anything in screen space that can be binned into tiles
+pub trait Primitive {
+    fn aabb(&self) -> [Vec2; 2];
+    //Closest NDC depth of the primitive, used for sorting front to back and Hi-Z culling
+    fn nearest_depth(&self, compare: DepthCompare) -> f32;
+}
+
+//Model and mesh a draw came from, numbered however the submitter likes, and the level of
+//detail the mesh drew
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DrawId {
+    pub model: u32,
+    pub mesh: u32,
+    pub lod: u32,
+}
+
+impl DrawId {
+    pub fn pixel(&self, primitive: u32) -> PixelId {
+        PixelId {
+            model: self.model,
+            mesh: self.mesh,
+            lod: self.lod,
+            primitive,
+        }
+    }
+}
+
+//What the ID buffer stores for a pixel, primitive is the triangle, line or point idx
+//within the indices of that level of detail
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PixelId {
+    pub model: u32,
+    pub mesh: u32,
+    pub lod: u32,
+    pub primitive: u32,
+}
+
+//Screen space primitives of one kind and the draw every one of them belongs to
+pub struct PrimitiveBuffer<T> {
+    pub prims: Vec<T>,
+    pub draws: Vec<u32>, //idx into draws for every primitive
+}
+
+impl<T> PrimitiveBuffer<T> {
+    fn new() -> Self {
+        Self {
+            prims: Vec::new(),
+            draws: Vec::new(),
+        }
+    }
+
+    fn append(&mut self, prims: &mut Vec<T>, draw_idx: u32) {
+        self.draws.resize(self.draws.len() + prims.len(), draw_idx);
+        self.prims.append(prims);
+    }
+
+    fn clear(&mut self) {
+        self.prims.clear();
+        self.draws.clear();
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DepthCompare {
+    Less,
+    LessEqual,
+    Greater, //reversed-Z
+    GreaterEqual,
+    Equal,
+    Always,
+}
+
+impl DepthCompare {
+    pub fn test(&self, depth: f32, stored: f32) -> bool {
+        match self {
+            DepthCompare::Less => depth < stored,
+            DepthCompare::LessEqual => depth <= stored,
+            DepthCompare::Greater => depth > stored,
+            DepthCompare::GreaterEqual => depth >= stored,
+            DepthCompare::Equal => depth == stored,
+            DepthCompare::Always => true,
+        }
+    }
+
+    //Larger depth is closer to the camera with reversed-Z
+    fn is_reversed(&self) -> bool {
+        matches!(self, DepthCompare::Greater | DepthCompare::GreaterEqual)
+    }
+
+    pub fn nearest(&self, a: f32, b: f32) -> f32 {
+        if self.is_reversed() {
+            a.max(b)
+        } else {
+            a.min(b)
+        }
+    }
+
+    pub fn farthest(&self, a: f32, b: f32) -> f32 {
+        if self.is_reversed() {
+            a.min(b)
+        } else {
+            a.max(b)
+        }
+    }
+
+    //Orders depths from closest to farthest
+    pub fn front_to_back(&self, a: f32, b: f32) -> std::cmp::Ordering {
+        if self.is_reversed() {
+            b.total_cmp(&a)
+        } else {
+            a.total_cmp(&b)
+        }
+    }
+
+    //Would something with this nearest depth fail the test everywhere behind the farthest depth?
+    pub fn occludes(&self, nearest: f32, farthest: f32) -> bool {
+        match self {
+            DepthCompare::Equal | DepthCompare::Always => false,
+            _ => !self.test(nearest, farthest),
+        }
+    }
+
+    //Strictly behind the farthest depth, so it can't even pass an equal test against it
+    pub fn is_behind(&self, nearest: f32, farthest: f32) -> bool {
+        self.nearest(nearest, farthest) != nearest
+    }
+}
+
+//Precision the depth gets stored with, f32 or emulated fixed point formats
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DepthFormat {
+    F32,
+    Unorm24,
+    Unorm16,
+}
+
+impl DepthFormat {
+    pub fn quantize(&self, depth: f32) -> f32 {
+        let max = match self {
+            DepthFormat::F32 => return depth,
+            DepthFormat::Unorm24 => 16_777_215.0,
+            DepthFormat::Unorm16 => 65_535.0,
+        };
+
+        (depth.clamp(0.0, 1.0) * max).round() / max
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct DepthState {
+    pub compare: DepthCompare,
+    pub format: DepthFormat,
+    pub write: bool,
+}
+
+impl Default for DepthState {
+    fn default() -> Self {
+        Self {
+            compare: DepthCompare::Less,
+            format: DepthFormat::F32,
+            write: true,
+        }
+    }
+}
+
+#[derive(Default, Clone, Copy, Debug)]
+pub struct FrameStats {
+    pub triangles: u64,
+    pub lines: u64,
+    pub points: u64,
+    pub depth_fragments: u64,  //fragments rasterized in the depth pre-pass
+    pub fragments_tested: u64, //fragments that went through the depth test while shading
+    pub fragments_shaded: u64, //fragments that passed it and got shaded
+    pub meshes_tested: u64,    //meshes tested against the Hi-Z pyramid
+    pub meshes_occluded: u64,  //meshes skipped because the Hi-Z pyramid hides them
+    pub nodes_tested: u64,     //scene BVH nodes tested against the Hi-Z pyramid
+    pub nodes_occluded: u64,   //scene BVH nodes whose whole subtree got skipped
+    pub bins_occluded: u64,    //triangle/tile pairs not binned because of the tile's farthest depth
+    pub tris_occluded: u64,    //triangles skipped in the shading pass after the depth pre-pass
+}
+
+impl FrameStats {
+    pub fn shaded_per_pixel(&self) -> f32 {
+        self.fragments_shaded as f32 / (crate::BUFF_WIDTH * crate::BUFF_HEIGHT) as f32
+    }
+
+    fn add(&mut self, other: &FrameStats) {
+        self.triangles += other.triangles;
+        self.lines += other.lines;
+        self.points += other.points;
+        self.depth_fragments += other.depth_fragments;
+        self.fragments_tested += other.fragments_tested;
+        self.fragments_shaded += other.fragments_shaded;
+        self.meshes_tested += other.meshes_tested;
+        self.meshes_occluded += other.meshes_occluded;
+        self.nodes_tested += other.nodes_tested;
+        self.nodes_occluded += other.nodes_occluded;
+        self.bins_occluded += other.bins_occluded;
+        self.tris_occluded += other.tris_occluded;
+    }
+}
+
+impl std::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Triangles: {} | Lines: {} | Points: {} | Depth fragments: {} | Tested: {} | Shaded: {} ({:.2} per pixel) | \
+            Occluded meshes: {}/{} | Occluded nodes: {}/{} | Occluded bins: {} | Occluded triangles: {}",
+            self.triangles,
+            self.lines,
+            self.points,
+            self.depth_fragments,
+            self.fragments_tested,
+            self.fragments_shaded,
+            self.shaded_per_pixel(),
+            self.meshes_occluded,
+            self.meshes_tested,
+            self.nodes_occluded,
+            self.nodes_tested,
+            self.bins_occluded,
+            self.tris_occluded
+        )
+    }
+}
+
 pub struct Tile {
-    pub pos: IVec2,           //pos of top left pixel of the tile
-    pub size: IVec2,          //size of tile in pixels
-    pub idx: IVec2,           //index of the tile
-    pub depth_data: Vec<f32>, //data to get written to in fragment shader
-    pub color_data: Vec<u32>, //data to get written to in fragment shader
-    pub tri_idx: Vec<u32>,    //indices from the triangle buffer to render
+    pub pos: IVec2,                    //pos of top left pixel of the tile
+    pub size: IVec2,                   //size of tile in pixels
+    pub idx: IVec2,                    //index of the tile
+    pub depth_data: Vec<f32>,          //data to get written to in fragment shader
+    pub color_data: Vec<u32>,          //data to get written to in fragment shader
+    pub id_data: Vec<Option<PixelId>>, //empty unless the ID buffer is on
+    pub tri_idx: Vec<u32>,             //indices from the triangle buffer to render
+    pub line_idx: Vec<u32>,            //indices from the line buffer to render
+    pub point_idx: Vec<u32>,           //indices from the point buffer to render
+    pub stats: FrameStats,             //counters of the last render, summed up by the SlicedBuffers
+    pub far_depth: f32,                //conservative farthest depth in the tile, for Hi-Z culling
 }
 
 impl Tile {
@@ -34,7 +278,12 @@ impl Tile {
             idx,
             color_data: vec![0; (size.x * size.y) as usize],
             depth_data: vec![0.0; (size.x * size.y) as usize],
+            id_data: Vec::new(),
             tri_idx: Vec::new(),
+            line_idx: Vec::new(),
+            point_idx: Vec::new(),
+            stats: FrameStats::default(),
+            far_depth: f32::INFINITY,
         }
     }
 
@@ -51,78 +300,276 @@ impl Tile {
         self.color_data.fill(val);
     }
 
+    pub fn clear_buffers_id(&mut self, enabled: bool) {
+        self.id_data.clear();
+        if enabled {
+            self.id_data
+                .resize((self.size.x * self.size.y) as usize, None);
+        }
+    }
+
     pub fn clear_buffers_depth(&mut self, val: f32) {
         self.depth_data.fill(val);
+        self.far_depth = val;
+    }
+
+    fn update_far_depth(&mut self, compare: DepthCompare) {
+        self.far_depth = self
+            .depth_data
+            .iter()
+            .copied()
+            .reduce(|farthest, depth| compare.farthest(farthest, depth))
+            .unwrap_or(self.far_depth);
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn render(
         &mut self,
-        tri_buff: &[Triangle],
+        tri_buff: &PrimitiveBuffer<Triangle>,
+        line_buff: &PrimitiveBuffer<Line>,
+        point_buff: &PrimitiveBuffer<Point>,
+        draws: &[DrawState],
         camera: &Camera,
-        render_type: &RenderMode,
-        texture: Option<&Arc<Texture>>,
-        material: &Material,
+        depth_state: DepthState,
+        lighting: &Lighting,
+        depth_prepass: bool,
+        hiz_culling: bool,
     ) {
+        let mut shade_state = depth_state;
+
+        if depth_prepass {
+            for i in self.tri_idx.iter() {
+                //Only the edges of a wireframe get drawn, the faces mustn't hide anything
+                let draw = &draws[tri_buff.draws[*i as usize] as usize];
+                if let RenderMode::Wireframe = draw.render_mode {
+                    continue;
+                }
+
+                tri_buff.prims[*i as usize].render_depth(
+                    self.pos,
+                    self.size,
+                    self.depth_data.as_mut_slice(),
+                    depth_state,
+                    &mut self.stats,
+                );
+            }
+
+            //Shading after a depth pre-pass, depth is already final
+            shade_state.compare = DepthCompare::Equal;
+            shade_state.write = false;
+            self.update_far_depth(depth_state.compare);
+        }
+
+        let mut ids = (!self.id_data.is_empty()).then_some(self.id_data.as_mut_slice());
+
+        //Triangles are stored in submission order, so draws stay in order per tile
         for i in self.tri_idx.iter() {
-            tri_buff[*i as usize].render_fragments(
+            //After the pre-pass anything behind the farthest depth can't pass the equal test,
+            //anything at it still can
+            if depth_prepass && hiz_culling {
+                let nearest = depth_state
+                    .format
+                    .quantize(tri_buff.prims[*i as usize].nearest_depth(depth_state.compare));
+                if depth_state.compare.is_behind(nearest, self.far_depth) {
+                    self.stats.tris_occluded += 1;
+                    continue;
+                }
+            }
+
+            let draw = &draws[tri_buff.draws[*i as usize] as usize];
+
+            //Wireframes skipped the pre-pass, so they test and write depth themselves
+            let state = match draw.render_mode {
+                RenderMode::Wireframe => depth_state,
+                _ => shade_state,
+            };
+
+            tri_buff.prims[*i as usize].render_fragments(
                 self.pos,
                 self.size,
                 self.color_data.as_mut_slice(),
                 self.depth_data.as_mut_slice(),
-                texture,
-                material,
-                render_type,
+                ids.as_deref_mut(),
+                draw,
+                state,
+                lighting,
+                &mut self.stats,
             )
             //This is most of the data, now we just draw.
         }
+
+        //Lines and points aren't part of the pre-pass, they test and write depth themselves.
+        //They go after the triangles so their anti-aliased edges blend over the surfaces
+        for i in self.line_idx.iter() {
+            let draw = &draws[line_buff.draws[*i as usize] as usize];
+
+            line_buff.prims[*i as usize].render_fragments(
+                self.pos,
+                self.size,
+                self.color_data.as_mut_slice(),
+                self.depth_data.as_mut_slice(),
+                ids.as_deref_mut(),
+                draw,
+                depth_state,
+                &mut self.stats,
+            )
+        }
+
+        for i in self.point_idx.iter() {
+            let draw = &draws[point_buff.draws[*i as usize] as usize];
+
+            point_buff.prims[*i as usize].render_fragments(
+                self.pos,
+                self.size,
+                self.color_data.as_mut_slice(),
+                self.depth_data.as_mut_slice(),
+                ids.as_deref_mut(),
+                draw,
+                depth_state,
+                &mut self.stats,
+            )
+        }
+
+        self.update_far_depth(depth_state.compare);
     }
 }
 
+//State a mesh submits together with its primitives
+pub struct DrawState {
+    pub render_mode: RenderMode,
+    pub texture: Option<Arc<Texture>>,
+    pub material: Material,
+    pub wireframe: Option<Wireframe>, //overlay on top of the render mode
+    pub id: Option<DrawId>,           //what the ID buffer gets, None clears it
+    pub lod: usize,                   //level of detail the mesh got drawn with
+}
+
 pub struct SlicedBuffers {
     pub tiles: Vec<Tile>,
-    pub triangles: Vec<Triangle>, //in screen space, to do AABB to check which tile should draw which triangle
+    pub triangles: PrimitiveBuffer<Triangle>, //in screen space, to do AABB to check which tile should draw which triangle
+    pub lines: PrimitiveBuffer<Line>,
+    pub points: PrimitiveBuffer<Point>,
+    pub draws: Vec<DrawState>, //all draws submitted this frame
     pub amount_of_tiles: IVec2,
     pub size_of_tiles: i32,
+    pub depth_compare: DepthCompare,
+    pub depth_format: DepthFormat,
+    pub depth_prepass: bool, //lay down depth first so every pixel gets shaded once
+    pub hiz_culling: bool,   //cull meshes and triangles against the depth drawn so far
+    pub hi_z: HiZ,
+    pub id_buffer: bool,         //write a PixelId for every pixel, for picking
+    pub draw_id: Option<DrawId>, //given to the draws submitted while it's set
+    pub lighting: Lighting,      //for RenderMode::Default
+    pub stats: FrameStats,
 }
 
 impl SlicedBuffers {
     pub fn new() -> Self {
         Self {
             tiles: Vec::new(),
-            triangles: Vec::new(),
+            triangles: PrimitiveBuffer::new(),
+            lines: PrimitiveBuffer::new(),
+            points: PrimitiveBuffer::new(),
+            draws: Vec::new(),
             amount_of_tiles: IVec2::splat(0),
             size_of_tiles: 0,
+            depth_compare: DepthCompare::Less,
+            depth_format: DepthFormat::F32,
+            depth_prepass: false,
+            hiz_culling: false,
+            hi_z: HiZ::new(IVec2::ONE, 1),
+            id_buffer: false,
+            draw_id: None,
+            lighting: Lighting::default(),
+            stats: FrameStats::default(),
         }
     }
 
-    //pub fn render(&mut self, camera: &Camera, render_type: &RenderMode) {
-    //    //Remove the "par_" from the line below to check performance single threaded
-    //    self.tiles.par_iter_mut().enumerate().for_each(|tile| {
-    //        tile.1.render(&self.triangles, camera, render_type, None);
-    //    });
-    //}
+    //Adds the screen space triangles of a mesh to the frame's triangle buffer
+    pub fn submit(&mut self, triangles: &mut Vec<Triangle>, draw: DrawState) {
+        let draw_idx = self.push_draw(draw);
+        self.triangles.append(triangles, draw_idx);
+    }
+
+    pub fn submit_lines(&mut self, lines: &mut Vec<Line>, draw: DrawState) {
+        let draw_idx = self.push_draw(draw);
+        self.lines.append(lines, draw_idx);
+    }
+
+    pub fn submit_points(&mut self, points: &mut Vec<Point>, draw: DrawState) {
+        let draw_idx = self.push_draw(draw);
+        self.points.append(points, draw_idx);
+    }
+
+    fn push_draw(&mut self, draw: DrawState) -> u32 {
+        self.draws.push(draw);
+        (self.draws.len() - 1) as u32
+    }
+
+    //Bins everything submitted this frame once and renders all tiles in one pass
+    pub fn render(&mut self, camera: &Camera) {
+        self.aa_bb_comparison();
+        self.stats.triangles += self.triangles.prims.len() as u64;
+        self.stats.lines += self.lines.prims.len() as u64;
+        self.stats.points += self.points.prims.len() as u64;
+
+        let depth_state = self.depth_state();
 
-    pub fn extern_render(
-        &mut self,
-        triangles: &[Triangle],
-        camera: &Camera,
-        render_type: &RenderMode,
-        texture: Option<&Arc<Texture>>,
-        material: &Material,
-    ) {
         //Remove the "par_" from the line below to check performance single threaded
-        self.tiles.par_iter_mut().enumerate().for_each(|tile| {
-            tile.1
-                .render(triangles, camera, render_type, texture, material);
+        self.tiles.par_iter_mut().for_each(|tile| {
+            tile.render(
+                &self.triangles,
+                &self.lines,
+                &self.points,
+                &self.draws,
+                camera,
+                depth_state,
+                &self.lighting,
+                self.depth_prepass,
+                self.hiz_culling,
+            );
         });
+
+        for tile in self.tiles.iter_mut() {
+            self.stats.add(&tile.stats);
+            tile.stats = FrameStats::default();
+        }
+
+        //Depth is final for everything submitted so far, later submissions get culled against it
+        self.hi_z.build(
+            self.tiles.iter().map(|tile| tile.far_depth),
+            self.depth_compare,
+        );
+
+        self.clear_tiles();
+    }
+
+    pub fn depth_state(&self) -> DepthState {
+        DepthState {
+            compare: self.depth_compare,
+            format: self.depth_format,
+            write: true,
+        }
     }
 
     pub fn from_buffers(color: &[u32], depth: &[f32], size_of_tile: i32) -> Self {
         let mut sliced_buff = Self {
             tiles: Vec::new(),
-            triangles: Vec::new(),
+            triangles: PrimitiveBuffer::new(),
+            lines: PrimitiveBuffer::new(),
+            points: PrimitiveBuffer::new(),
+            draws: Vec::new(),
             amount_of_tiles: IVec2::splat(0),
             size_of_tiles: size_of_tile,
+            depth_compare: DepthCompare::Less,
+            depth_format: DepthFormat::F32,
+            depth_prepass: false,
+            hiz_culling: false,
+            hi_z: HiZ::new(IVec2::ONE, size_of_tile),
+            id_buffer: false,
+            draw_id: None,
+            lighting: Lighting::default(),
+            stats: FrameStats::default(),
         };
 
         //assert!(
@@ -139,6 +586,7 @@ impl SlicedBuffers {
             f32::ceil(crate::BUFF_HEIGHT as f32 / size_of_tile as f32) as i32,
         );
         sliced_buff.amount_of_tiles = amount_tiles;
+        sliced_buff.hi_z = HiZ::new(amount_tiles, size_of_tile);
 
         sliced_buff
             .tiles
@@ -159,68 +607,151 @@ impl SlicedBuffers {
         sliced_buff
     }
 
-    //We have all triangles as a buffer,
+    //We have all primitives as a buffer,
     //Now we want to distribute the indexes of them to the proper cell
     pub fn aa_bb_comparison(&mut self) {
-        //Performance sinkhole
-        for i in 0..self.triangles.len() {
-            let tri = self.triangles[i];
+        let triangles = std::mem::take(&mut self.triangles.prims);
+        self.external_aa_bb_comparison(&triangles);
+        self.triangles.prims = triangles;
+
+        let (bins, occluded) = self.bin(&self.lines.prims);
+        self.tiles
+            .par_iter_mut()
+            .zip(bins)
+            .for_each(|(tile, bin)| tile.line_idx.extend(bin));
+        self.stats.bins_occluded += occluded;
+
+        let (bins, occluded) = self.bin(&self.points.prims);
+        self.tiles
+            .par_iter_mut()
+            .zip(bins)
+            .for_each(|(tile, bin)| tile.point_idx.extend(bin));
+        self.stats.bins_occluded += occluded;
+    }
 
-            let aabb = tri.aabb.unwrap();
+    pub fn external_aa_bb_comparison(&mut self, triangles: &[Triangle]) {
+        let (bins, occluded) = self.bin(triangles);
+        self.tiles
+            .par_iter_mut()
+            .zip(bins)
+            .for_each(|(tile, bin)| tile.tri_idx.extend(bin));
+        self.stats.bins_occluded += occluded;
+    }
 
-            //Gives me the idx of the tile in which it is
-            let min = Vec2::floor(aabb[0]) / self.size_of_tiles as f32;
-            let max = Vec2::floor(aabb[1]) / self.size_of_tiles as f32;
+    //Gives every tile the indices of the primitives overlapping it, in submission order,
+    //and how many primitive/tile pairs got culled by the tile's farthest depth
+    fn bin<T: Primitive + Sync>(&self, prims: &[T]) -> (Vec<Vec<u32>>, u64) {
+        let num_tiles = self.tiles.len();
+        if prims.is_empty() {
+            return (vec![Vec::new(); num_tiles], 0);
+        }
 
-            for x in min.x as i32..=max.x as i32 {
-                for y in min.y as i32..=max.y as i32 {
-                    //let idx = (y + x * self.size.y) as usize;
-                    let idx = (x + y * self.amount_of_tiles.x) as usize;
-                    self.tiles[idx].tri_idx.push(i as u32);
+        let chunk_size = (prims.len() / rayon::current_num_threads()).max(BIN_CHUNK_MIN);
+
+        //Every thread bins its own chunk of primitives into private bin lists
+        let bins: Vec<(Vec<Vec<u32>>, u64)> = prims
+            .par_chunks(chunk_size)
+            .enumerate()
+            .map(|(chunk_idx, chunk)| {
+                let mut bins = vec![Vec::new(); num_tiles];
+                let mut occluded = 0;
+                let offset = chunk_idx * chunk_size;
+
+                for (i, prim) in chunk.iter().enumerate() {
+                    let (min, max) = self.tile_range(prim);
+                    let nearest = self
+                        .depth_format
+                        .quantize(prim.nearest_depth(self.depth_compare));
+
+                    for x in min.x..=max.x {
+                        for y in min.y..=max.y {
+                            let idx = (x + y * self.amount_of_tiles.x) as usize;
+
+                            if self.hiz_culling
+                                && self
+                                    .depth_compare
+                                    .occludes(nearest, self.tiles[idx].far_depth)
+                            {
+                                occluded += 1;
+                                continue;
+                            }
+
+                            bins[idx].push((offset + i) as u32);
+                        }
+                    }
                 }
-            }
+
+                (bins, occluded)
+            })
+            .collect();
+
+        //Merge the per-thread lists chunk by chunk so every tile keeps submission order
+        let merged = (0..num_tiles)
+            .into_par_iter()
+            .map(|idx| {
+                bins.iter()
+                    .flat_map(|(chunk_bins, _)| chunk_bins[idx].iter().copied())
+                    .collect()
+            })
+            .collect();
+
+        (merged, bins.iter().map(|(_, occluded)| occluded).sum())
+    }
+
+    //Gives me the min/max idx of the tiles the primitive's AABB overlaps
+    fn tile_range(&self, prim: &impl Primitive) -> (IVec2, IVec2) {
+        let aabb = prim.aabb();
+
+        let min = Vec2::floor(aabb[0]) / self.size_of_tiles as f32;
+        let max = Vec2::floor(aabb[1]) / self.size_of_tiles as f32;
+
+        (min.as_ivec2(), max.as_ivec2())
+    }
+
+    //Tests a mesh's screen space rect against the Hi-Z pyramid, true means it's hidden
+    pub fn is_occluded(&mut self, min: Vec2, max: Vec2, depth_range: Vec2) -> bool {
+        if !self.hiz_culling {
+            return false;
+        }
+
+        self.stats.meshes_tested += 1;
+        let occluded = self.hi_z_test(min, max, depth_range);
+        if occluded {
+            self.stats.meshes_occluded += 1;
         }
+
+        occluded
     }
 
-    pub fn external_aa_bb_comparison(&mut self, triangles: &mut [Triangle]) {
-        //WIP
-        // triangles.par_iter_mut().enumerate().for_each(|(i , tri)|{
-        //     let aabb = tri.aabb.unwrap();
-
-        //     //Gives me the idx of the tile in which it is
-        //     let min = Vec2::floor(aabb[0]) / self.size_of_tiles as f32;
-        //     let max = Vec2::floor(aabb[1]) / self.size_of_tiles as f32;
-
-        //     for x in min.x as i32..=max.x as i32 {
-        //         for y in min.y as i32..=max.y as i32 {
-        //             //let idx = (y + x * self.size.y) as usize;
-        //             let idx = (x + y * self.amount_of_tiles.x) as usize;
-        //             self.tiles[idx].tri_idx.push(i as u32);
-        //         }
-        //     }
-        // })
-
-        for (i, tri) in triangles.iter().enumerate() {
-            let aabb = tri.aabb.unwrap();
-
-            //Gives me the idx of the tile in which it is
-            let min = Vec2::floor(aabb[0]) / self.size_of_tiles as f32;
-            let max = Vec2::floor(aabb[1]) / self.size_of_tiles as f32;
-
-            for x in min.x as i32..=max.x as i32 {
-                for y in min.y as i32..=max.y as i32 {
-                    //let idx = (y + x * self.size.y) as usize;
-                    let idx = (x + y * self.amount_of_tiles.x) as usize;
-                    self.tiles[idx].tri_idx.push(i as u32);
-                }
-            }
+    //Same for a scene BVH node, counted apart so the mesh counters stay per mesh
+    pub fn is_node_occluded(&mut self, min: Vec2, max: Vec2, depth_range: Vec2) -> bool {
+        if !self.hiz_culling {
+            return false;
+        }
+
+        self.stats.nodes_tested += 1;
+        let occluded = self.hi_z_test(min, max, depth_range);
+        if occluded {
+            self.stats.nodes_occluded += 1;
         }
+
+        occluded
+    }
+
+    fn hi_z_test(&self, min: Vec2, max: Vec2, depth_range: Vec2) -> bool {
+        let nearest = self
+            .depth_format
+            .quantize(self.depth_compare.nearest(depth_range.x, depth_range.y));
+
+        self.hi_z.is_occluded(min, max, nearest, self.depth_compare)
     }
 
     pub fn clear_depth(&mut self, val: f32) {
         for tile in self.tiles.iter_mut() {
             tile.clear_buffers_depth(val);
         }
+
+        self.hi_z.fill(val);
     }
 
     pub fn clear_color(&mut self, val: u32) {
@@ -229,10 +760,51 @@ impl SlicedBuffers {
         }
     }
 
+    //Also allocates or frees the ID channel when id_buffer got toggled
+    pub fn clear_ids(&mut self) {
+        for tile in self.tiles.iter_mut() {
+            tile.clear_buffers_id(self.id_buffer);
+        }
+    }
+
+    //Tile and idx within it of a pixel of the whole buffer
+    fn tile_pixel(&self, x: usize, y: usize) -> Option<(&Tile, usize)> {
+        if x >= crate::BUFF_WIDTH || y >= crate::BUFF_HEIGHT {
+            return None;
+        }
+
+        let size = self.size_of_tiles as usize;
+        let tile = self
+            .tiles
+            .get(x / size + y / size * self.amount_of_tiles.x as usize)?;
+        let local = (x - tile.pos.x as usize) + (y - tile.pos.y as usize) * tile.size.x as usize;
+        Some((tile, local))
+    }
+
+    //None if nothing with an id covers the pixel or the ID buffer is off
+    pub fn id_at(&self, x: usize, y: usize) -> Option<PixelId> {
+        let (tile, idx) = self.tile_pixel(x, y)?;
+        tile.id_data.get(idx).copied().flatten()
+    }
+
+    pub fn depth_at(&self, x: usize, y: usize) -> Option<f32> {
+        let (tile, idx) = self.tile_pixel(x, y)?;
+        tile.depth_data.get(idx).copied()
+    }
+
+    pub fn clear_stats(&mut self) {
+        self.stats = FrameStats::default();
+    }
+
     pub fn clear_tiles(&mut self) {
         self.triangles.clear();
+        self.lines.clear();
+        self.points.clear();
+        self.draws.clear();
         for tile in self.tiles.iter_mut() {
             tile.tri_idx.clear();
+            tile.line_idx.clear();
+            tile.point_idx.clear();
         }
     }
 
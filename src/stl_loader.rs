@@ -0,0 +1,197 @@
+use glam::{Vec2, Vec3, Vec4};
+use rand::Rng;
+
+use crate::{
+    material::Material,
+    mesh::VertexMesh,
+    model::{LoadError, LoadOptions},
+};
+
+//Binary STL is an 80 byte header, a triangle count and 50 bytes per triangle
+const HEADER_SIZE: usize = 84;
+const TRIANGLE_SIZE: usize = 50;
+
+pub struct StlModel {
+    pub mesh: VertexMesh,
+    pub warnings: Vec<String>,
+}
+
+pub fn load(filepath: &str, options: &LoadOptions) -> Result<StlModel, LoadError> {
+    let bytes = std::fs::read(filepath)?;
+    parse(&bytes, options).map_err(|err| LoadError::Parse(format!("{filepath}: {err}")))
+}
+
+//Every triangle gets its own vertices and a flat normal from its winding, the normals in
+//the file are often zero or wrong
+pub fn parse(bytes: &[u8], options: &LoadOptions) -> Result<StlModel, String> {
+    let mut warnings = Vec::new();
+    let positions = if is_binary(bytes) {
+        parse_binary(bytes)
+    } else if bytes.trim_ascii_start().starts_with(b"solid") {
+        parse_ascii(bytes, &mut warnings)?
+    } else {
+        return Err("neither a binary nor an ASCII STL".to_string());
+    };
+
+    if positions.is_empty() {
+        warnings.push("no triangles".to_string());
+    }
+
+    let normals = VertexMesh::flat_normals(&positions);
+    let indices: Vec<u32> = (0..positions.len() as u32).collect();
+
+    let color = if options.random_colors {
+        let mut rng = rand::thread_rng();
+        Vec4::new(rng.gen(), rng.gen(), rng.gen(), 1.0)
+    } else {
+        Vec4::ONE
+    };
+
+    let mesh = VertexMesh::from_buffers(
+        &indices,
+        &positions,
+        &normals,
+        &vec![color; positions.len()],
+        &vec![Vec2::ZERO; positions.len()],
+        Material::default(),
+    );
+
+    Ok(StlModel { mesh, warnings })
+}
+
+//Binary files may start with "solid" too, the size is the reliable check
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() < HEADER_SIZE {
+        return false;
+    }
+
+    let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+    bytes.len() == HEADER_SIZE + count * TRIANGLE_SIZE
+}
+
+fn parse_binary(bytes: &[u8]) -> Vec<Vec3> {
+    let read_vec3 = |bytes: &[u8]| {
+        let f = |i: usize| f32::from_le_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap());
+        Vec3::new(f(0), f(1), f(2))
+    };
+
+    bytes[HEADER_SIZE..]
+        .chunks_exact(TRIANGLE_SIZE)
+        .flat_map(|tri| {
+            //Skip the stored normal and the attribute byte count
+            [
+                read_vec3(&tri[12..24]),
+                read_vec3(&tri[24..36]),
+                read_vec3(&tri[36..48]),
+            ]
+        })
+        .collect()
+}
+
+//solid name / facet normal n n n / outer loop / vertex x y z ... / endloop / endfacet
+fn parse_ascii(bytes: &[u8], warnings: &mut Vec<String>) -> Result<Vec<Vec3>, String> {
+    let text = std::str::from_utf8(bytes).map_err(|err| err.to_string())?;
+    let mut positions = Vec::new();
+    let mut facet: Vec<Vec3> = Vec::new();
+
+    for (line_idx, line) in text.lines().enumerate() {
+        let mut tokens = line.split_whitespace();
+        let line_err = |err: String| format!("line {}: {err}", line_idx + 1);
+
+        match tokens.next() {
+            Some("vertex") => {
+                let v = tokens
+                    .map(|token| token.parse::<f32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|err| line_err(err.to_string()))?;
+                if v.len() != 3 {
+                    return Err(line_err("vertex needs 3 coordinates".to_string()));
+                }
+                facet.push(Vec3::new(v[0], v[1], v[2]));
+            }
+            Some("endloop") => {
+                if facet.len() < 3 {
+                    warnings.push(line_err(format!(
+                        "facet with {} vertices, skipped",
+                        facet.len()
+                    )));
+                }
+
+                //Only a few exporters write more than 3, fan them like polygons
+                for i in 1..facet.len().saturating_sub(1) {
+                    positions.extend([facet[0], facet[i], facet[i + 1]]);
+                }
+                facet.clear();
+            }
+            _ => {}
+        }
+    }
+
+    Ok(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(header: &[u8], triangles: &[[Vec3; 3]]) -> Vec<u8> {
+        let mut bytes = header.to_vec();
+        bytes.resize(80, 0);
+        bytes.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+
+        for tri in triangles {
+            bytes.extend_from_slice(&[0; 12]); //zero normal, it gets recomputed
+            for v in tri {
+                bytes.extend(v.to_array().iter().flat_map(|f| f.to_le_bytes()));
+            }
+            bytes.extend_from_slice(&[0; 2]);
+        }
+        bytes
+    }
+
+    const TRIANGLE: [Vec3; 3] = [Vec3::ZERO, Vec3::X, Vec3::Y];
+
+    #[test]
+    fn parses_binary_with_flat_normals() {
+        //A binary header starting with "solid" mustn't be taken for ASCII
+        let bytes = binary(b"solid but binary", &[TRIANGLE, TRIANGLE]);
+        let mesh = parse(&bytes, &LoadOptions::default()).unwrap().mesh;
+
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 3, 4, 5]);
+        assert!(mesh.vertices.iter().all(|v| v.normal == Vec3::Z));
+    }
+
+    #[test]
+    fn parses_ascii() {
+        let text = "solid part
+            facet normal 0 0 0
+              outer loop
+                vertex 0 0 0
+                vertex 1 0 0
+                vertex 0 1 0
+              endloop
+            endfacet
+            facet normal 0 0 -1
+              outer loop
+                vertex 0 0 0
+                vertex 0 1 0
+                vertex 1 0 0
+              endloop
+            endfacet
+            endsolid part";
+
+        let mesh = parse(text.as_bytes(), &LoadOptions::default())
+            .unwrap()
+            .mesh;
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.vertices[0].normal, Vec3::Z);
+        assert_eq!(mesh.vertices[3].normal, Vec3::NEG_Z);
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        assert!(parse(b"not an stl", &LoadOptions::default()).is_err());
+        assert!(parse(b"solid x\nvertex 0 0\n", &LoadOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,20 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+//A directory of its own per test and process, so parallel test runs don't share files
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rust_erizer_{name}_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+//Writes the file with an explicit modification time, so the change shows up whatever the
+//file system's resolution
+pub fn touch(path: &Path, contents: &str, time: u64) {
+    std::fs::write(path, contents).unwrap();
+    let file = std::fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(time))
+        .unwrap();
+}
@@ -3,12 +3,15 @@ use std::{
     sync::{Arc, RwLock},
 };
 
-use crate::texture::Texture;
+use crate::{file_watcher::FileWatcher, texture::Texture};
 use gltf::image::Data;
 
 pub struct TextureManager {
     textures: HashMap<i32, Arc<Texture>>,
-    num_to_assign: i32, //This always goes up, never down.
+    num_to_assign: i32,              //This always goes up, never down.
+    filepaths: HashMap<i32, String>, //textures loaded from files, for hot-reloading
+    watcher: FileWatcher,
+    white: Arc<Texture>, //shared by every image that failed to load
 }
 
 impl TextureManager {
@@ -16,40 +19,116 @@ impl TextureManager {
         Self {
             textures: HashMap::new(),
             num_to_assign: 0,
+            filepaths: HashMap::new(),
+            watcher: FileWatcher::default(),
+            white: Arc::new(Texture::white()),
         }
     }
 
     //Returns index of this
     pub fn load_from_filepath(&mut self, fp: &str) -> Result<i32, String> {
-        //error "handling" is done in Texture here :p / dunno whether that's the best approach
-        let texture = Arc::new(Texture::from_filepath(fp));
+        let texture = match self.cached(fp) {
+            Some(texture) => texture,
+            None => Arc::new(Texture::from_filepath(fp)?),
+        };
+        Ok(self.insert_file(fp, texture))
+    }
+
+    //A file that fails to load gets a white texture and a warning instead. It's still
+    //watched, so fixing the file swaps in the real one
+    pub fn load_or_white(&mut self, fp: &str, warnings: &mut Vec<String>) -> i32 {
+        let texture = match self.cached(fp) {
+            Some(texture) => texture,
+            None => match Texture::from_filepath(fp) {
+                Ok(texture) => Arc::new(texture),
+                Err(err) => {
+                    warnings.push(format!("{err}, using a white texture"));
+                    self.white.clone()
+                }
+            },
+        };
+        self.insert_file(fp, texture)
+    }
 
+    //A file that's already loaded shares its image, every model still gets its own index
+    //so destroying one doesn't pull it from under the others. Files that failed get
+    //another try
+    fn cached(&self, fp: &str) -> Option<Arc<Texture>> {
+        self.filepaths
+            .iter()
+            .find(|(_, path)| *path == fp)
+            .and_then(|(idx, _)| self.textures.get(idx))
+            .filter(|texture| !Arc::ptr_eq(texture, &self.white))
+            .cloned()
+    }
+
+    fn insert_file(&mut self, fp: &str, texture: Arc<Texture>) -> i32 {
         self.num_to_assign += 1;
         self.textures.insert(self.num_to_assign, texture);
-        Ok(self.num_to_assign)
+        self.filepaths.insert(self.num_to_assign, fp.to_string());
+        self.watcher.watch(fp);
+        self.num_to_assign
     }
 
-    pub fn load_from_gltf_images(&mut self, images: Vec<Data>) -> Result<Vec<i32>, String> {
-        if images.is_empty() {
-            return Err("Images is empty".to_string());
-        }
-
+    //Images in a format we can't read become white textures, so indices still line up
+    pub fn load_from_gltf_images(
+        &mut self,
+        images: Vec<Data>,
+        warnings: &mut Vec<String>,
+    ) -> Vec<i32> {
         //Start of the array of image data from GLTF Image.
         let mut tex_indices = Vec::new();
 
-        for image in images {
-            let texture = Arc::new(Texture::from_gltf_image(&image));
+        for (idx, image) in images.iter().enumerate() {
+            let texture = match Texture::from_gltf_image(image) {
+                Ok(texture) => Arc::new(texture),
+                Err(err) => {
+                    warnings.push(format!("image {idx}: {err}, using a white texture"));
+                    self.white.clone()
+                }
+            };
 
             self.num_to_assign += 1;
             tex_indices.push(self.num_to_assign);
             self.textures.insert(self.num_to_assign, texture);
         }
 
-        Ok(tex_indices)
+        tex_indices
     }
 
     pub fn destroy_texture(&mut self, idx: &i32) {
         self.textures.remove(idx);
+
+        if let Some(fp) = self.filepaths.remove(idx) {
+            if !self.filepaths.values().any(|other| *other == fp) {
+                self.watcher.unwatch(&fp);
+            }
+        }
+    }
+
+    //Swaps in the new image under the same indices, so meshes pick it up without knowing.
+    //Returns the reloaded paths, a file that fails to load keeps the old texture
+    pub fn reload_changed(&mut self) -> Vec<String> {
+        let mut reloaded = Vec::new();
+
+        for fp in self.watcher.poll() {
+            let texture = match Texture::from_filepath(&fp) {
+                Ok(texture) => Arc::new(texture),
+                Err(err) => {
+                    println!("Failed to reload {fp}: {err}");
+                    continue;
+                }
+            };
+
+            for (idx, path) in &self.filepaths {
+                if *path == fp {
+                    self.textures.insert(*idx, texture.clone());
+                }
+            }
+            reloaded.push(fp);
+        }
+
+        reloaded
     }
 
     pub fn get_texture(&self, idx: &i32) -> Option<&Arc<Texture>> {
@@ -62,3 +141,44 @@ lazy_static::lazy_static! {
     pub static ref TEXTURE_MANAGER: RwLock<TextureManager> = RwLock::new(TextureManager::new());
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreadable_files_load_as_white() {
+        let mut manager = TextureManager::new();
+        let mut warnings = Vec::new();
+        let tex = manager.load_or_white("resources/textures/missing.png", &mut warnings);
+
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(manager.get_texture(&tex).unwrap().data, [u32::MAX]);
+        assert!(manager
+            .load_from_filepath("resources/textures/missing.png")
+            .is_err());
+
+        //Still missing the second time, not taken from the cache
+        manager.load_or_white("resources/textures/missing.png", &mut warnings);
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn shares_images_loaded_from_the_same_file() {
+        let mut manager = TextureManager::new();
+        let first = manager
+            .load_from_filepath("resources/textures/bojan.jpg")
+            .unwrap();
+        let second = manager
+            .load_from_filepath("resources/textures/bojan.jpg")
+            .unwrap();
+        assert_ne!(first, second);
+
+        let image = manager.get_texture(&first).unwrap().clone();
+        assert!(Arc::ptr_eq(&image, manager.get_texture(&second).unwrap()));
+
+        //The other index keeps it alive
+        manager.destroy_texture(&first);
+        assert!(Arc::ptr_eq(&image, manager.get_texture(&second).unwrap()));
+    }
+}
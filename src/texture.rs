@@ -23,34 +23,40 @@ impl Texture {
         }
     }
 
-    pub fn from_filepath(fp: &str) -> Self {
-        let _tex = open(fp).expect("Texture Error: ");
-        let width = _tex.width();
-        let height = _tex.height();
-        let data: Vec<u32> = _tex
-            .as_rgb8()
-            .expect("Shit's not there >:( ")
-            .to_vec()
-            .chunks(3)
-            .map(|rgb| crate::render_utils::argb8_to_u32(rgb[0], rgb[1], rgb[2], 255))
+    //Stands in for images that failed to load, looks the same as no texture
+    pub fn white() -> Self {
+        Self {
+            width: 1,
+            height: 1,
+            data: vec![u32::MAX],
+            ..Default::default()
+        }
+    }
+
+    pub fn from_filepath(fp: &str) -> Result<Self, String> {
+        let tex = open(fp).map_err(|err| format!("{fp}: {err}"))?.to_rgba8();
+        let width = tex.width();
+        let height = tex.height();
+        let data: Vec<u32> = tex
+            .pixels()
+            .map(|p| crate::render_utils::argb8_to_u32(p[0], p[1], p[2], p[3]))
             .collect();
 
-        Self {
+        Ok(Self {
             width,
             height,
             data,
             ..Default::default()
-        }
+        })
     }
 
-    pub fn from_gltf_image(image: &Data) -> Self {
+    pub fn from_gltf_image(image: &Data) -> Result<Self, String> {
         let data = match image.format {
             gltf::image::Format::R8 => image
                 .pixels
                 .iter()
                 .map(|r| crate::render_utils::rgba8_to_u32(*r, 0, 0, 0))
                 .collect(),
-            gltf::image::Format::R8G8 => todo!(),
             gltf::image::Format::R8G8B8 => image
                 .pixels
                 .chunks(3)
@@ -61,20 +67,15 @@ impl Texture {
                 .chunks(4)
                 .map(|rgba| crate::render_utils::rgba8_to_u32(rgba[0], rgba[1], rgba[2], rgba[3]))
                 .collect(),
-            gltf::image::Format::R16 => todo!(),
-            gltf::image::Format::R16G16 => todo!(),
-            gltf::image::Format::R16G16B16 => todo!(),
-            gltf::image::Format::R16G16B16A16 => todo!(),
-            gltf::image::Format::R32G32B32FLOAT => todo!(),
-            gltf::image::Format::R32G32B32A32FLOAT => todo!(),
+            format => return Err(format!("Unsupported image format {format:?}")),
         };
 
-        Texture {
+        Ok(Texture {
             width: image.width,
             height: image.height,
             data,
             ..Default::default()
-        }
+        })
     }
 
     pub fn get_pixel(&self, img_width: u32, img_height: u32) -> Vec4 {
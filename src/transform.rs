@@ -8,7 +8,7 @@ pub enum TransformInitParams {
     TranslationRotation(Vec3, Quat),
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Transform {
     pub translation: Vec3,
     pub rotation: Quat,
@@ -1,10 +1,12 @@
 use std::sync::Arc;
 
 use crate::{
-    material::Material,
-    mesh::RenderMode,
+    light::Lighting,
+    material::{Material, TextureSlot},
+    mesh::{lod_color, RenderMode},
     render_utils::{self, edge_fun},
     sampler::*,
+    sliced_buffer::{DepthCompare, DepthState, DrawState, FrameStats, PixelId, Primitive},
     texture::Texture,
 };
 
@@ -18,6 +20,7 @@ pub struct Triangle {
     pub ssc: [Vec2; 3], //screen coordinates
     pub total_area: f32,
     pub aabb: Option<[Vec2; 2]>, // 0 -> min / 1 -> max
+    pub primitive: u32,          //idx of the triangle in its mesh, for the ID buffer
 }
 
 #[allow(clippy::upper_case_acronyms)]
@@ -44,6 +47,7 @@ impl Triangle {
             rec: [0.0, 0.0, 0.0],
             ssc: [Vec2::splat(0.0); 3],
             total_area: 0.0,
+            primitive: 0,
         }
     }
 
@@ -139,10 +143,22 @@ impl Triangle {
         size: IVec2,
         color_buff: &mut [u32],
         depth_buff: &mut [f32],
-        texture: Option<&Arc<Texture>>,
-        material: &Material,
-        render_type: &RenderMode,
+        mut id_buff: Option<&mut [Option<PixelId>]>,
+        draw: &DrawState,
+        depth_state: DepthState,
+        lighting: &Lighting,
+        stats: &mut FrameStats,
     ) {
+        let texture = draw.texture.as_ref();
+        let material = &draw.material;
+        let render_type = &draw.render_mode;
+
+        //Wireframe mode only draws the edges, everything else can overlay them
+        let wireframe = match render_type {
+            RenderMode::Wireframe => Some(draw.wireframe.unwrap_or_default()),
+            _ => draw.wireframe,
+        };
+
         for idx_x in 0..size.x {
             for idx_y in 0..size.y {
                 let x = pos.x + idx_x;
@@ -168,6 +184,33 @@ impl Triangle {
                 let m_all_sign = ((area0.to_bits() | area1.to_bits() | area2.to_bits()) >> 31) == 0;
 
                 if m_all_sign {
+                    let coverage = wireframe.map_or(0.0, |wireframe| {
+                        self.edge_coverage(p, [area0, area1, area2], wireframe.width)
+                    });
+
+                    //Inside of the triangle doesn't exist in wireframe mode
+                    if let RenderMode::Wireframe = render_type {
+                        if coverage <= 0.0 {
+                            continue;
+                        }
+                    }
+
+                    let depth = depth_state.format.quantize(self.fragment_depth(p));
+
+                    stats.fragments_tested += 1;
+                    if !depth_state.compare.test(depth, depth_buff[idx]) {
+                        continue;
+                    }
+
+                    if depth_state.write {
+                        depth_buff[idx] = depth;
+                    }
+
+                    if let Some(id_buff) = id_buff.as_deref_mut() {
+                        id_buff[idx] = draw.id.map(|id| id.pixel(self.primitive));
+                    }
+
+                    stats.fragments_shaded += 1;
                     match render_type {
                         RenderMode::Default => {
                             self.render_default(
@@ -176,9 +219,9 @@ impl Triangle {
                                 self.ssc,
                                 self.total_area,
                                 color_buff,
-                                depth_buff,
                                 texture,
                                 material,
+                                lighting,
                                 idx,
                             );
                         }
@@ -189,7 +232,6 @@ impl Triangle {
                                 self.ssc,
                                 self.total_area,
                                 color_buff,
-                                depth_buff,
                                 idx,
                             );
                         }
@@ -200,7 +242,6 @@ impl Triangle {
                                 self.ssc,
                                 self.total_area,
                                 color_buff,
-                                depth_buff,
                                 idx,
                             );
                         }
@@ -212,8 +253,8 @@ impl Triangle {
                                     self.ssc,
                                     self.total_area,
                                     color_buff,
-                                    depth_buff,
                                     texture,
+                                    &material.base_tex,
                                     idx,
                                 );
                             } else {
@@ -223,7 +264,6 @@ impl Triangle {
                                     self.ssc,
                                     self.total_area,
                                     color_buff,
-                                    depth_buff,
                                     idx,
                                 );
                             }
@@ -236,8 +276,8 @@ impl Triangle {
                                     self.ssc,
                                     self.total_area,
                                     color_buff,
-                                    depth_buff,
                                     texture,
+                                    &material.base_tex,
                                     idx,
                                 );
                             } else {
@@ -247,7 +287,6 @@ impl Triangle {
                                     self.ssc,
                                     self.total_area,
                                     color_buff,
-                                    depth_buff,
                                     idx,
                                 );
                             }
@@ -259,7 +298,6 @@ impl Triangle {
                                 self.ssc,
                                 self.total_area,
                                 color_buff,
-                                depth_buff,
                                 idx,
                             );
                         }
@@ -270,24 +308,30 @@ impl Triangle {
                                 self.ssc,
                                 self.total_area,
                                 color_buff,
-                                depth_buff,
                                 idx,
                             );
                         }
                         RenderMode::Depth => {
-                            self.render_pixel_depth(
+                            self.render_pixel_depth(color_buff, depth, idx);
+                        }
+                        RenderMode::Aabb => {
+                            //covered above.
+                        }
+                        RenderMode::Wireframe => {
+                            //blended below.
+                        }
+                        RenderMode::Lod => {
+                            self.render_pixel_lod(
                                 p,
                                 self.rec,
                                 self.ssc,
                                 self.total_area,
                                 color_buff,
-                                depth_buff,
+                                draw.lod,
+                                lighting,
                                 idx,
                             );
                         }
-                        RenderMode::Aabb => {
-                            //covered above.
-                        }
                         RenderMode::Normal => {
                             self.render_pixel_normal(
                                 p,
@@ -295,18 +339,72 @@ impl Triangle {
                                 self.ssc,
                                 self.total_area,
                                 color_buff,
-                                depth_buff,
                                 idx,
                             );
                         }
                     }
+
+                    if let Some(wireframe) = wireframe {
+                        if coverage > 0.0 {
+                            render_utils::blend(color_buff, idx, wireframe.color, coverage);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    //Depth pre-pass, only writes the closest depth so shading can run with an equal test
+    pub fn render_depth(
+        &self,
+        pos: IVec2,
+        size: IVec2,
+        depth_buff: &mut [f32],
+        depth_state: DepthState,
+        stats: &mut FrameStats,
+    ) {
+        for idx_x in 0..size.x {
+            for idx_y in 0..size.y {
+                let x = pos.x + idx_x;
+                let y = pos.y + idx_y;
+
+                let p = Vec2::new(x as f32, y as f32) + 0.5;
+                let idx: usize = (idx_x + idx_y * size.x) as usize;
+
+                let area0 = render_utils::edge_fun(p, self.ssc[1], self.ssc[2]) / self.total_area;
+                let area1 = render_utils::edge_fun(p, self.ssc[2], self.ssc[0]) / self.total_area;
+                let area2 = render_utils::edge_fun(p, self.ssc[0], self.ssc[1]) / self.total_area;
+                let m_all_sign = ((area0.to_bits() | area1.to_bits() | area2.to_bits()) >> 31) == 0;
+
+                if m_all_sign {
+                    let depth = depth_state.format.quantize(self.fragment_depth(p));
+
+                    stats.depth_fragments += 1;
+                    if depth_state.compare.test(depth, depth_buff[idx]) {
+                        depth_buff[idx] = depth;
+                    }
                 }
             }
         }
     }
 
-    pub fn render_triangle(&self) -> ClipResult {
-        match Self::clip_cull_triangle(self) {
+    //Both passes have to calculate depth the exact same way for the equal test to work
+    fn fragment_depth(&self, p: Vec2) -> f32 {
+        let bary = render_utils::barycentric_coordinates(
+            p,
+            self.ssc[0],
+            self.ssc[1],
+            self.ssc[2],
+            self.total_area,
+        );
+
+        bary.x * self.v[0].position.z
+            + bary.y * self.v[1].position.z
+            + bary.z * self.v[2].position.z
+    }
+
+    pub fn render_triangle(&self, reversed_z: bool) -> ClipResult {
+        match Self::clip_cull_triangle(self, reversed_z) {
             ClipResult::Clipped => ClipResult::Clipped,
             ClipResult::One(tri) => ClipResult::One(Self::triangle_to_screen(&tri)),
             ClipResult::Two(tri) => ClipResult::Two((
@@ -324,71 +422,53 @@ impl Triangle {
         ssc: [Vec2; 3],
         total_area: f32,
         color_buff: &mut [u32],
-        z_buffer: &mut [f32],
         texture: Option<&Arc<Texture>>,
         material: &Material,
+        lighting: &Lighting,
         idx: usize,
     ) {
         //Bary -> Depth
         let bary = render_utils::barycentric_coordinates(p, ssc[0], ssc[1], ssc[2], total_area);
         let correction = bary.x * rec[0] + bary.y * rec[1] + bary.z * rec[2];
         let correction = 1.0 / correction;
-        let depth = bary.x * self.v[0].position.z
-            + bary.y * self.v[1].position.z
-            + bary.z * self.v[2].position.z;
-
-        if depth < z_buffer[idx] {
-            z_buffer[idx] = depth;
-
-            let v0_uv = self.v[0].uv * rec[0];
-            let v1_uv = self.v[1].uv * rec[1];
-            let v2_uv = self.v[2].uv * rec[2];
-
-            let v0_normal = self.v[0].normal * rec[0];
-            let v1_normal = self.v[1].normal * rec[1];
-            let v2_normal = self.v[2].normal * rec[2];
 
-            let uv = v0_uv * bary.x + v1_uv * bary.y + v2_uv * bary.z;
-            let uv = uv * correction;
+        let v0_normal = self.v[0].normal * rec[0];
+        let v1_normal = self.v[1].normal * rec[1];
+        let v2_normal = self.v[2].normal * rec[2];
 
-            let mut tex_color = Vec4::splat(1.0);
-            if let Some(texture) = texture {
-                let uv = Triangle::calc_uv_sampler(uv, &texture.sampler);
+        let uv = self.slot_uv(bary, rec, correction, &material.base_tex);
 
-                let img_width = (texture.width as f32 - 1.0) * uv.x;
-                let img_height = (texture.height as f32 - 1.0) * uv.y;
+        let mut tex_color = Vec4::splat(1.0);
+        if let Some(texture) = texture {
+            let uv = Triangle::calc_uv_sampler(uv, &texture.sampler);
 
-                //let img_width = f32::clamp(img_width, 0, tex )
+            let img_width = (texture.width as f32 - 1.0) * uv.x;
+            let img_height = (texture.height as f32 - 1.0) * uv.y;
 
-                if img_width < 0.0 || img_width >= texture.width as f32 {
-                    let b = 1.0;
-                    panic!("Image WIDTH out of bounds. Value: {img_width}");
-                }
-                if img_height < 0.0 || img_height >= texture.height as f32 {
-                    panic!("Image HEIGHT out of bounds. Value: {img_height}")
-                }
+            //let img_width = f32::clamp(img_width, 0, tex )
 
-                tex_color = texture.get_pixel(img_width as u32, img_height as u32);
-                tex_color *= 0.003_921_569; //reciprocal of 255
+            if img_width < 0.0 || img_width >= texture.width as f32 {
+                let b = 1.0;
+                panic!("Image WIDTH out of bounds. Value: {img_width}");
+            }
+            if img_height < 0.0 || img_height >= texture.height as f32 {
+                panic!("Image HEIGHT out of bounds. Value: {img_height}")
             }
 
-            let normals = v0_normal * bary.x + v1_normal * bary.y + v2_normal * bary.z;
-            let normals = normals * correction;
-
-            let light_dir = Vec3::new(-1.0, -1.0, -1.0).normalize();
-            let ambient_col = Vec4::new(0.2, 0.2, 0.2, 1.0);
+            tex_color = texture.get_pixel(img_width as u32, img_height as u32);
+            tex_color *= 0.003_921_569; //reciprocal of 255
+        }
 
-            let intensity = normals.dot(-light_dir);
-            let object_col = tex_color * material.base_color;
+        let normals = v0_normal * bary.x + v1_normal * bary.y + v2_normal * bary.z;
+        let normals = normals * correction;
 
-            let diffuse = f32::max(intensity, 0.0) * object_col;
-            let ambient = ambient_col * object_col;
+        let object_col = tex_color * material.base_color;
+        let light = lighting.shade(normals);
 
-            let mut fc = ambient + diffuse;
-            fc *= 255.0;
+        let mut fc = object_col * Vec4::from((light, 1.0));
+        fc *= 255.0;
 
-            color_buff[idx] = render_utils::vec4_to_u32(fc);
-        }
+        color_buff[idx] = render_utils::vec4_to_u32(fc);
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -399,33 +479,24 @@ impl Triangle {
         ssc: [Vec2; 3],
         total_area: f32,
         color_buff: &mut [u32],
-        z_buffer: &mut [f32],
         idx: usize,
     ) {
         let bary = render_utils::barycentric_coordinates(p, ssc[0], ssc[1], ssc[2], total_area);
 
         let correction = bary.x * rec[0] + bary.y * rec[1] + bary.z * rec[2];
         let correction = 1.0 / correction;
-        let depth = bary.x * self.v[0].position.z
-            + bary.y * self.v[1].position.z
-            + bary.z * self.v[2].position.z;
 
-        if depth < z_buffer[idx] {
-            z_buffer[idx] = depth;
+        //Color
+        let color =
+            self.v[0].position * bary.x + self.v[1].position * bary.y + self.v[2].position * bary.z;
 
-            //Color
-            let color = self.v[0].position * bary.x
-                + self.v[1].position * bary.y
-                + self.v[2].position * bary.z;
+        let fc = Vec3::new(
+            f32::abs(f32::sin(color.x * 209.0)),
+            0.0,
+            f32::abs(f32::sin(color.x * 209.0)),
+        ) * 255.0;
 
-            let fc = Vec3::new(
-                f32::abs(f32::sin(color.x * 209.0)),
-                0.0,
-                f32::abs(f32::sin(color.x * 209.0)),
-            ) * 255.0;
-
-            color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
-        }
+        color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -436,49 +507,37 @@ impl Triangle {
         ssc: [Vec2; 3],
         total_area: f32,
         color_buff: &mut [u32],
-        z_buffer: &mut [f32],
         texture: &Texture,
+        slot: &TextureSlot,
         idx: usize,
     ) {
-        let v0_uv = self.v[0].uv * rec[0];
-        let v1_uv = self.v[1].uv * rec[1];
-        let v2_uv = self.v[2].uv * rec[2];
-
         let bary = render_utils::barycentric_coordinates(p, ssc[0], ssc[1], ssc[2], total_area);
 
         let correction = bary.x * rec[0] + bary.y * rec[1] + bary.z * rec[2];
         let correction = 1.0 / correction;
-        let depth = bary.x * self.v[0].position.z
-            + bary.y * self.v[1].position.z
-            + bary.z * self.v[2].position.z;
 
-        if depth < z_buffer[idx] {
-            z_buffer[idx] = depth;
+        let uv = self.slot_uv(bary, rec, correction, slot);
 
-            let uv = v0_uv * bary.x + v1_uv * bary.y + v2_uv * bary.z;
-            let uv = uv * correction;
+        let uv = Triangle::calc_uv_sampler(uv, &texture.sampler);
 
-            let uv = Triangle::calc_uv_sampler(uv, &texture.sampler);
-
-            let img_width = (texture.width as f32 - 1.0) * uv.x;
-            let img_height = (texture.height as f32 - 1.0) * uv.y;
+        let img_width = (texture.width as f32 - 1.0) * uv.x;
+        let img_height = (texture.height as f32 - 1.0) * uv.y;
 
-            //let img_width = f32::clamp(img_width, 0, tex )
+        //let img_width = f32::clamp(img_width, 0, tex )
 
-            if img_width < 0.0 || img_width >= texture.width as f32 {
-                let b = 1.0;
-                panic!("Image WIDTH out of bounds. Value: {img_width}");
-            }
-            if img_height < 0.0 || img_height >= texture.height as f32 {
-                panic!("Image HEIGHT out of bounds. Value: {img_height}")
-            }
+        if img_width < 0.0 || img_width >= texture.width as f32 {
+            let b = 1.0;
+            panic!("Image WIDTH out of bounds. Value: {img_width}");
+        }
+        if img_height < 0.0 || img_height >= texture.height as f32 {
+            panic!("Image HEIGHT out of bounds. Value: {img_height}")
+        }
 
-            let color = texture.get_pixel(img_width as u32, img_height as u32);
+        let color = texture.get_pixel(img_width as u32, img_height as u32);
 
-            let fc = Vec3::new(color[0], color[1], color[2]);
+        let fc = Vec3::new(color[0], color[1], color[2]);
 
-            color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
-        }
+        color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -489,13 +548,10 @@ impl Triangle {
         ssc: [Vec2; 3],
         total_area: f32,
         color_buff: &mut [u32],
-        z_buffer: &mut [f32],
         texture: &Texture,
+        slot: &TextureSlot,
         idx: usize,
     ) {
-        let v0_uv = self.v[0].uv * rec[0];
-        let v1_uv = self.v[1].uv * rec[1];
-        let v2_uv = self.v[2].uv * rec[2];
         let v0_color = self.v[0].color * rec[0];
         let v1_color = self.v[1].color * rec[1];
         let v2_color = self.v[2].color * rec[2];
@@ -504,37 +560,54 @@ impl Triangle {
 
         let correction = bary.x * rec[0] + bary.y * rec[1] + bary.z * rec[2];
         let correction = 1.0 / correction;
-        let depth = bary.x * self.v[0].position.z
-            + bary.y * self.v[1].position.z
-            + bary.z * self.v[2].position.z;
 
-        if depth < z_buffer[idx] {
-            z_buffer[idx] = depth;
+        let v_color = v0_color * bary.x + v1_color * bary.y + v2_color * bary.z;
+        let v_color = v_color * correction;
+        let uv = self.slot_uv(bary, rec, correction, slot);
 
-            let v_color = v0_color * bary.x + v1_color * bary.y + v2_color * bary.z;
-            let uv = v0_uv * bary.x + v1_uv * bary.y + v2_uv * bary.z;
-            let v_color = v_color * correction;
-            let uv = uv * correction;
+        let uv = Triangle::calc_uv_sampler(uv, &texture.sampler);
+        let v_color = v_color.xyz() * Vec3::splat(255.0);
 
-            let uv = Triangle::calc_uv_sampler(uv, &texture.sampler);
-            let v_color = v_color * Vec3::splat(255.0);
+        let img_width = (texture.width as f32 - 1.0) * uv.x;
+        let img_height = (texture.height as f32 - 1.0) * uv.y;
 
-            let img_width = (texture.width as f32 - 1.0) * uv.x;
-            let img_height = (texture.height as f32 - 1.0) * uv.y;
+        if img_width < 0.0 || img_width >= texture.width as f32 {
+            panic!("Image WIDTH out of bounds.")
+        }
+        if img_height < 0.0 || img_height >= texture.height as f32 {
+            panic!("Image HEIGHT out of bounds.")
+        }
 
-            if img_width < 0.0 || img_width >= texture.width as f32 {
-                panic!("Image WIDTH out of bounds.")
-            }
-            if img_height < 0.0 || img_height >= texture.height as f32 {
-                panic!("Image HEIGHT out of bounds.")
-            }
+        let color = texture.get_pixel(img_width as u32, img_height as u32);
 
-            let color = texture.get_pixel(img_width as u32, img_height as u32);
+        let fc = (Vec3::new(color[0], color[1], color[2]) + v_color) / 2.0;
 
-            let fc = (Vec3::new(color[0], color[1], color[2]) + v_color) / 2.0;
+        color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
+    }
 
-            color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
-        }
+    //Lit, so the shape still reads under the flat LOD color
+    #[allow(clippy::too_many_arguments)]
+    fn render_pixel_lod(
+        &self,
+        p: Vec2,
+        rec: [f32; 3],
+        ssc: [Vec2; 3],
+        total_area: f32,
+        color_buff: &mut [u32],
+        lod: usize,
+        lighting: &Lighting,
+        idx: usize,
+    ) {
+        let bary = render_utils::barycentric_coordinates(p, ssc[0], ssc[1], ssc[2], total_area);
+        let correction = 1.0 / (bary.x * rec[0] + bary.y * rec[1] + bary.z * rec[2]);
+
+        let normal = (self.v[0].normal * rec[0] * bary.x
+            + self.v[1].normal * rec[1] * bary.y
+            + self.v[2].normal * rec[2] * bary.z)
+            * correction;
+
+        let fc = lod_color(lod) * lighting.shade(normal) * 255.0;
+        color_buff[idx] = render_utils::vec3_to_u32(fc);
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -545,7 +618,6 @@ impl Triangle {
         ssc: [Vec2; 3],
         total_area: f32,
         color_buff: &mut [u32],
-        z_buffer: &mut [f32],
         idx: usize,
     ) {
         let v0_normal = self.v[0].normal * rec[0];
@@ -556,23 +628,16 @@ impl Triangle {
 
         let correction = bary.x * rec[0] + bary.y * rec[1] + bary.z * rec[2];
         let correction = 1.0 / correction;
-        let depth = bary.x * self.v[0].position.z
-            + bary.y * self.v[1].position.z
-            + bary.z * self.v[2].position.z;
 
-        if depth < z_buffer[idx] {
-            z_buffer[idx] = depth;
+        let normal = v0_normal * bary.x + v1_normal * bary.y + v2_normal * bary.z;
+        let normal = normal * correction;
 
-            let normal = v0_normal * bary.x + v1_normal * bary.y + v2_normal * bary.z;
-            let normal = normal * correction;
+        let normal = normal * 0.5 + 0.5; //correction for rendering normals ffddirectly
+        let color = normal * Vec3::splat(255.0);
 
-            let normal = normal * 0.5 + 0.5; //correction for rendering normals ffddirectly
-            let color = normal * Vec3::splat(255.0);
+        let fc = Vec3::new(color[0], color[1], color[2]);
 
-            let fc = Vec3::new(color[0], color[1], color[2]);
-
-            color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
-        }
+        color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -583,25 +648,17 @@ impl Triangle {
         ssc: [Vec2; 3],
         total_area: f32,
         color_buff: &mut [u32],
-        z_buffer: &mut [f32],
         idx: usize,
     ) {
         let bary = render_utils::barycentric_coordinates(p, ssc[0], ssc[1], ssc[2], total_area);
 
         let correction = bary.x * rec[0] + bary.y * rec[1] + bary.z * rec[2];
         let correction = 1.0 / correction;
-        let depth = bary.x * self.v[0].position.z
-            + bary.y * self.v[1].position.z
-            + bary.z * self.v[2].position.z;
 
-        if depth < z_buffer[idx] {
-            z_buffer[idx] = depth;
+        //Bary
+        let fc = bary * Vec3::splat(255.0);
 
-            //Bary
-            let fc = bary * Vec3::splat(255.0);
-
-            color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
-        }
+        color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -612,7 +669,6 @@ impl Triangle {
         ssc: [Vec2; 3],
         total_area: f32,
         color_buff: &mut [u32],
-        z_buffer: &mut [f32],
         idx: usize,
     ) {
         let v0_uv = self.v[0].uv * rec[0];
@@ -623,23 +679,16 @@ impl Triangle {
 
         let correction = bary.x * rec[0] + bary.y * rec[1] + bary.z * rec[2];
         let correction = 1.0 / correction;
-        let depth = bary.x * self.v[0].position.z
-            + bary.y * self.v[1].position.z
-            + bary.z * self.v[2].position.z;
 
-        if depth < z_buffer[idx] {
-            z_buffer[idx] = depth;
+        let uv = v0_uv * bary.x + v1_uv * bary.y + v2_uv * bary.z;
+        let uv = uv * correction;
 
-            let uv = v0_uv * bary.x + v1_uv * bary.y + v2_uv * bary.z;
-            let uv = uv * correction;
+        let uv = uv.clamp(Vec2::splat(0.0), Vec2::splat(1.0));
 
-            let uv = uv.clamp(Vec2::splat(0.0), Vec2::splat(1.0));
+        //UV
+        let fc = Vec3::new(uv.x, uv.y, 0.0) * Vec3::splat(255.0);
 
-            //UV
-            let fc = Vec3::new(uv.x, uv.y, 0.0) * Vec3::splat(255.0);
-
-            color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
-        }
+        color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -650,7 +699,6 @@ impl Triangle {
         ssc: [Vec2; 3],
         total_area: f32,
         color_buff: &mut [u32],
-        z_buffer: &mut [f32],
         idx: usize,
     ) {
         let v0_color = self.v[0].color * rec[0];
@@ -661,50 +709,39 @@ impl Triangle {
 
         let correction = bary.x * rec[0] + bary.y * rec[1] + bary.z * rec[2];
         let correction = 1.0 / correction;
-        let depth = bary.x * self.v[0].position.z
-            + bary.y * self.v[1].position.z
-            + bary.z * self.v[2].position.z;
 
-        if depth < z_buffer[idx] {
-            z_buffer[idx] = depth;
+        let color = v0_color * bary.x + v1_color * bary.y + v2_color * bary.z;
+        let color = color * correction;
 
-            let color = v0_color * bary.x + v1_color * bary.y + v2_color * bary.z;
-            let color = color * correction;
+        //UV
+        let fc = Vec3::new(color.x, color.y, color.z) * Vec3::splat(255.0);
 
-            //UV
-            let fc = Vec3::new(color.x, color.y, color.z) * Vec3::splat(255.0);
-
-            color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
-        }
+        color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn render_pixel_depth(
-        &self,
-        p: Vec2,
-        rec: [f32; 3],
-        ssc: [Vec2; 3],
-        total_area: f32,
-        color_buff: &mut [u32],
-        z_buffer: &mut [f32],
-        idx: usize,
-    ) {
-        let bary = render_utils::barycentric_coordinates(p, ssc[0], ssc[1], ssc[2], total_area);
+    fn render_pixel_depth(&self, color_buff: &mut [u32], depth: f32, idx: usize) {
+        //Depth
+        let fc = depth * Vec3::splat(255.0);
 
-        let correction = bary.x * rec[0] + bary.y * rec[1] + bary.z * rec[2];
-        let correction = 1.0 / correction;
-        let depth = bary.x * self.v[0].position.z
-            + bary.y * self.v[1].position.z
-            + bary.z * self.v[2].position.z;
-
-        if depth < z_buffer[idx] {
-            z_buffer[idx] = depth;
-
-            //Depth
-            let fc = depth * Vec3::splat(255.0);
+        color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
+    }
 
-            color_buff[idx] = render_utils::argb8_to_u32(255, fc.x as u8, fc.y as u8, fc.z as u8);
+    //How much of the pixel a line of the given width along the closest edge covers.
+    //Distances are in pixels so lines keep the same width no matter the distance
+    fn edge_coverage(&self, p: Vec2, areas: [f32; 3], width: f32) -> f32 {
+        let edges = [
+            self.ssc[2] - self.ssc[1],
+            self.ssc[0] - self.ssc[2],
+            self.ssc[1] - self.ssc[0],
+        ];
+
+        let mut dist = f32::INFINITY;
+        for i in 0..3 {
+            let edge_dist = (areas[i] * self.total_area).abs() / edges[i].length();
+            dist = dist.min(edge_dist);
         }
+
+        (width * 0.5 + 0.5 - dist).clamp(0.0, 1.0)
     }
 
     fn render_aabb(&self, color_buff: &mut [u32], idx: usize) {
@@ -712,7 +749,17 @@ impl Triangle {
         color_buff[idx] = render_utils::argb8_to_u32(255, 0, 0, 255);
     }
 
-    pub fn clip_cull_triangle(tri: &Triangle) -> ClipResult {
+    //Signed distance to the near plane in clip space, negative is outside.
+    //The near plane is z = 0 normally and z = w with reversed-Z
+    pub fn near_dist(pos: &Vec4, reversed_z: bool) -> f32 {
+        if reversed_z {
+            pos.w - pos.z
+        } else {
+            pos.z
+        }
+    }
+
+    pub fn clip_cull_triangle(tri: &Triangle, reversed_z: bool) -> ClipResult {
         // All triangles not facing the camera are discarded
         //if Self::cull_triangle_backface(&tri.v[0].position, &tri.v[1].position, &tri.v[2].position) {
         //    return ClipResult::Clipped;
@@ -722,22 +769,41 @@ impl Triangle {
             ClipResult::Clipped
         } else {
             // clipping routines
-            if tri.v[0].position.z < 0.0 {
-                if tri.v[1].position.z < 0.0 {
-                    ClipResult::One(Self::clip_triangle_one(tri))
-                } else if tri.v[2].position.z < 0.0 {
-                    ClipResult::One(Self::clip_triangle_one(&tri.reorder(VerticesOrder::ACB)))
+            let d0 = Self::near_dist(&tri.v[0].position, reversed_z);
+            let d1 = Self::near_dist(&tri.v[1].position, reversed_z);
+            let d2 = Self::near_dist(&tri.v[2].position, reversed_z);
+
+            if d0 < 0.0 {
+                if d1 < 0.0 {
+                    ClipResult::One(Self::clip_triangle_one(tri, reversed_z))
+                } else if d2 < 0.0 {
+                    ClipResult::One(Self::clip_triangle_one(
+                        &tri.reorder(VerticesOrder::ACB),
+                        reversed_z,
+                    ))
                 } else {
-                    ClipResult::Two(Self::clip_triangle_two(&tri.reorder(VerticesOrder::ACB)))
+                    ClipResult::Two(Self::clip_triangle_two(
+                        &tri.reorder(VerticesOrder::ACB),
+                        reversed_z,
+                    ))
                 }
-            } else if tri.v[1].position.z < 0.0 {
-                if tri.v[2].position.z < 0.0 {
-                    ClipResult::One(Self::clip_triangle_one(&tri.reorder(VerticesOrder::BCA)))
+            } else if d1 < 0.0 {
+                if d2 < 0.0 {
+                    ClipResult::One(Self::clip_triangle_one(
+                        &tri.reorder(VerticesOrder::BCA),
+                        reversed_z,
+                    ))
                 } else {
-                    ClipResult::Two(Self::clip_triangle_two(&tri.reorder(VerticesOrder::BAC)))
+                    ClipResult::Two(Self::clip_triangle_two(
+                        &tri.reorder(VerticesOrder::BAC),
+                        reversed_z,
+                    ))
                 }
-            } else if tri.v[2].position.z < 0.0 {
-                ClipResult::Two(Self::clip_triangle_two(&tri.reorder(VerticesOrder::CBA)))
+            } else if d2 < 0.0 {
+                ClipResult::Two(Self::clip_triangle_two(
+                    &tri.reorder(VerticesOrder::CBA),
+                    reversed_z,
+                ))
             } else {
                 // no near clipping necessary
                 //return original
@@ -765,10 +831,10 @@ impl Triangle {
         dot0 < 0.0 && dot1 < 0.0 && dot2 < 0.0
     }
 
-    pub fn clip_triangle_one(&self) -> Triangle {
-        let v0z = self.v[0].position.z;
-        let v1z = self.v[1].position.z;
-        let v2z = self.v[2].position.z;
+    pub fn clip_triangle_one(&self, reversed_z: bool) -> Triangle {
+        let v0z = Self::near_dist(&self.v[0].position, reversed_z);
+        let v1z = Self::near_dist(&self.v[1].position, reversed_z);
+        let v2z = Self::near_dist(&self.v[2].position, reversed_z);
 
         // calculate alpha values for getting adjusted vertices
         let alpha_a = (-v0z) / (v2z - v0z);
@@ -791,10 +857,14 @@ impl Triangle {
         copy
     }
 
-    fn clip_triangle_two(&self) -> (Triangle, Triangle) {
+    fn clip_triangle_two(&self, reversed_z: bool) -> (Triangle, Triangle) {
+        let v0z = Self::near_dist(&self.v[0].position, reversed_z);
+        let v1z = Self::near_dist(&self.v[1].position, reversed_z);
+        let v2z = Self::near_dist(&self.v[2].position, reversed_z);
+
         // calculate alpha values for getting adjusted vertices
-        let alpha_a = (-self.v[0].position.z) / (self.v[1].position.z - self.v[0].position.z);
-        let alpha_b = (-self.v[0].position.z) / (self.v[2].position.z - self.v[0].position.z);
+        let alpha_a = (-v0z) / (v1z - v0z);
+        let alpha_b = (-v0z) / (v2z - v0z);
 
         // interpolate to get v0a and v0b
         let v0_a = render_utils::lerp(self.v[0], self.v[1], alpha_a);
@@ -812,6 +882,7 @@ impl Triangle {
 
     pub fn cull_triangle_view_frustum(triangle: &Triangle) -> bool {
         // cull tests against the 6 planes
+        // z < 0 and z > w are the near and far plane, swapped with reversed-Z
         if triangle.v[0].position.x.abs() > triangle.v[0].position.w
             && triangle.v[1].position.x.abs() > triangle.v[1].position.w
             && triangle.v[2].position.x.abs() > triangle.v[2].position.w
@@ -842,6 +913,18 @@ impl Triangle {
         false
     }
 
+    //Perspective correct UV from the set the slot samples with, its transform applied
+    fn slot_uv(&self, bary: Vec3, rec: [f32; 3], correction: f32, slot: &TextureSlot) -> Vec2 {
+        let uv = |v: &Vertex| if slot.tex_coord == 1 { v.uv1 } else { v.uv };
+
+        let uv = (uv(&self.v[0]) * rec[0] * bary.x
+            + uv(&self.v[1]) * rec[1] * bary.y
+            + uv(&self.v[2]) * rec[2] * bary.z)
+            * correction;
+
+        slot.transform.map_or(uv, |transform| transform.apply(uv))
+    }
+
     fn calc_uv_sampler(uv: Vec2, sampler: &Sampler) -> Vec2 {
         Vec2::new(
             match sampler.wrap_s {
@@ -881,3 +964,16 @@ impl Triangle {
         self.aabb = Some(taabb);
     }
 }
+
+impl Primitive for Triangle {
+    fn aabb(&self) -> [Vec2; 2] {
+        self.aabb.unwrap()
+    }
+
+    fn nearest_depth(&self, compare: DepthCompare) -> f32 {
+        compare.nearest(
+            compare.nearest(self.v[0].position.z, self.v[1].position.z),
+            self.v[2].position.z,
+        )
+    }
+}